@@ -1,56 +1,350 @@
-// use halo2_mpt_circuits::serde::SMTTrace;
-// use halo2_proofs::dev::MockProver;
-// pub use halo2_proofs::halo2curves::bn256::Fr as Fp;
-// use std::fs::File;
-// use std::io::Read;
-
-// use serde::Deserialize;
-
-// #[derive(Deserialize, Default)]
-// pub struct BlockResult {
-//     #[serde(rename = "mptwitness", default)]
-//     pub mpt_witness: Vec<SMTTrace>,
-// }
-
-fn main() {
-    // let mut buffer = Vec::new();
-    // let mut f = File::open("integration-tests/trace.json").unwrap();
-    // f.read_to_end(&mut buffer).unwrap();
-
-    // let traces: Vec<SMTTrace> = serde_json::from_slice::<BlockResult>(&buffer)
-    //     .unwrap()
-    //     .mpt_witness;
-    // let ops: Vec<AccountOp<Fp>> = traces.iter().map(|tr| tr.try_into().unwrap()).collect();
-
-    // let mut data: EthTrie<Fp> = Default::default();
-    // data.add_ops(ops);
-
-    // let (rows, hash_rows) = data.use_rows();
-    // let log2_ceil = |n| u32::BITS - (n as u32).leading_zeros() - (n & (n - 1) == 0) as u32;
-    // let k = log2_ceil(rows) + 1;
-    // let k = k.max(6);
-
-    // println!(
-    //     "start proving trace with mpt-circuit, has {rows} rows, {hash_rows} hash_rows and base k is {k}",
-    // );
-
-    // let final_root = data.final_root();
-
-    // let (circuit, hash_circuit) = match k {
-    //     6 => data.circuits(40),
-    //     7 => data.circuits(90),
-    //     8 => data.circuits(200),
-    //     9 => data.circuits(450),
-    //     10 => data.circuits(900),
-    //     11 => data.circuits(1950),
-    //     _ => panic!("too large k {k}"),
-    // };
-
-    // let prover_mpt = MockProver::<Fp>::run(k, &circuit, vec![]).unwrap();
-    // let prover_hash = MockProver::<Fp>::run(k + 6, &hash_circuit, vec![]).unwrap();
-
-    // assert_eq!(prover_mpt.verify(), Ok(()));
-    // assert_eq!(prover_hash.verify(), Ok(()));
-
-    // println!("done, final hash {final_root:?}");
+//! Replays recorded witness files through the mpt circuit with the MockProver, e.g. to reproduce
+//! a block that failed in production.
+//!
+//! Usage: integration-test [explain|graph] [OPTIONS] <TRACE FILE>...
+//!
+//! Each file is checked on its own, and the exit code is non-zero if any of them fails. With
+//! `explain`, the traces are described instead of checked, and with `graph`, the trie paths they
+//! touch are written out as a Graphviz graph.
+use halo2_mpt_circuits::{
+    hash_traces,
+    serde::{
+        reader::{TraceFormat, TraceReader},
+        SMTTrace,
+    },
+    trie_graph::TrieGraph,
+    types::Proof,
+    validate, MPTProofType, MptCircuit, MptCircuitConfig, RowUsage,
+};
+use halo2_proofs::{
+    dev::MockProver,
+    halo2curves::bn256::Fr,
+    plonk::{Circuit, ConstraintSystem, Error},
+};
+use std::{
+    fs::File,
+    io::{BufRead, BufReader, BufWriter},
+    path::{Path, PathBuf},
+    process::ExitCode,
+};
+
+const USAGE: &str = "\
+usage: integration-test [explain|graph] [OPTIONS] <TRACE FILE>...
+
+commands:
+    explain                       describe each trace, e.g. the values it changes and how the
+                                  paths are extended, instead of checking it. Only --format
+                                  applies.
+    graph                         write the account and storage trie paths that the traces of
+                                  each file touch to <TRACE FILE>.dot, in the Graphviz DOT
+                                  language, instead of checking them. Only --format applies.
+
+options:
+    --format <array|lines|block>  layout of the trace files. Defaults to lines for .jsonl files,
+                                  block for files with a json object, and array otherwise.
+    --circuit <mpt|test>          circuit to run. mpt is the production MptCircuit, with the
+                                  poseidon hashes proven. test looks them up in a table instead,
+                                  and needs the test-circuit feature. Defaults to mpt.
+    --n-rows <N>                  rows for the mpt circuit. Defaults to the rows required.
+    --k <K>                       log2 of the circuit size. Defaults to the smallest k that fits
+                                  the mpt rows and the poseidon hashes.
+    --max-poseidon-hashes <N>     hashes for the mpt circuit's poseidon circuit. Defaults to the
+                                  hashes required.
+";
+
+// Number of constraint failures to print for a failing circuit.
+const MAX_FAILURES_SHOWN: usize = 20;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum CircuitKind {
+    Mpt,
+    Test,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+enum Command {
+    #[default]
+    Replay,
+    Explain,
+    Graph,
+}
+
+#[derive(Debug, Default)]
+struct Options {
+    command: Command,
+    format: Option<TraceFormat>,
+    circuit: Option<CircuitKind>,
+    n_rows: Option<usize>,
+    k: Option<u32>,
+    max_poseidon_hashes: Option<usize>,
+    paths: Vec<PathBuf>,
+}
+
+fn parse_args(args: impl Iterator<Item = String>) -> Result<Options, String> {
+    let mut args = args.peekable();
+    let mut options = Options::default();
+    if let Some(command) = args.next_if(|arg| arg == "explain" || arg == "graph") {
+        options.command = match command.as_str() {
+            "explain" => Command::Explain,
+            _ => Command::Graph,
+        };
+    }
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or_else(|| format!("{arg} needs a value"));
+        match arg.as_str() {
+            "--format" => {
+                options.format = Some(match value()?.as_str() {
+                    "array" => TraceFormat::JsonArray,
+                    "lines" => TraceFormat::JsonLines,
+                    "block" => TraceFormat::BlockResult,
+                    format => return Err(format!("unknown trace format {format}")),
+                })
+            }
+            "--circuit" => {
+                options.circuit = Some(match value()?.as_str() {
+                    "mpt" => CircuitKind::Mpt,
+                    "test" => CircuitKind::Test,
+                    circuit => return Err(format!("unknown circuit {circuit}")),
+                })
+            }
+            "--n-rows" => options.n_rows = Some(parse_number(&arg, &value()?)?),
+            "--k" => options.k = Some(parse_number(&arg, &value()?)?),
+            "--max-poseidon-hashes" => {
+                options.max_poseidon_hashes = Some(parse_number(&arg, &value()?)?)
+            }
+            "-h" | "--help" => return Err(String::new()),
+            _ if arg.starts_with('-') => return Err(format!("unknown option {arg}")),
+            _ => options.paths.push(arg.into()),
+        }
+    }
+    if options.paths.is_empty() {
+        return Err("no trace files".to_string());
+    }
+    Ok(options)
+}
+
+fn parse_number<T: std::str::FromStr>(arg: &str, value: &str) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("{arg} needs a number, not {value}"))
+}
+
+fn detect_format(path: &Path, reader: &mut impl BufRead) -> Result<TraceFormat, String> {
+    if path
+        .extension()
+        .map_or(false, |extension| extension == "jsonl")
+    {
+        return Ok(TraceFormat::JsonLines);
+    }
+    loop {
+        let bytes = reader.fill_buf().map_err(|e| e.to_string())?;
+        match bytes.iter().position(|byte| !byte.is_ascii_whitespace()) {
+            Some(i) if bytes[i] == b'{' => return Ok(TraceFormat::BlockResult),
+            Some(_) => return Ok(TraceFormat::JsonArray),
+            None if bytes.is_empty() => return Err("file is empty".to_string()),
+            None => {
+                let n_bytes = bytes.len();
+                reader.consume(n_bytes);
+            }
+        }
+    }
+}
+
+fn read_traces(
+    path: &Path,
+    format: Option<TraceFormat>,
+) -> Result<Vec<(MPTProofType, SMTTrace)>, String> {
+    let mut reader = BufReader::new(File::open(path).map_err(|e| e.to_string())?);
+    let format = match format {
+        Some(format) => format,
+        None => detect_format(path, &mut reader)?,
+    };
+    println!("reading {format:?} traces");
+    TraceReader::new(reader, format)
+        .collect::<Result<_, _>>()
+        .map_err(|e| e.to_string())
+}
+
+fn replay(path: &Path, options: &Options) -> Result<(), String> {
+    let traces = read_traces(path, options.format)?;
+    println!("read {} traces", traces.len());
+
+    let mut n_invalid = 0;
+    for (i, (proof_type, trace)) in traces.iter().enumerate() {
+        if let Err(error) = validate(trace, *proof_type) {
+            println!("trace {i} ({proof_type:?}) is invalid: {error}");
+            n_invalid += 1;
+        }
+    }
+    if n_invalid > 0 {
+        return Err(format!("{n_invalid} invalid traces"));
+    }
+    let proofs: Vec<_> = traces
+        .iter()
+        .cloned()
+        .map(|trace| Proof::try_from(trace).unwrap())
+        .collect();
+
+    let usage = MptCircuitConfig::row_usage(&proofs);
+    println!("rows required: {} ({})", usage.n_rows(), usage.bottleneck());
+    for (gadget, n_rows) in usage.gadgets {
+        println!("    {gadget}: {n_rows}");
+    }
+    for (proof_type, n_rows) in &usage.proof_types {
+        println!("    {proof_type:?}: {n_rows} mpt update rows");
+    }
+    let n_rows = options.n_rows.unwrap_or_else(|| usage.n_rows());
+    MptCircuitConfig::check_capacity(&proofs, n_rows).map_err(|e| e.to_string())?;
+
+    match options.circuit.unwrap_or(CircuitKind::Mpt) {
+        CircuitKind::Mpt => {
+            let max_poseidon_hashes = options
+                .max_poseidon_hashes
+                .unwrap_or_else(|| hash_traces(&proofs).len());
+            let circuit = MptCircuit::new(n_rows, max_poseidon_hashes, traces);
+            let instances = circuit.instances();
+            // The sponge uses more than one row per hash, so this is only a lower bound on k.
+            mock_prove(&circuit, instances, options.k, &usage, max_poseidon_hashes)
+        }
+        CircuitKind::Test => test_circuit::mock_prove(
+            n_rows,
+            traces,
+            options.k,
+            &usage,
+            hash_traces(&proofs).len(),
+        ),
+    }
+}
+
+fn explain(path: &Path, options: &Options) -> Result<(), String> {
+    let traces = read_traces(path, options.format)?;
+    for (i, (proof_type, trace)) in traces.iter().enumerate() {
+        println!("trace {i}: {}", trace.explain(*proof_type));
+    }
+    Ok(())
+}
+
+fn graph(path: &Path, options: &Options) -> Result<(), String> {
+    let proofs = read_traces(path, options.format)?
+        .into_iter()
+        .enumerate()
+        .map(|(i, trace)| Proof::try_from(trace).map_err(|e| format!("trace {i}: {e}")))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut dot_path = path.as_os_str().to_owned();
+    dot_path.push(".dot");
+    let dot_path = PathBuf::from(dot_path);
+    let file = File::create(&dot_path).map_err(|e| e.to_string())?;
+    TrieGraph::new(&proofs)
+        .write_dot(BufWriter::new(file))
+        .map_err(|e| e.to_string())?;
+    println!("wrote {}", dot_path.display());
+    Ok(())
+}
+
+// Largest k tried when searching for a k that fits the poseidon hashes.
+const MAX_K: u32 = 26;
+
+/// Without `k`, tries k from the smallest one that fits the mpt rows and `poseidon_rows` rows of
+/// poseidon hashes, until the circuit fits.
+fn mock_prove<C: Circuit<Fr>>(
+    circuit: &C,
+    instances: Vec<Vec<Fr>>,
+    k: Option<u32>,
+    usage: &RowUsage,
+    poseidon_rows: usize,
+) -> Result<(), String> {
+    let (mut k, search) = match k {
+        Some(k) => (k, false),
+        None => {
+            let mut cs = ConstraintSystem::default();
+            C::configure(&mut cs);
+            (usage.min_k(&cs, poseidon_rows), true)
+        }
+    };
+    let prover = loop {
+        println!("running MockProver with k = {k}");
+        match MockProver::run(k, circuit, instances.clone()) {
+            Err(Error::NotEnoughRowsAvailable { .. }) if search && k < MAX_K => k += 1,
+            result => break result.map_err(|e| format!("{e:?}"))?,
+        }
+    };
+    prover.verify().map_err(|failures| {
+        for failure in failures.iter().take(MAX_FAILURES_SHOWN) {
+            println!("{failure}");
+        }
+        format!("{} constraint failures", failures.len())
+    })
+}
+
+#[cfg(feature = "test-circuit")]
+mod test_circuit {
+    use super::*;
+    use halo2_mpt_circuits::{gadgets::poseidon::PoseidonTable, TestCircuit};
+
+    pub fn mock_prove(
+        n_rows: usize,
+        traces: Vec<(MPTProofType, SMTTrace)>,
+        k: Option<u32>,
+        usage: &RowUsage,
+        n_hashes: usize,
+    ) -> Result<(), String> {
+        super::mock_prove(
+            &TestCircuit::new(n_rows, traces),
+            vec![],
+            k,
+            usage,
+            PoseidonTable::n_rows_required(n_hashes),
+        )
+    }
+}
+
+#[cfg(not(feature = "test-circuit"))]
+mod test_circuit {
+    use super::*;
+
+    pub fn mock_prove(
+        _: usize,
+        _: Vec<(MPTProofType, SMTTrace)>,
+        _: Option<u32>,
+        _: &RowUsage,
+        _: usize,
+    ) -> Result<(), String> {
+        Err("the test circuit needs the test-circuit feature".to_string())
+    }
+}
+
+fn main() -> ExitCode {
+    let options = match parse_args(std::env::args().skip(1)) {
+        Ok(options) => options,
+        Err(error) => {
+            if !error.is_empty() {
+                eprintln!("{error}\n");
+            }
+            eprint!("{USAGE}");
+            return ExitCode::from(2);
+        }
+    };
+
+    let mut n_failed = 0;
+    for path in &options.paths {
+        println!("{}:", path.display());
+        let result = match options.command {
+            Command::Replay => replay(path, &options),
+            Command::Explain => explain(path, &options),
+            Command::Graph => graph(path, &options),
+        };
+        match result {
+            Ok(()) => println!("ok"),
+            Err(error) => {
+                println!("failed: {error}");
+                n_failed += 1;
+            }
+        }
+    }
+    if n_failed > 0 {
+        eprintln!("{n_failed} of {} trace files failed", options.paths.len());
+        return ExitCode::FAILURE;
+    }
+    ExitCode::SUCCESS
 }
@@ -1,28 +1,156 @@
+#[cfg(any(test, feature = "test-circuit"))]
+use crate::gadgets::poseidon::PoseidonTable;
 use crate::{
-    gadgets::poseidon::PoseidonTable, hash_traces, serde::SMTTrace, types::Proof, MPTProofType,
-    MptCircuitConfig,
+    gadgets::poseidon::PoseidonConfig, hash_traces, serde::SMTTrace, types::Proof, MPTProofType,
+    MptCircuitConfig, MptCircuitOptions,
 };
 use halo2_proofs::{
     circuit::{Layouter, SimpleFloorPlanner},
     halo2curves::bn256::Fr,
     plonk::{Circuit, ConstraintSystem, Error, FirstPhase},
 };
+use std::marker::PhantomData;
 
+/// Public input options of a standalone mpt circuit. They are part of the circuit type because
+/// `Circuit::configure` has no access to the circuit itself.
+pub trait CircuitOptions: Clone + std::fmt::Debug + Default {
+    const OPTIONS: MptCircuitOptions;
+}
+
+/// No public inputs, with the updates not chained, as for the mpt circuit in zkevm-circuits.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct NoPublicInputs;
+
+impl CircuitOptions for NoPublicInputs {
+    const OPTIONS: MptCircuitOptions = MptCircuitOptions {
+        expose_roots: false,
+        expose_update_count: false,
+        chain_roots: false,
+    };
+}
+
+/// Consecutive updates, with the old root of the first update, the new root of the last update,
+/// and the number of updates as public inputs.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct StateTransition;
+
+impl CircuitOptions for StateTransition {
+    const OPTIONS: MptCircuitOptions = MptCircuitOptions {
+        expose_roots: true,
+        expose_update_count: true,
+        chain_roots: true,
+    };
+}
+
+/// Standalone mpt circuit, with the poseidon hashes it looks up proven by a poseidon hash circuit.
+pub type MptCircuit = MptCircuitWithOptions<NoPublicInputs>;
+
+/// Standalone mpt circuit whose updates have to be consecutive, i.e. the old root of each update
+/// is the new root of the one before it. The public inputs are the old root of the first update,
+/// the new root of the last update, and the number of updates.
+pub type StateTransitionCircuit = MptCircuitWithOptions<StateTransition>;
+
+/// Standalone mpt circuit, with the poseidon hashes it looks up proven by a poseidon hash circuit,
+/// and the public inputs and root chaining given by `O`.
+#[derive(Clone, Debug, Default)]
+pub struct MptCircuitWithOptions<O: CircuitOptions> {
+    n_rows: usize,
+    max_poseidon_hashes: usize,
+    proofs: Vec<Proof>,
+    options: PhantomData<O>,
+}
+
+impl<O: CircuitOptions> MptCircuitWithOptions<O> {
+    /// `n_rows` and `max_poseidon_hashes` determine the shape of the circuit, and so have to be
+    /// the same for keygen and proving. Panics if a trace is invalid, which can be checked
+    /// beforehand with `Proof::try_from`.
+    pub fn new(
+        n_rows: usize,
+        max_poseidon_hashes: usize,
+        traces: Vec<(MPTProofType, SMTTrace)>,
+    ) -> Self {
+        Self::from_proofs(
+            n_rows,
+            max_poseidon_hashes,
+            traces
+                .into_iter()
+                .map(Proof::from_trace_unchecked)
+                .collect(),
+        )
+    }
+
+    /// Like `new`, for traces that have already been converted into `Proof`s.
+    pub fn from_proofs(n_rows: usize, max_poseidon_hashes: usize, proofs: Vec<Proof>) -> Self {
+        Self {
+            n_rows,
+            max_poseidon_hashes,
+            proofs,
+            options: PhantomData,
+        }
+    }
+
+    /// One column of public inputs if `O` exposes any, and none otherwise.
+    pub fn instances(&self) -> Vec<Vec<Fr>> {
+        if O::OPTIONS.expose_roots {
+            vec![O::OPTIONS.instance(&self.proofs)]
+        } else {
+            vec![]
+        }
+    }
+}
+
+impl<O: CircuitOptions> Circuit<Fr> for MptCircuitWithOptions<O> {
+    type Config = (PoseidonConfig, MptCircuitConfig);
+    type FloorPlanner = SimpleFloorPlanner;
+
+    fn without_witnesses(&self) -> Self {
+        Self::from_proofs(self.n_rows, self.max_poseidon_hashes, vec![])
+    }
+
+    fn configure(cs: &mut ConstraintSystem<Fr>) -> Self::Config {
+        let poseidon = PoseidonConfig::configure(cs);
+        let challenge = cs.challenge_usable_after(FirstPhase);
+        let mpt_circuit_config =
+            MptCircuitConfig::configure_with_options(cs, challenge, &poseidon, O::OPTIONS);
+        (poseidon, mpt_circuit_config)
+    }
+
+    fn synthesize(
+        &self,
+        config: Self::Config,
+        mut layouter: impl Layouter<Fr>,
+    ) -> Result<(), Error> {
+        let (poseidon, mpt_circuit_config) = config;
+        mpt_circuit_config.assign(&mut layouter, &self.proofs, self.n_rows)?;
+        poseidon.load(
+            &mut layouter,
+            &hash_traces(&self.proofs),
+            self.max_poseidon_hashes,
+        )
+    }
+}
+
+#[cfg(any(test, feature = "test-circuit"))]
 #[derive(Clone, Debug, Default)]
 pub struct TestCircuit {
     n_rows: usize,
     proofs: Vec<Proof>,
 }
 
+#[cfg(any(test, feature = "test-circuit"))]
 impl TestCircuit {
     pub fn new(n_rows: usize, traces: Vec<(MPTProofType, SMTTrace)>) -> Self {
         Self {
             n_rows,
-            proofs: traces.into_iter().map(Proof::from).collect(),
+            proofs: traces
+                .into_iter()
+                .map(Proof::from_trace_unchecked)
+                .collect(),
         }
     }
 }
 
+#[cfg(any(test, feature = "test-circuit"))]
 impl Circuit<Fr> for TestCircuit {
     type Config = (PoseidonTable, MptCircuitConfig);
     type FloorPlanner = SimpleFloorPlanner;
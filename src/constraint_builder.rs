@@ -8,19 +8,51 @@ use itertools::Itertools;
 mod binary_column;
 mod binary_query;
 mod column;
+mod description;
+mod native;
 mod query;
 
 pub use binary_column::BinaryColumn;
 pub use binary_query::BinaryQuery;
 pub use column::{AdviceColumn, FixedColumn, SecondPhaseAdviceColumn, SelectorColumn};
+pub use description::{
+    ColumnDescription, ColumnKind, ConstraintDescription, ConstraintSystemDescription,
+    LookupDescription,
+};
+pub use native::{AssignRegion, NativeFailure, WitnessTable};
+pub(crate) use query::format_constant;
 pub use query::Query;
 
 pub struct ConstraintBuilder<F: FromUniformBytes<64> + Ord> {
-    constraints: Vec<(&'static str, Query<F>)>,
-    #[allow(clippy::type_complexity)]
-    lookups: Vec<(&'static str, Vec<(Query<F>, Query<F>)>)>,
+    constraints: Vec<Constraint<F>>,
+    lookups: Vec<Lookup<F>>,
+
+    conditions: Vec<BinaryQuery<F>>,
+}
 
+struct Constraint<F: FromUniformBytes<64> + Ord> {
+    name: &'static str,
+    // The conditions the constraint was added under, starting with the every row selector.
     conditions: Vec<BinaryQuery<F>>,
+    query: Query<F>,
+}
+
+impl<F: FromUniformBytes<64> + Ord> Constraint<F> {
+    // The query, multiplied by all its conditions.
+    fn conditional_query(&self) -> Query<F> {
+        let condition = self
+            .conditions
+            .iter()
+            .fold(BinaryQuery::one(), |a, b| a.and(b.clone()));
+        condition.condition(self.query.clone())
+    }
+}
+
+struct Lookup<F: FromUniformBytes<64> + Ord> {
+    name: &'static str,
+    conditions: Vec<BinaryQuery<F>>,
+    // Pairs of input and table queries. The conditions are already applied to the inputs.
+    queries: Vec<(Query<F>, Query<F>)>,
 }
 
 impl<F: FromUniformBytes<64> + Ord> ConstraintBuilder<F> {
@@ -41,11 +73,11 @@ impl<F: FromUniformBytes<64> + Ord> ConstraintBuilder<F> {
     }
 
     pub fn assert_zero(&mut self, name: &'static str, query: Query<F>) {
-        let condition = self
-            .conditions
-            .iter()
-            .fold(BinaryQuery::one(), |a, b| a.and(b.clone()));
-        self.constraints.push((name, condition.condition(query)))
+        self.constraints.push(Constraint {
+            name,
+            conditions: self.conditions.clone(),
+            query,
+        })
     }
 
     pub fn assert_equal(&mut self, name: &'static str, left: Query<F>, right: Query<F>) {
@@ -83,7 +115,11 @@ impl<F: FromUniformBytes<64> + Ord> ConstraintBuilder<F> {
             .collect();
         // If condition is true, every_row_selector must be enabled.
         lookup.push((condition.into(), self.every_row_selector().into()));
-        self.lookups.push((name, lookup))
+        self.lookups.push(Lookup {
+            name,
+            conditions: self.conditions.clone(),
+            queries: lookup,
+        })
     }
 
     pub fn poseidon_lookup(
@@ -120,13 +156,14 @@ impl<F: FromUniformBytes<64> + Ord> ConstraintBuilder<F> {
             head_mark.current(),
         ];
 
-        self.lookups.push((
+        self.lookups.push(Lookup {
             name,
-            extended_queries
+            conditions: self.conditions.clone(),
+            queries: extended_queries
                 .into_iter()
                 .zip_eq(poseidon_lookup_queries)
                 .collect(),
-        ))
+        })
     }
 
     pub fn build_columns<const A: usize, const B: usize, const C: usize>(
@@ -160,20 +197,45 @@ impl<F: FromUniformBytes<64> + Ord> ConstraintBuilder<F> {
         [0; N].map(|_| BinaryColumn::configure::<F>(cs, self))
     }
 
-    pub fn build(self, cs: &mut ConstraintSystem<F>) {
+    pub fn build(&self, cs: &mut ConstraintSystem<F>) {
+        self.build_without(cs, &[])
+    }
+
+    /// Names of the constraints, then of the lookups. Names are not unique, e.g. a constraint
+    /// added for each segment type has the same name for all of them, so constraints and lookups
+    /// are identified by their index in this list instead.
+    pub fn names(&self) -> Vec<&'static str> {
+        self.constraints
+            .iter()
+            .map(|constraint| constraint.name)
+            .chain(self.lookups.iter().map(|lookup| lookup.name))
+            .collect()
+    }
+
+    /// Like `build`, but leaves out the constraints and lookups with indices in `disabled`, where
+    /// the indices are as in `names`.
+    pub fn build_without(&self, cs: &mut ConstraintSystem<F>, disabled: &[usize]) {
         assert_eq!(
             self.conditions.len(),
             1,
             "Cannot call build while in a condition"
         );
 
-        for (name, query) in self.constraints {
-            cs.create_gate(name, |meta| vec![query.run(meta)])
+        let is_enabled = |index: usize| !disabled.contains(&index);
+        for (index, constraint) in self.constraints.iter().enumerate() {
+            if is_enabled(index) {
+                let query = constraint.conditional_query();
+                cs.create_gate(constraint.name, |meta| vec![query.run(meta)])
+            }
         }
-        for (name, lookup) in self.lookups {
+        let n_constraints = self.constraints.len();
+        for (index, Lookup { name, queries, .. }) in self.lookups.iter().enumerate() {
+            if !is_enabled(n_constraints + index) {
+                continue;
+            }
             cs.lookup_any(name, |meta| {
-                lookup
-                    .into_iter()
+                queries
+                    .iter()
                     .map(|(left, right)| (left.run(meta), right.run(meta)))
                     .collect()
             });
@@ -1,6 +1,6 @@
-use super::{BinaryQuery, ConstraintBuilder, Query};
+use super::{AssignRegion, BinaryQuery, ConstraintBuilder, Query};
 use halo2_proofs::{
-    circuit::{Region, Value},
+    circuit::Value,
     halo2curves::ff::FromUniformBytes,
     plonk::ConstraintSystem,
     plonk::{Advice, Column},
@@ -40,17 +40,15 @@ impl BinaryColumn {
 
     pub fn assign<F: FromUniformBytes<64> + Ord>(
         &self,
-        region: &mut Region<'_, F>,
+        region: &mut impl AssignRegion<F>,
         offset: usize,
         value: bool,
     ) {
-        region
-            .assign_advice(
-                || "binary",
-                self.0,
-                offset,
-                || Value::known(F::from(value as u64)),
-            )
-            .expect("failed assign_advice");
+        region.assign_advice_value(
+            "binary",
+            self.0,
+            offset,
+            Value::known(F::from(value as u64).into()),
+        );
     }
 }
@@ -1,7 +1,7 @@
-use super::{BinaryQuery, Query};
+use super::{AssignRegion, BinaryQuery, Query};
 use halo2_proofs::plonk::Assigned;
 use halo2_proofs::{
-    circuit::{Region, Value},
+    circuit::Value,
     halo2curves::ff::FromUniformBytes,
     plonk::{Advice, Column, Fixed},
 };
@@ -19,10 +19,12 @@ impl SelectorColumn {
         BinaryQuery(Query::Fixed(self.0, i))
     }
 
-    pub fn enable<F: FromUniformBytes<64> + Ord>(&self, region: &mut Region<'_, F>, offset: usize) {
-        region
-            .assign_fixed(|| "selector", self.0, offset, || Value::known(F::ONE))
-            .expect("failed enable selector");
+    pub fn enable<F: FromUniformBytes<64> + Ord>(
+        &self,
+        region: &mut impl AssignRegion<F>,
+        offset: usize,
+    ) {
+        region.assign_fixed_value("selector", self.0, offset, F::ONE.into());
     }
 }
 
@@ -44,20 +46,14 @@ impl FixedColumn {
 
     pub fn assign<F: FromUniformBytes<64> + Ord, T: Copy + TryInto<F>>(
         &self,
-        region: &mut Region<'_, F>,
+        region: &mut impl AssignRegion<F>,
         offset: usize,
         value: T,
     ) where
         <T as TryInto<F>>::Error: Debug,
     {
-        region
-            .assign_fixed(
-                || "fixed",
-                self.0,
-                offset,
-                || Value::known(value.try_into().unwrap()),
-            )
-            .expect("failed assign_fixed");
+        let value: F = value.try_into().unwrap();
+        region.assign_fixed_value("fixed", self.0, offset, value.into());
     }
 }
 
@@ -87,31 +83,23 @@ impl AdviceColumn {
 
     pub fn assign<F: FromUniformBytes<64> + Ord, T: Copy + TryInto<F>>(
         &self,
-        region: &mut Region<'_, F>,
+        region: &mut impl AssignRegion<F>,
         offset: usize,
         value: T,
     ) where
         <T as TryInto<F>>::Error: Debug,
     {
-        region
-            .assign_advice(
-                || "advice",
-                self.0,
-                offset,
-                || Value::known(value.try_into().unwrap()),
-            )
-            .expect("failed assign_advice");
+        let value: F = value.try_into().unwrap();
+        region.assign_advice_value("advice", self.0, offset, Value::known(value.into()));
     }
 
     pub fn assign_rational<F: FromUniformBytes<64> + Ord>(
         &self,
-        region: &mut Region<'_, F>,
+        region: &mut impl AssignRegion<F>,
         offset: usize,
         value: Assigned<F>,
     ) {
-        region
-            .assign_advice(|| "advice", self.0, offset, || Value::known(value))
-            .expect("failed assign_advice");
+        region.assign_advice_value("advice", self.0, offset, Value::known(value));
     }
 }
 
@@ -133,12 +121,10 @@ impl SecondPhaseAdviceColumn {
 
     pub fn assign<F: FromUniformBytes<64> + Ord>(
         &self,
-        region: &mut Region<'_, F>,
+        region: &mut impl AssignRegion<F>,
         offset: usize,
         value: Value<F>,
     ) {
-        region
-            .assign_advice(|| "second phase advice", self.0, offset, || value)
-            .expect("failed assign_advice");
+        region.assign_advice_value("second phase advice", self.0, offset, value.map(Into::into));
     }
 }
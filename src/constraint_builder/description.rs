@@ -0,0 +1,180 @@
+use super::{ConstraintBuilder, Query};
+use halo2_proofs::{halo2curves::ff::FromUniformBytes, plonk::ConstraintSystem};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+
+/// Machine readable description of the columns, constraints, and lookups of a constraint system,
+/// for audits and for diffing the constraints of two versions of a circuit. Queries are formatted
+/// with `Query`'s `Display` impl.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ConstraintSystemDescription {
+    pub columns: Vec<ColumnDescription>,
+    pub constraints: Vec<ConstraintDescription>,
+    pub lookups: Vec<LookupDescription>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ColumnKind {
+    Advice,
+    SecondPhaseAdvice,
+    Fixed,
+    /// A fixed column that is used as a condition, i.e. a `SelectorColumn`.
+    Selector,
+    Instance,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ColumnDescription {
+    /// How the column is referred to in queries, e.g. `advice[3]`.
+    pub name: String,
+    pub kind: ColumnKind,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ConstraintDescription {
+    pub name: String,
+    /// The conditions the constraint was added under, outermost first. The first is always the
+    /// every row selector.
+    pub conditions: Vec<String>,
+    /// The query that must be zero when all the conditions are true.
+    pub constraint: String,
+    /// Degree of the constraint multiplied by its conditions, which is what gets built.
+    pub degree: usize,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LookupDescription {
+    pub name: String,
+    /// The conditions the lookup was added under, outermost first.
+    pub conditions: Vec<String>,
+    /// Input queries, which already include the conditions.
+    pub input: Vec<String>,
+    pub table: Vec<String>,
+}
+
+impl<F: FromUniformBytes<64> + Ord> ConstraintBuilder<F> {
+    /// Describes the constraints and lookups added so far, and all the columns in `cs`.
+    pub fn description(&self, cs: &ConstraintSystem<F>) -> ConstraintSystemDescription {
+        let selectors: BTreeSet<_> = self
+            .constraints
+            .iter()
+            .flat_map(|constraint| &constraint.conditions)
+            .chain(self.lookups.iter().flat_map(|lookup| &lookup.conditions))
+            .filter_map(|condition| match &condition.0 {
+                Query::Fixed(column, _) => Some(column.index()),
+                _ => None,
+            })
+            .collect();
+
+        let advice_columns = cs
+            .advice_column_phase()
+            .into_iter()
+            .enumerate()
+            .map(|(i, phase)| ColumnDescription {
+                name: format!("advice[{i}]"),
+                kind: if phase == 0 {
+                    ColumnKind::Advice
+                } else {
+                    ColumnKind::SecondPhaseAdvice
+                },
+            });
+        let fixed_columns = (0..cs.num_fixed_columns()).map(|i| ColumnDescription {
+            name: format!("fixed[{i}]"),
+            kind: if selectors.contains(&i) {
+                ColumnKind::Selector
+            } else {
+                ColumnKind::Fixed
+            },
+        });
+        let instance_columns = (0..cs.num_instance_columns()).map(|i| ColumnDescription {
+            name: format!("instance[{i}]"),
+            kind: ColumnKind::Instance,
+        });
+
+        ConstraintSystemDescription {
+            columns: advice_columns
+                .chain(fixed_columns)
+                .chain(instance_columns)
+                .collect(),
+            constraints: self
+                .constraints
+                .iter()
+                .map(|constraint| ConstraintDescription {
+                    name: constraint.name.to_string(),
+                    conditions: format_queries(constraint.conditions.iter().map(|c| &c.0)),
+                    constraint: constraint.query.to_string(),
+                    degree: constraint.conditional_query().degree(),
+                })
+                .collect(),
+            lookups: self
+                .lookups
+                .iter()
+                .map(|lookup| LookupDescription {
+                    name: lookup.name.to_string(),
+                    conditions: format_queries(lookup.conditions.iter().map(|c| &c.0)),
+                    input: format_queries(lookup.queries.iter().map(|(input, _)| input)),
+                    table: format_queries(lookup.queries.iter().map(|(_, table)| table)),
+                })
+                .collect(),
+        }
+    }
+}
+
+fn format_queries<'a, F: FromUniformBytes<64> + Ord + 'a>(
+    queries: impl Iterator<Item = &'a Query<F>>,
+) -> Vec<String> {
+    queries.map(ToString::to_string).collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::constraint_builder::{AdviceColumn, SelectorColumn};
+    use halo2_proofs::halo2curves::bn256::Fr;
+
+    #[test]
+    fn describe_constraints() {
+        let mut cs = ConstraintSystem::<Fr>::default();
+        let selector = SelectorColumn(cs.fixed_column());
+        let mut cb = ConstraintBuilder::new(selector);
+        let [a, b] = [0; 2].map(|_| AdviceColumn(cs.advice_column()));
+        let [flag] = cb.binary_columns(&mut cs);
+        cb.condition(flag.current(), |cb| {
+            cb.assert_equal("a is b plus 1", a.current(), b.previous() + Query::one());
+            cb.add_lookup("a is in b", [a.current()], [b.current()]);
+        });
+
+        let description = cb.description(&cs);
+        assert_eq!(
+            description.columns,
+            [
+                ("advice[0]", ColumnKind::Advice),
+                ("advice[1]", ColumnKind::Advice),
+                ("advice[2]", ColumnKind::Advice),
+                ("fixed[0]", ColumnKind::Selector),
+            ]
+            .map(|(name, kind)| ColumnDescription {
+                name: name.to_string(),
+                kind
+            })
+        );
+        assert_eq!(
+            description.constraints[1],
+            ConstraintDescription {
+                name: "a is b plus 1".to_string(),
+                conditions: vec!["fixed[0]".to_string(), "advice[2]".to_string()],
+                constraint: "advice[0] - (advice[1](-1) + 1)".to_string(),
+                degree: 3,
+            }
+        );
+        assert_eq!(description.lookups[0].name, "a is in b");
+        assert_eq!(description.lookups[0].table, ["advice[1]", "fixed[0]"]);
+
+        let json = serde_json::to_string(&description).unwrap();
+        assert_eq!(
+            serde_json::from_str::<ConstraintSystemDescription>(&json).unwrap(),
+            description
+        );
+    }
+}
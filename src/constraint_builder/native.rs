@@ -0,0 +1,348 @@
+use super::{ConstraintBuilder, Query};
+use halo2_proofs::{
+    arithmetic::Field,
+    circuit::{Region, Value},
+    halo2curves::ff::FromUniformBytes,
+    plonk::{Advice, Any, Assigned, Column, Fixed},
+};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+/// Somewhere to assign witness values: a halo2 `Region`, or a `WitnessTable` for evaluating the
+/// constraints natively.
+pub trait AssignRegion<F: Field> {
+    fn assign_advice_value(
+        &mut self,
+        annotation: &'static str,
+        column: Column<Advice>,
+        offset: usize,
+        value: Value<Assigned<F>>,
+    );
+
+    fn assign_fixed_value(
+        &mut self,
+        annotation: &'static str,
+        column: Column<Fixed>,
+        offset: usize,
+        value: Assigned<F>,
+    );
+}
+
+impl<F: Field> AssignRegion<F> for Region<'_, F> {
+    fn assign_advice_value(
+        &mut self,
+        annotation: &'static str,
+        column: Column<Advice>,
+        offset: usize,
+        value: Value<Assigned<F>>,
+    ) {
+        self.assign_advice(|| annotation, column, offset, || value)
+            .expect("failed assign_advice");
+    }
+
+    fn assign_fixed_value(
+        &mut self,
+        annotation: &'static str,
+        column: Column<Fixed>,
+        offset: usize,
+        value: Assigned<F>,
+    ) {
+        self.assign_fixed(|| annotation, column, offset, || Value::known(value))
+            .expect("failed assign_fixed");
+    }
+}
+
+/// Values of advice and fixed columns, filled without a halo2 layouter. Unassigned cells are 0,
+/// as are cells queried outside of the table, where halo2 would wrap around instead.
+#[derive(Clone, Debug)]
+pub struct WitnessTable<F> {
+    n_rows: usize,
+    advice: BTreeMap<usize, Vec<F>>,
+    fixed: BTreeMap<usize, Vec<F>>,
+}
+
+impl<F: Field> WitnessTable<F> {
+    pub fn new(n_rows: usize) -> Self {
+        Self {
+            n_rows,
+            advice: BTreeMap::new(),
+            fixed: BTreeMap::new(),
+        }
+    }
+
+    pub fn n_rows(&self) -> usize {
+        self.n_rows
+    }
+
+    pub fn advice(&self, column: Column<Advice>, row: usize) -> F {
+        Self::value(&self.advice, column.index(), row)
+    }
+
+    pub fn fixed(&self, column: Column<Fixed>, row: usize) -> F {
+        Self::value(&self.fixed, column.index(), row)
+    }
+
+    /// Value of an advice or fixed column. Instance columns are not in the table, so they are 0.
+    pub fn cell(&self, column: Column<Any>, row: usize) -> F {
+        match column.column_type() {
+            Any::Advice(_) => Self::value(&self.advice, column.index(), row),
+            Any::Fixed => Self::value(&self.fixed, column.index(), row),
+            Any::Instance => F::ZERO,
+        }
+    }
+
+    pub fn set_advice(&mut self, column: Column<Advice>, row: usize, value: F) {
+        let n_rows = self.n_rows;
+        Self::set(&mut self.advice, n_rows, column.index(), row, value)
+    }
+
+    pub fn set_fixed(&mut self, column: Column<Fixed>, row: usize, value: F) {
+        let n_rows = self.n_rows;
+        Self::set(&mut self.fixed, n_rows, column.index(), row, value)
+    }
+
+    /// The assigned columns, named as in `ConstraintSystemDescription`, e.g. `advice[3]`.
+    pub fn columns(&self) -> impl Iterator<Item = (String, &[F])> {
+        let advice = self
+            .advice
+            .iter()
+            .map(|(i, values)| (format!("advice[{i}]"), values.as_slice()));
+        let fixed = self
+            .fixed
+            .iter()
+            .map(|(i, values)| (format!("fixed[{i}]"), values.as_slice()));
+        advice.chain(fixed)
+    }
+
+    fn value(columns: &BTreeMap<usize, Vec<F>>, index: usize, row: usize) -> F {
+        columns.get(&index).map_or(F::ZERO, |values| values[row])
+    }
+
+    fn set(
+        columns: &mut BTreeMap<usize, Vec<F>>,
+        n_rows: usize,
+        index: usize,
+        row: usize,
+        value: F,
+    ) {
+        assert!(
+            row < n_rows,
+            "row {row} is outside of the witness table, which has {n_rows} rows"
+        );
+        columns
+            .entry(index)
+            .or_insert_with(|| vec![F::ZERO; n_rows])[row] = value;
+    }
+}
+
+impl<F: Field> AssignRegion<F> for WitnessTable<F> {
+    fn assign_advice_value(
+        &mut self,
+        _: &'static str,
+        column: Column<Advice>,
+        offset: usize,
+        value: Value<Assigned<F>>,
+    ) {
+        // Unknown values, e.g. of second phase columns without a challenge, are left as 0.
+        let _ = value.map(|value| self.set_advice(column, offset, value.evaluate()));
+    }
+
+    fn assign_fixed_value(
+        &mut self,
+        _: &'static str,
+        column: Column<Fixed>,
+        offset: usize,
+        value: Assigned<F>,
+    ) {
+        self.set_fixed(column, offset, value.evaluate());
+    }
+}
+
+/// A constraint or lookup that a `WitnessTable` does not satisfy. `index` is the index of the
+/// constraint or lookup in `ConstraintBuilder::names`, since names are not unique.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, thiserror::Error)]
+pub enum NativeFailure {
+    #[error("constraint {index} \"{name}\" is not satisfied at row {row}")]
+    /// The constraint is not 0 at the row.
+    Constraint {
+        index: usize,
+        name: &'static str,
+        row: usize,
+    },
+    #[error("lookup {index} \"{name}\" input at row {row} is not in the table")]
+    /// The lookup input at the row does not match any row of the table.
+    Lookup {
+        index: usize,
+        name: &'static str,
+        row: usize,
+    },
+}
+
+impl NativeFailure {
+    pub fn index(&self) -> usize {
+        match self {
+            Self::Constraint { index, .. } | Self::Lookup { index, .. } => *index,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Constraint { name, .. } | Self::Lookup { name, .. } => *name,
+        }
+    }
+
+    pub fn row(&self) -> usize {
+        match self {
+            Self::Constraint { row, .. } | Self::Lookup { row, .. } => *row,
+        }
+    }
+}
+
+impl<F: FromUniformBytes<64> + Ord> Query<F> {
+    /// Value of the query at `row` of `table`, with `challenges` as the challenge values, by
+    /// challenge index. Cells that a rotation puts outside of the table are 0, whereas halo2 wraps
+    /// around to the other end of the table, so constraints that query past the first or last row
+    /// can evaluate differently than in the MockProver.
+    pub fn evaluate(&self, table: &WitnessTable<F>, row: usize, challenges: &[F]) -> F {
+        let rotated = |rotation: i32| {
+            row.checked_add_signed(rotation as isize)
+                .filter(|row| *row < table.n_rows)
+        };
+        match self {
+            Query::Constant(f) => *f,
+            Query::Advice(c, r) => rotated(*r).map_or(F::ZERO, |row| table.advice(*c, row)),
+            Query::Fixed(c, r) => rotated(*r).map_or(F::ZERO, |row| table.fixed(*c, row)),
+            Query::Challenge(c) => challenges[c.index()],
+            Query::Neg(q) => -q.evaluate(table, row, challenges),
+            Query::Add(q, u) => {
+                q.evaluate(table, row, challenges) + u.evaluate(table, row, challenges)
+            }
+            Query::Mul(q, u) => {
+                q.evaluate(table, row, challenges) * u.evaluate(table, row, challenges)
+            }
+        }
+    }
+
+    fn collect_advice(&self, indices: &mut BTreeSet<usize>) {
+        match self {
+            Query::Advice(c, _) => {
+                indices.insert(c.index());
+            }
+            Query::Neg(q) => q.collect_advice(indices),
+            Query::Add(q, u) | Query::Mul(q, u) => {
+                q.collect_advice(indices);
+                u.collect_advice(indices);
+            }
+            Query::Constant(_) | Query::Fixed(..) | Query::Challenge(_) => {}
+        }
+    }
+}
+
+impl<F: FromUniformBytes<64> + Ord> ConstraintBuilder<F> {
+    /// Checks every constraint and lookup at every row of `table`, without the MockProver.
+    /// `challenges` are the challenge values, by challenge index. Copy constraints, e.g. to
+    /// instance columns, are not checked.
+    pub fn evaluate(
+        &self,
+        table: &WitnessTable<F>,
+        challenges: &[F],
+    ) -> Result<(), Vec<NativeFailure>> {
+        let mut failures = vec![];
+        for (index, constraint) in self.constraints.iter().enumerate() {
+            for row in 0..table.n_rows() {
+                // The built constraint is the product of the conditions and the query, so only the
+                // rows where all the conditions are non-zero need the query to be evaluated.
+                if self.is_enabled(index, table, row, challenges)
+                    && constraint.query.evaluate(table, row, challenges) != F::ZERO
+                {
+                    failures.push(NativeFailure::Constraint {
+                        index,
+                        name: constraint.name,
+                        row,
+                    });
+                }
+            }
+        }
+
+        // Many lookups share a table, e.g. all the poseidon lookups, so each table is only
+        // evaluated once.
+        let mut tables: HashMap<Vec<String>, BTreeSet<Vec<F>>> = HashMap::new();
+        for (i, lookup) in self.lookups.iter().enumerate() {
+            let (inputs, table_queries): (Vec<_>, Vec<_>) = lookup
+                .queries
+                .iter()
+                .map(|(input, table)| (input, table))
+                .unzip();
+            let key = table_queries.iter().map(ToString::to_string).collect();
+            let lookup_table = tables.entry(key).or_insert_with(|| {
+                (0..table.n_rows())
+                    .map(|row| {
+                        table_queries
+                            .iter()
+                            .map(|q| q.evaluate(table, row, challenges))
+                            .collect()
+                    })
+                    .collect()
+            });
+            for row in 0..table.n_rows() {
+                let input: Vec<_> = inputs
+                    .iter()
+                    .map(|q| q.evaluate(table, row, challenges))
+                    .collect();
+                if !lookup_table.contains(&input) {
+                    failures.push(NativeFailure::Lookup {
+                        index: self.constraints.len() + i,
+                        name: lookup.name,
+                        row,
+                    });
+                }
+            }
+        }
+
+        if failures.is_empty() {
+            Ok(())
+        } else {
+            Err(failures)
+        }
+    }
+
+    /// Whether all the conditions of the constraint or lookup at `index` in `names` hold at `row`
+    /// of `table`, i.e. whether it applies there.
+    pub fn is_enabled(
+        &self,
+        index: usize,
+        table: &WitnessTable<F>,
+        row: usize,
+        challenges: &[F],
+    ) -> bool {
+        let conditions = match self.constraints.get(index) {
+            Some(constraint) => &constraint.conditions,
+            None => &self.lookups[index - self.constraints.len()].conditions,
+        };
+        conditions
+            .iter()
+            .all(|condition| condition.0.evaluate(table, row, challenges) != F::ZERO)
+    }
+
+    /// Indices of the advice columns that the constraint or lookup at `index` in `names` reads, in
+    /// its conditions, its query, or its lookup inputs. Columns of lookup tables are left out.
+    pub fn queried_advice(&self, index: usize) -> BTreeSet<usize> {
+        let mut indices = BTreeSet::new();
+        let conditions = match self.constraints.get(index) {
+            Some(constraint) => {
+                constraint.query.collect_advice(&mut indices);
+                &constraint.conditions
+            }
+            None => {
+                let lookup = &self.lookups[index - self.constraints.len()];
+                for (input, _) in &lookup.queries {
+                    input.collect_advice(&mut indices);
+                }
+                &lookup.conditions
+            }
+        };
+        for condition in conditions {
+            condition.0.collect_advice(&mut indices);
+        }
+        indices
+    }
+}
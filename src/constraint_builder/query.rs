@@ -5,6 +5,7 @@ use halo2_proofs::{
     plonk::{Advice, Challenge, Column, Expression, Fixed, VirtualCells},
     poly::Rotation,
 };
+use std::fmt::{Display, Formatter};
 
 #[derive(Clone)]
 pub enum Query<F: Clone> {
@@ -45,6 +46,86 @@ impl<F: FromUniformBytes<64> + Ord> Query<F> {
     pub fn square(self) -> Self {
         self.clone() * self
     }
+
+    /// Degree of the query as a polynomial in the cells it queries.
+    pub fn degree(&self) -> usize {
+        match self {
+            Query::Constant(_) | Query::Challenge(_) => 0,
+            Query::Advice(..) | Query::Fixed(..) => 1,
+            Query::Neg(q) => q.degree(),
+            Query::Add(q, u) => q.degree().max(u.degree()),
+            Query::Mul(q, u) => q.degree() + u.degree(),
+        }
+    }
+
+    // Writes the query, with parentheses if it is a sum.
+    fn fmt_factor(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Query::Add(..) => write!(f, "({self})"),
+            _ => write!(f, "{self}"),
+        }
+    }
+}
+
+/// Formats columns as `advice[i]` and `fixed[i]`, with the rotation in parentheses if it is not
+/// 0, e.g. `advice[3] * (fixed[0](-1) - 1)`.
+impl<F: FromUniformBytes<64> + Ord> Display for Query<F> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Query::Constant(x) => write!(f, "{}", format_constant(x)),
+            Query::Advice(c, r) => write!(f, "advice[{}]{}", c.index(), format_rotation(*r)),
+            Query::Fixed(c, r) => write!(f, "fixed[{}]{}", c.index(), format_rotation(*r)),
+            Query::Challenge(c) => write!(f, "challenge[{}]", c.index()),
+            Query::Neg(q) => {
+                write!(f, "-")?;
+                q.fmt_factor(f)
+            }
+            Query::Add(q, u) => match u.as_ref() {
+                Query::Neg(u) => {
+                    write!(f, "{q} - ")?;
+                    u.fmt_factor(f)
+                }
+                _ => write!(f, "{q} + {u}"),
+            },
+            Query::Mul(q, u) => {
+                q.fmt_factor(f)?;
+                write!(f, " * ")?;
+                u.fmt_factor(f)
+            }
+        }
+    }
+}
+
+fn format_rotation(rotation: i32) -> String {
+    match rotation {
+        0 => String::new(),
+        _ => format!("({rotation})"),
+    }
+}
+
+// Small constants and their negations are written in decimal, and everything else in hex.
+pub(crate) fn format_constant<F: FromUniformBytes<64>>(x: &F) -> String {
+    let small = |x: &F| {
+        let little_endian_bytes = x.to_repr();
+        let (low, high) = little_endian_bytes.as_ref().split_at(8);
+        high.iter()
+            .all(|byte| *byte == 0)
+            .then(|| u64::from_le_bytes(low.try_into().unwrap()))
+    };
+    if let Some(x) = small(x) {
+        x.to_string()
+    } else if let Some(x) = small(&-*x) {
+        format!("-{x}")
+    } else {
+        let big_endian_hex: String = x
+            .to_repr()
+            .as_ref()
+            .iter()
+            .rev()
+            .map(|byte| format!("{byte:02x}"))
+            .collect();
+        format!("0x{big_endian_hex}")
+    }
 }
 
 impl<F: FromUniformBytes<64> + Ord> From<u64> for Query<F> {
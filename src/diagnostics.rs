@@ -0,0 +1,198 @@
+//! Maps failures of the MockProver, or of the native evaluator, back to the update, segment, and
+//! path that own the failing row of the mpt update gadget.
+use crate::{
+    constraint_builder::NativeFailure,
+    gadgets::mpt_update::{PathType, SegmentType},
+    types::{ClaimKind, Proof},
+    MPTProofType,
+};
+use ethers_core::types::{Address, U256};
+use halo2_proofs::{
+    dev::{FailureLocation, VerifyFailure},
+    halo2curves::bn256::Fr,
+};
+use std::{
+    fmt::{Display, Formatter},
+    ops::Range,
+};
+
+/// What a row of the mpt update gadget is assigned to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RowOwner {
+    /// The first row, which is all zeros.
+    AllZero,
+    /// A row of the `proof`th update.
+    Update {
+        proof: usize,
+        proof_type: MPTProofType,
+        address: Address,
+        /// The storage key, for storage updates.
+        storage_key: Option<U256>,
+        segment_type: SegmentType,
+        path_type: PathType,
+        /// Depth of the row in the account or storage trie, or 0 outside of the tries.
+        depth: u64,
+    },
+    /// A padding row after the updates.
+    Padding,
+}
+
+impl Display for RowOwner {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::AllZero => write!(f, "all-zero row"),
+            Self::Padding => write!(f, "padding"),
+            Self::Update {
+                proof,
+                proof_type,
+                address,
+                storage_key,
+                segment_type,
+                path_type,
+                depth,
+            } => {
+                write!(f, "proof #{proof} ({proof_type:?}, {address}")?;
+                if let Some(storage_key) = storage_key {
+                    write!(f, ", slot {storage_key:#x}")?;
+                }
+                write!(f, "), {segment_type:?}")?;
+                if matches!(
+                    segment_type,
+                    SegmentType::AccountTrie | SegmentType::StorageTrie
+                ) {
+                    write!(f, " depth {depth}")?;
+                }
+                write!(f, ", {path_type:?} path")
+            }
+        }
+    }
+}
+
+/// A region that `MptCircuitConfig::assign` assigns mpt update rows in. Sequential assignment
+/// uses one region for all the updates, and parallel assignment one region per update, and one for
+/// the padding rows after them.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct UpdateRegion {
+    /// Index of the region in the layouter, as in `FailureLocation::InRegion`.
+    pub index: usize,
+    /// Indices of the updates assigned in the region.
+    pub proofs: Range<usize>,
+    /// Row of the circuit that the region starts at.
+    pub first_row: usize,
+}
+
+/// Owners of the mpt update rows, from `MptCircuitConfig::diagnostics`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Diagnostics {
+    rows: Vec<RowOwner>,
+    regions: Vec<UpdateRegion>,
+}
+
+impl Diagnostics {
+    /// `rows` are the segment type, path type, and depth of each row, starting from the all-zero
+    /// row, as assigned for `proofs`, and `regions` the regions they were assigned in.
+    pub(crate) fn new(
+        proofs: &[Proof],
+        rows: &[(SegmentType, PathType, Fr)],
+        regions: &[UpdateRegion],
+    ) -> Self {
+        let mut owners = vec![RowOwner::AllZero];
+        for (i, proof) in proofs.iter().enumerate() {
+            let storage_key = match proof.claim.kind {
+                ClaimKind::Storage { key, .. } | ClaimKind::IsEmpty(Some(key)) => Some(key),
+                _ => None,
+            };
+            for _ in 0..proof.n_rows() {
+                let (segment_type, path_type, depth) = rows[owners.len()];
+                owners.push(RowOwner::Update {
+                    proof: i,
+                    proof_type: MPTProofType::from(proof.claim),
+                    address: proof.claim.address,
+                    storage_key,
+                    segment_type,
+                    path_type,
+                    depth: u64::from_le_bytes(depth.to_bytes()[..8].try_into().unwrap()),
+                });
+            }
+        }
+        Self {
+            rows: owners,
+            regions: regions.to_vec(),
+        }
+    }
+
+    /// Owner of `row` of the mpt update gadget. Other gadgets, e.g. key_bit, assign their rows in
+    /// a different order, so this does not apply to their constraints.
+    pub fn row_owner(&self, row: usize) -> RowOwner {
+        self.rows.get(row).copied().unwrap_or(RowOwner::Padding)
+    }
+
+    /// The row a MockProver failure is at. The MockProver reports failures in regions by their
+    /// offset in the region, so those are only mapped to a row if they are in one of the mpt update
+    /// regions that `assign` returned.
+    pub fn failure_row(&self, failure: &VerifyFailure) -> Option<usize> {
+        let location = match failure {
+            VerifyFailure::ConstraintNotSatisfied { location, .. }
+            | VerifyFailure::Lookup { location, .. }
+            | VerifyFailure::Permutation { location, .. } => location,
+            _ => return None,
+        };
+        match location {
+            FailureLocation::InRegion { region, offset } => {
+                let index = region_index(region)?;
+                self.regions
+                    .iter()
+                    .find(|update_region| update_region.index == index)
+                    .map(|update_region| update_region.first_row + offset)
+            }
+            FailureLocation::OutsideRegion { row } => Some(*row),
+        }
+    }
+
+    /// Describes `failure` with the owner of its row, and the name of the constraint or lookup
+    /// from the `ConstraintBuilder`, e.g. "row 45, proof #17 (StorageChanged, 0x0000…0001, slot
+    /// 0x5), StorageTrie depth 4, Common path: constraint "depth increases by 1 in trie segments"
+    /// is not satisfied".
+    pub fn explain(&self, failure: &VerifyFailure) -> String {
+        let description = match failure {
+            VerifyFailure::ConstraintNotSatisfied { constraint, .. } => {
+                match constraint_name(constraint) {
+                    Some(name) => format!("constraint \"{name}\" is not satisfied"),
+                    None => failure.to_string(),
+                }
+            }
+            VerifyFailure::Lookup { name, .. } => {
+                format!("lookup \"{name}\" input is not in the table")
+            }
+            _ => failure.to_string(),
+        };
+        match self.failure_row(failure) {
+            Some(row) => format!("row {row}, {}: {description}", self.row_owner(row)),
+            None => description,
+        }
+    }
+
+    /// Describes a failure of `ConstraintBuilder::evaluate` with the owner of its row.
+    pub fn explain_native(&self, failure: &NativeFailure) -> String {
+        let row = failure.row();
+        format!("row {row}, {}: {failure}", self.row_owner(row))
+    }
+}
+
+// `ConstraintBuilder::build` creates a gate for each constraint, named after it. halo2 does not
+// expose the gate name of a failed constraint, so this takes it from the end of its description,
+// e.g. "Constraint 0 in gate 12 ('depth is 0 in non-trie segments')".
+fn constraint_name(constraint: &impl Display) -> Option<String> {
+    let description = constraint.to_string();
+    let (_, gate) = description.rsplit_once(" in gate ")?;
+    let (_, name) = gate.split_once(" ('")?;
+    name.strip_suffix("')").map(str::to_string)
+}
+
+// halo2 does not expose the index of the region of a failure either, so this takes it from its
+// description, e.g. "Region 3 ('mpt updates')".
+fn region_index(region: &impl Display) -> Option<usize> {
+    let description = region.to_string();
+    let (index, _) = description.strip_prefix("Region ")?.split_once(' ')?;
+    index.parse().ok()
+}
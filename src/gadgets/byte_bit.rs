@@ -1,5 +1,5 @@
-use super::super::constraint_builder::{ConstraintBuilder, FixedColumn, Query};
-use halo2_proofs::{circuit::Region, halo2curves::ff::FromUniformBytes, plonk::ConstraintSystem};
+use super::super::constraint_builder::{AssignRegion, ConstraintBuilder, FixedColumn, Query};
+use halo2_proofs::{halo2curves::ff::FromUniformBytes, plonk::ConstraintSystem};
 
 // TODO: fix name to configggggggg
 #[derive(Clone)]
@@ -30,7 +30,7 @@ impl ByteBitGadget {
         Self { byte, index, bit }
     }
 
-    pub fn assign<F: FromUniformBytes<64> + Ord>(&self, region: &mut Region<'_, F>) {
+    pub fn assign<F: FromUniformBytes<64> + Ord>(&self, region: &mut impl AssignRegion<F>) {
         let mut offset = 1;
         for byte in 0..256 {
             for index in 0..8 {
@@ -1,11 +1,11 @@
 use super::{byte_bit::RangeCheck256Lookup, is_zero::IsZeroGadget, rlc_randomness::RlcRandomness};
 use crate::constraint_builder::{
-    AdviceColumn, ConstraintBuilder, Query, SecondPhaseAdviceColumn, SelectorColumn,
+    AdviceColumn, AssignRegion, ConstraintBuilder, Query, SecondPhaseAdviceColumn, SelectorColumn,
 };
 use halo2_proofs::{
-    circuit::{Region, Value},
+    circuit::Value,
     halo2curves::{bn256::Fr, ff::FromUniformBytes},
-    plonk::ConstraintSystem,
+    plonk::{Any, Column, ConstraintSystem},
 };
 
 pub trait RlcLookup {
@@ -93,7 +93,7 @@ impl ByteRepresentationConfig {
 
     pub fn assign<F: FromUniformBytes<64> + Ord>(
         &self,
-        region: &mut Region<'_, F>,
+        region: &mut impl AssignRegion<F>,
         u32s: &[u32],
         u64s: &[u64],
         u128s: &[u128],
@@ -137,6 +137,25 @@ impl ByteRepresentationConfig {
         );
     }
 
+    /// Columns of the gadget, by name, e.g. to dump their assigned values.
+    pub fn columns(&self) -> Vec<(String, Column<Any>)> {
+        let columns: [(&str, Column<Any>); 6] = [
+            ("value", self.value.0.into()),
+            ("rlc", self.rlc.0.into()),
+            ("index", self.index.0.into()),
+            ("is_first", self.is_first.0.into()),
+            ("byte", self.byte.0.into()),
+            (
+                "index_is_zero.inverse_or_zero",
+                self.index_is_zero.inverse_or_zero.0.into(),
+            ),
+        ];
+        columns
+            .into_iter()
+            .map(|(name, column)| (name.to_string(), column))
+            .collect()
+    }
+
     pub fn n_rows_required(u32s: &[u32], u64s: &[u64], u128s: &[u128], frs: &[Fr]) -> usize {
         // +1 because assigment starts on offset = 1 instead of offset = 0.
         1 + u32s.len() * 4 + u64s.len() * 8 + u128s.len() * 16 + frs.len() * 31
@@ -1,6 +1,6 @@
 use super::super::constraint_builder::{
-    AdviceColumn, BinaryColumn, ConstraintBuilder, FixedColumn, Query, SecondPhaseAdviceColumn,
-    SelectorColumn,
+    AdviceColumn, AssignRegion, BinaryColumn, ConstraintBuilder, FixedColumn, Query,
+    SecondPhaseAdviceColumn, SelectorColumn,
 };
 use super::{byte_bit::RangeCheck256Lookup, is_zero::IsZeroGadget, rlc_randomness::RlcRandomness};
 use ethers_core::k256::elliptic_curve::PrimeField;
@@ -9,7 +9,7 @@ use halo2_proofs::{
     arithmetic::Field,
     circuit::{Region, Value},
     halo2curves::bn256::Fr,
-    plonk::ConstraintSystem,
+    plonk::{Any, Column, ConstraintSystem},
 };
 use halo2_proofs::{circuit::Layouter, halo2curves::ff::FromUniformBytes, plonk::Error};
 use itertools::Itertools;
@@ -134,7 +134,7 @@ impl CanonicalRepresentationConfig {
 
     pub fn assign(
         &self,
-        region: &mut Region<'_, Fr>,
+        region: &mut impl AssignRegion<Fr>,
         randomness: Value<Fr>,
         values: &[Fr],
         n_rows: usize,
@@ -305,6 +305,32 @@ impl CanonicalRepresentationConfig {
             .unwrap();
     }
 
+    /// Columns of the gadget, by name, e.g. to dump their assigned values.
+    pub fn columns(&self) -> Vec<(String, Column<Any>)> {
+        let columns: [(&str, Column<Any>); 10] = [
+            ("value", self.value.0.into()),
+            ("index", self.index.0.into()),
+            ("byte", self.byte.0.into()),
+            ("rlc", self.rlc.0.into()),
+            ("index_is_zero", self.index_is_zero.0.into()),
+            ("index_is_31", self.index_is_31.0.into()),
+            ("modulus_byte", self.modulus_byte.0.into()),
+            ("difference", self.difference.0.into()),
+            (
+                "difference_is_zero.inverse_or_zero",
+                self.difference_is_zero.inverse_or_zero.0.into(),
+            ),
+            (
+                "differences_are_zero_so_far",
+                self.differences_are_zero_so_far.0.into(),
+            ),
+        ];
+        columns
+            .into_iter()
+            .map(|(name, column)| (name.to_string(), column))
+            .collect()
+    }
+
     pub fn n_rows_required(values: &[Fr]) -> usize {
         // +1 because assigment starts on offset = 1 instead of offset = 0.
         values.len() * 32 + 1
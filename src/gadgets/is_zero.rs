@@ -1,7 +1,7 @@
-use crate::constraint_builder::{AdviceColumn, BinaryQuery, ConstraintBuilder, Query};
-use halo2_proofs::{
-    circuit::Region, halo2curves::ff::FromUniformBytes, plonk::Assigned, plonk::ConstraintSystem,
+use crate::constraint_builder::{
+    AdviceColumn, AssignRegion, BinaryQuery, ConstraintBuilder, Query,
 };
+use halo2_proofs::{halo2curves::ff::FromUniformBytes, plonk::Assigned, plonk::ConstraintSystem};
 use std::fmt::Debug;
 
 #[derive(Clone, Copy)]
@@ -21,7 +21,7 @@ impl IsZeroGadget {
 
     pub fn assign<F: FromUniformBytes<64> + Ord, T: Copy + TryInto<F>>(
         &self,
-        region: &mut Region<'_, F>,
+        region: &mut impl AssignRegion<F>,
         offset: usize,
         value: T,
     ) where
@@ -38,7 +38,7 @@ impl IsZeroGadget {
     // TODO: get rid of assign method in favor of it.
     pub fn assign_value_and_inverse<F: FromUniformBytes<64> + Ord, T: Copy + TryInto<F>>(
         &self,
-        region: &mut Region<'_, F>,
+        region: &mut impl AssignRegion<F>,
         offset: usize,
         value: T,
     ) where
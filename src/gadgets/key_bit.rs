@@ -2,12 +2,12 @@ use super::{
     byte_bit::{ByteBitLookup, RangeCheck256Lookup, RangeCheck8Lookup},
     canonical_representation::CanonicalRepresentationLookup,
 };
-use crate::constraint_builder::{AdviceColumn, ConstraintBuilder, Query};
+use crate::constraint_builder::{AdviceColumn, AssignRegion, ConstraintBuilder, Query};
 use halo2_proofs::circuit::Layouter;
 use halo2_proofs::{
     circuit::Region,
     halo2curves::{bn256::Fr, ff::FromUniformBytes},
-    plonk::ConstraintSystem,
+    plonk::{Any, Column, ConstraintSystem},
 };
 use itertools::Itertools;
 
@@ -86,12 +86,12 @@ impl KeyBitConfig {
         }
     }
 
-    pub fn assign(&self, region: &mut Region<'_, Fr>, lookups: &[(Fr, usize, bool)]) {
+    pub fn assign(&self, region: &mut impl AssignRegion<Fr>, lookups: &[(Fr, usize, bool)]) {
         self.assign_internal(region, lookups, false)
     }
     pub fn assign_internal(
         &self,
-        region: &mut Region<'_, Fr>,
+        region: &mut impl AssignRegion<Fr>,
         lookups: &[(Fr, usize, bool)],
         use_par: bool,
     ) {
@@ -160,6 +160,21 @@ impl KeyBitConfig {
         layouter.assign_regions(|| "key_bit", assignments).unwrap();
     }
 
+    /// Columns of the gadget, by name, e.g. to dump their assigned values.
+    pub fn columns(&self) -> Vec<(String, Column<Any>)> {
+        [
+            ("value", self.value),
+            ("index", self.index),
+            ("bit", self.bit),
+            ("index_div_8", self.index_div_8),
+            ("index_mod_8", self.index_mod_8),
+            ("byte", self.byte),
+        ]
+        .into_iter()
+        .map(|(name, column)| (name.to_string(), column.0.into()))
+        .collect()
+    }
+
     pub fn n_rows_required(lookups: &[(Fr, usize, bool)]) -> usize {
         // +1 because assigment starts on offset = 1 instead of offset = 0.
         1 + lookups.len()
@@ -3,7 +3,7 @@ mod path;
 mod segment;
 mod word_rlc;
 pub use path::PathType;
-use segment::SegmentType;
+pub use segment::SegmentType;
 use word_rlc::{assign as assign_word_rlc, configure as configure_word_rlc};
 
 use super::{
@@ -17,8 +17,10 @@ use super::{
 };
 use crate::{
     constraint_builder::{
-        AdviceColumn, BinaryQuery, ConstraintBuilder, Query, SecondPhaseAdviceColumn,
+        AdviceColumn, AssignRegion, BinaryColumn, BinaryQuery, ConstraintBuilder, Query,
+        SecondPhaseAdviceColumn, SelectorColumn, WitnessTable,
     },
+    diagnostics::UpdateRegion,
     types::{
         storage::{StorageLeaf, StorageProof},
         trie::{next_domain, TrieRows},
@@ -31,9 +33,9 @@ use ethers_core::types::Address;
 use halo2_proofs::circuit::Layouter;
 use halo2_proofs::{
     arithmetic::Field,
-    circuit::{Region, Value},
+    circuit::{AssignedCell, Region, Value},
     halo2curves::{bn256::Fr, ff::FromUniformBytes, group::ff::PrimeField},
-    plonk::ConstraintSystem,
+    plonk::{Advice, Any, Assigned, Column, ConstraintSystem},
 };
 use itertools::{izip, Itertools};
 use lazy_static::lazy_static;
@@ -75,6 +77,21 @@ pub struct MptUpdateConfig {
     intermediate_values: [AdviceColumn; 10], // can be 4?
     second_phase_intermediate_values: [SecondPhaseAdviceColumn; 10], // 4?
     is_zero_gadgets: [IsZeroGadget; 4],      // can be 3
+
+    root_transitions: Option<RootTransitions>,
+}
+
+/// Running values over the mpt updates, from the first row up to and including the current one.
+#[derive(Clone, Copy)]
+struct RootTransitions {
+    // Padding updates have to come after all the non-padding ones.
+    is_padding: BinaryColumn,
+    first_old_root: AdviceColumn,
+    last_new_root: AdviceColumn,
+    n_updates: AdviceColumn,
+    // Inverses of key - hash(0, 0) and old root, one of which exists unless the update is in the
+    // padding form.
+    padding_form_inverses: [AdviceColumn; 2],
 }
 
 impl<F: FromUniformBytes<64> + Ord> MptUpdateLookup<F> for MptUpdateConfig {
@@ -257,6 +274,7 @@ impl MptUpdateConfig {
             intermediate_values,
             second_phase_intermediate_values,
             is_zero_gadgets,
+            root_transitions: None,
         };
 
         let path_transitions = path::forward_transitions();
@@ -332,7 +350,7 @@ impl MptUpdateConfig {
                         rlc,
                         rlc_randomness.query(),
                     ),
-                    MPTProofType::AccountDestructed => cb.assert_unreachable("unimplemented!"),
+                    MPTProofType::AccountDestructed => configure_self_destruct(cb, &config),
                 }
             };
             cb.condition(
@@ -344,8 +362,253 @@ impl MptUpdateConfig {
         config
     }
 
+    /// Adds columns tracking the old root of the first non-padding update, the new root of the
+    /// last non-padding update, and the number of non-padding updates. Their values on the final
+    /// row, which must be padding, are the values for the whole circuit.
+    ///
+    /// An update is padding iff it is a proof that address 0 does not exist in the empty mpt, so
+    /// the number of updates cannot be inflated by counting padding updates as non-padding. A
+    /// non-padding update of this form can only be proven as padding, i.e. as the last update.
+    pub fn configure_root_transitions<F: FromUniformBytes<64> + Ord>(
+        &mut self,
+        cs: &mut ConstraintSystem<F>,
+        cb: &mut ConstraintBuilder<F>,
+        selector: SelectorColumn,
+        is_final_row: SelectorColumn,
+    ) {
+        let is_padding = BinaryColumn::configure(cs, cb);
+        let [first_old_root, last_new_root, n_updates] = cb.advice_columns(cs);
+        for column in [first_old_root, last_new_root, n_updates] {
+            cs.enable_equality(column.0);
+        }
+        let padding_form_inverses: [AdviceColumn; 2] = cb.advice_columns(cs);
+
+        let is_start = self.segment_type.current_matches(&[SegmentType::Start]);
+        let is_first_row = !selector.rotation(-1);
+        let n_updates_delta = Query::one() - is_padding.current();
+        cb.condition(is_first_row.clone(), |cb| {
+            cb.assert_equal(
+                "first_old_root is old root on first row",
+                first_old_root.current(),
+                self.old_hash.current(),
+            );
+            cb.assert_equal(
+                "last_new_root is new root on first row, unless it is padding",
+                last_new_root.current(),
+                is_padding
+                    .current()
+                    .select(Query::zero(), self.new_hash.current()),
+            );
+            cb.assert_equal(
+                "n_updates is 1 on first row, unless it is padding",
+                n_updates.current(),
+                n_updates_delta.clone(),
+            );
+        });
+        cb.condition(!is_first_row, |cb| {
+            cb.assert_equal(
+                "first_old_root does not change",
+                first_old_root.current(),
+                first_old_root.previous(),
+            );
+            cb.condition(!is_start.clone(), |cb| {
+                cb.assert_equal(
+                    "is_padding can only change on Start rows",
+                    is_padding.current().into(),
+                    is_padding.previous().into(),
+                );
+                cb.assert_equal(
+                    "last_new_root can only change on Start rows",
+                    last_new_root.current(),
+                    last_new_root.previous(),
+                );
+                cb.assert_equal(
+                    "n_updates can only change on Start rows",
+                    n_updates.current(),
+                    n_updates.previous(),
+                );
+            });
+            cb.condition(is_start.clone(), |cb| {
+                cb.condition(is_padding.previous(), |cb| {
+                    cb.assert(
+                        "padding updates come after non-padding updates",
+                        is_padding.current(),
+                    );
+                });
+                cb.assert_equal(
+                    "last_new_root is new root, unless update is padding",
+                    last_new_root.current(),
+                    is_padding
+                        .current()
+                        .select(last_new_root.previous(), self.new_hash.current()),
+                );
+                cb.assert_equal(
+                    "n_updates increases by 1, unless update is padding",
+                    n_updates.current(),
+                    n_updates.previous() + n_updates_delta,
+                );
+            });
+        });
+        cb.condition(
+            is_start.clone().and(!is_padding.current()).and(
+                self.proof_type
+                    .current_matches(&[MPTProofType::AccountDoesNotExist]),
+            ),
+            |cb| {
+                // The key is hash(0, 0) iff the address is 0, because the key is the poseidon
+                // hash of the address.
+                let [key_inverse, old_root_inverse] = padding_form_inverses;
+                cb.assert_equal(
+                    "non-padding update is not proof that address 0 does not exist in empty mpt",
+                    (self.key.current() - *ZERO_PAIR_HASH) * key_inverse.current()
+                        + self.old_hash.current() * old_root_inverse.current(),
+                    Query::one(),
+                );
+            },
+        );
+        cb.condition(is_start.and(is_padding.current()), |cb| {
+            cb.assert(
+                "padding update is proof that address 0 does not exist in empty mpt",
+                self.proof_type
+                    .current_matches(&[MPTProofType::AccountDoesNotExist]),
+            );
+            let [address_high, address_low, ..] = self.intermediate_values;
+            cb.assert_zero("address_high is 0 for padding", address_high.current());
+            cb.assert_zero("address_low is 0 for padding", address_low.current());
+            cb.assert_zero("old root is 0 for padding", self.old_hash.current());
+        });
+        cb.condition(is_final_row.current(), |cb| {
+            cb.assert("final update is padding", is_padding.current());
+        });
+
+        self.root_transitions = Some(RootTransitions {
+            is_padding,
+            first_old_root,
+            last_new_root,
+            n_updates,
+            padding_form_inverses,
+        });
+    }
+
+    /// Constrains the old root of each non-padding update to be the new root of the update before
+    /// it, so that the updates form a single state transition. Requires the columns added by
+    /// `configure_root_transitions`.
+    pub fn configure_root_chaining<F: FromUniformBytes<64> + Ord>(
+        &self,
+        cb: &mut ConstraintBuilder<F>,
+        selector: SelectorColumn,
+    ) {
+        let RootTransitions {
+            is_padding,
+            last_new_root,
+            ..
+        } = self
+            .root_transitions
+            .expect("root chaining requires root transitions to be configured");
+
+        // Padding updates come last, so the update before a non-padding update is never padding,
+        // and last_new_root on the previous row is its new root.
+        let is_first_row = !selector.rotation(-1);
+        let is_start = self.segment_type.current_matches(&[SegmentType::Start]);
+        cb.condition(
+            (!is_first_row).and(is_start).and(!is_padding.current()),
+            |cb| {
+                cb.assert_equal(
+                    "old root is new root of previous update",
+                    self.old_hash.current(),
+                    last_new_root.previous(),
+                );
+            },
+        );
+    }
+
+    /// Assigns the root transition columns, if they are configured, for a padding row after
+    /// `proofs`. Returns the cells for the first old root, last new root, and number of updates.
+    pub fn assign_padding_root_transitions(
+        &self,
+        region: &mut Region<'_, Fr>,
+        offset: usize,
+        proofs: &[Proof],
+    ) -> Option<[AssignedCell<Fr, Fr>; 3]> {
+        let root_transitions = self.root_transitions?;
+        root_transitions.is_padding.assign(region, offset, true);
+        let [first_old_root, last_new_root, n_updates] = root_transition_values(proofs);
+        // These are assigned directly on the region, because `AssignRegion` does not return cells.
+        Some(
+            [
+                (root_transitions.first_old_root, first_old_root),
+                (root_transitions.last_new_root, last_new_root),
+                (root_transitions.n_updates, n_updates),
+            ]
+            .map(|(column, value)| {
+                region
+                    .assign_advice(
+                        || "root transition",
+                        column.0,
+                        offset,
+                        || Value::known(value),
+                    )
+                    .expect("failed assign_advice")
+            }),
+        )
+    }
+
+    /// Like `assign_padding_root_transitions`, but for any `AssignRegion`, so without the cells.
+    pub fn assign_padding_root_transition_values(
+        &self,
+        region: &mut impl AssignRegion<Fr>,
+        offset: usize,
+        proofs: &[Proof],
+    ) {
+        self.assign_root_transitions(region, offset, 1, true, proofs)
+    }
+
+    // `proofs` are the non-padding updates up to and including the ones in the assigned rows.
+    fn assign_root_transitions(
+        &self,
+        region: &mut impl AssignRegion<Fr>,
+        offset: usize,
+        n_rows: usize,
+        is_padding: bool,
+        proofs: &[Proof],
+    ) {
+        let Some(root_transitions) = self.root_transitions else {
+            return;
+        };
+        let [first_old_root, last_new_root, n_updates] = root_transition_values(proofs);
+        if let (false, Some(proof)) = (is_padding, proofs.last()) {
+            let [key_inverse, old_root_inverse] = root_transitions.padding_form_inverses;
+            let key_difference = account_key(proof.claim.address) - *ZERO_PAIR_HASH;
+            if key_difference != Fr::zero() {
+                key_inverse.assign_rational(
+                    region,
+                    offset,
+                    Assigned::from(key_difference).invert(),
+                );
+            } else {
+                old_root_inverse.assign_rational(
+                    region,
+                    offset,
+                    Assigned::from(proof.claim.old_root).invert(),
+                );
+            }
+        }
+        for offset in offset..offset + n_rows {
+            root_transitions
+                .is_padding
+                .assign(region, offset, is_padding);
+            root_transitions
+                .first_old_root
+                .assign(region, offset, first_old_root);
+            root_transitions
+                .last_new_root
+                .assign(region, offset, last_new_root);
+            root_transitions.n_updates.assign(region, offset, n_updates);
+        }
+    }
+
     /// Valid assignment proving that the address 0 doesn't exist in an empty MPT.
-    pub fn assign_padding_row(&self, region: &mut Region<'_, Fr>, offset: usize) {
+    pub fn assign_padding_row(&self, region: &mut impl AssignRegion<Fr>, offset: usize) {
         self.proof_type
             .assign(region, offset, MPTProofType::AccountDoesNotExist);
         self.key.assign(region, offset, *ZERO_PAIR_HASH);
@@ -356,14 +619,15 @@ impl MptUpdateConfig {
     /// ..
     pub fn assign(
         &self,
-        region: &mut Region<'_, Fr>,
+        region: &mut impl AssignRegion<Fr>,
         proofs: &[Proof],
         randomness: Value<Fr>,
     ) -> usize {
         let n_rows = proofs.iter().map(|proof| proof.n_rows()).sum();
         let mut offset = 1; // selector on first row is disabled.
-        for proof in proofs {
+        for (i, proof) in proofs.iter().enumerate() {
             self.assign_single_proof(region, proof, randomness, offset);
+            self.assign_root_transitions(region, offset, proof.n_rows(), false, &proofs[..=i]);
             offset += proof.n_rows();
             log::debug!("offset: {}", offset);
         }
@@ -379,7 +643,7 @@ impl MptUpdateConfig {
 
     pub fn assign_single_proof(
         &self,
-        region: &mut Region<'_, Fr>,
+        region: &mut impl AssignRegion<Fr>,
         proof: &Proof,
         randomness: Value<Fr>,
         mut offset: usize,
@@ -519,8 +783,8 @@ impl MptUpdateConfig {
             MPTProofType::StorageChanged | MPTProofType::StorageDoesNotExist => {
                 vec![true, false, true, false]
             }
+            MPTProofType::AccountDestructed => vec![true],
             MPTProofType::AccountDoesNotExist => unreachable!(),
-            MPTProofType::AccountDestructed => unimplemented!(),
         };
         let next_offset = offset + directions.len();
 
@@ -536,7 +800,13 @@ impl MptUpdateConfig {
             izip!(segment_types, siblings, old_hashes, new_hashes, directions).enumerate()
         {
             if i == 0 {
-                self.is_zero_gadgets[3].assign_value_and_inverse(region, offset, old_hash);
+                // The nonexistence proof for a deleted account is on the new side of the leaf.
+                let hash = if proof_type == MPTProofType::AccountDestructed {
+                    new_hash
+                } else {
+                    old_hash
+                };
+                self.is_zero_gadgets[3].assign_value_and_inverse(region, offset, hash);
                 self.domain.assign(region, offset + i, HashDomain::Leaf);
             } else {
                 self.domain
@@ -562,7 +832,17 @@ impl MptUpdateConfig {
                     if let ClaimKind::Storage { key, .. } | ClaimKind::IsEmpty(Some(key)) =
                         proof.claim.kind
                     {
-                        self.key.assign(region, offset + 3, proof.storage.key());
+                        // Only existing accounts have this segment, and Proof::try_from
+                        // rejects their storage claims without a storage path.
+                        let mpt_key = proof
+                            .storage
+                            .key()
+                            .expect("storage claim has a storage key");
+                        let other_mpt_key = proof
+                            .storage
+                            .other_key()
+                            .expect("storage claim has a storage key");
+                        self.key.assign(region, offset + 3, mpt_key);
                         let [storage_key_high, storage_key_low, new_domain, ..] =
                             self.intermediate_values;
                         let [rlc_storage_key_high, rlc_storage_key_low, ..] =
@@ -575,8 +855,7 @@ impl MptUpdateConfig {
                             [rlc_storage_key_high, rlc_storage_key_low],
                             randomness,
                         );
-                        self.other_key
-                            .assign(region, offset + 3, proof.storage.other_key());
+                        self.other_key.assign(region, offset + 3, other_mpt_key);
                         new_domain.assign(region, offset + 3, HashDomain::AccountFields);
                     }
                 }
@@ -614,18 +893,21 @@ impl MptUpdateConfig {
         self.assign_storage(region, next_offset, &proof.storage, randomness);
     }
 
+    /// Assigns each update in its own region, and returns those regions.
     pub(crate) fn assign_par(
         &self,
         layouter: &mut impl Layouter<Fr>,
         proofs: &[Proof],
         randomness: Value<Fr>,
-    ) -> usize {
+    ) -> Vec<UpdateRegion> {
         let mut is_first_passes = vec![true; proofs.len()];
+        let mut region_indices = vec![0; proofs.len()];
         let update_assignments = proofs
             .iter()
             .zip(is_first_passes.iter_mut())
+            .zip(region_indices.iter_mut())
             .enumerate()
-            .map(|(i, (proof, is_first_pass))| {
+            .map(|(i, ((proof, is_first_pass), region_index))| {
                 move |mut region: Region<'_, Fr>| {
                     let n_rows = proof.n_rows();
                     let (first_off, last_off) = if i == 0 {
@@ -649,6 +931,14 @@ impl MptUpdateConfig {
                         return Ok(());
                     }
                     self.assign_single_proof(&mut region, proof, randomness, first_off);
+                    self.assign_root_transitions(
+                        &mut region,
+                        first_off,
+                        n_rows,
+                        false,
+                        &proofs[..=i],
+                    );
+                    *region_index = self.region_index(&mut region, first_off, proof.claim.old_root);
 
                     Ok(())
                 }
@@ -659,7 +949,38 @@ impl MptUpdateConfig {
             .assign_regions(|| "mpt updates", update_assignments)
             .unwrap();
 
-        proofs.iter().map(|proof| proof.n_rows()).sum()
+        // The first region also has the all-zero row, and the others follow it without gaps.
+        let mut first_row = 0;
+        izip!(0.., region_indices, proofs)
+            .map(|(i, index, proof)| {
+                let region = UpdateRegion {
+                    index,
+                    proofs: i..i + 1,
+                    first_row,
+                };
+                first_row += usize::from(i == 0) + proof.n_rows();
+                region
+            })
+            .collect()
+    }
+
+    /// Index of `region` in the layouter. `AssignRegion` does not return cells, so this assigns
+    /// `old_hash` at `offset` again, directly on the region, with `old_hash`, its value there.
+    pub(crate) fn region_index(
+        &self,
+        region: &mut Region<'_, Fr>,
+        offset: usize,
+        old_hash: Fr,
+    ) -> usize {
+        let cell = region
+            .assign_advice(
+                || "old_hash",
+                self.old_hash.0,
+                offset,
+                || Value::known(old_hash),
+            )
+            .expect("failed assign_advice");
+        *cell.cell().region_index
     }
 
     pub fn n_rows_required(proofs: &[Proof]) -> usize {
@@ -669,7 +990,7 @@ impl MptUpdateConfig {
 
     fn assign_account_trie_rows(
         &self,
-        region: &mut Region<'_, Fr>,
+        region: &mut impl AssignRegion<Fr>,
         starting_offset: usize,
         rows: &TrieRows,
     ) -> usize {
@@ -683,7 +1004,7 @@ impl MptUpdateConfig {
 
     fn assign_storage_trie_rows(
         &self,
-        region: &mut Region<'_, Fr>,
+        region: &mut impl AssignRegion<Fr>,
         starting_offset: usize,
         rows: &TrieRows,
     ) -> usize {
@@ -697,7 +1018,7 @@ impl MptUpdateConfig {
 
     fn assign_trie_rows(
         &self,
-        region: &mut Region<'_, Fr>,
+        region: &mut impl AssignRegion<Fr>,
         starting_offset: usize,
         rows: &TrieRows,
     ) -> usize {
@@ -711,11 +1032,10 @@ impl MptUpdateConfig {
                 if !matches!(next_row.path_type, PathType::Start | PathType::Common)
                     && row.path_type == PathType::Common
                 {
-                    self.intermediate_values[2].assign(
-                        region,
-                        offset,
-                        next_domain(row.domain, row.direction),
-                    );
+                    // TrieRows::new rejects extensions below nodes that cannot be extended.
+                    let domain = next_domain(row.domain, row.direction)
+                        .expect("extension starts at a branch with an empty child");
+                    self.intermediate_values[2].assign(region, offset, domain);
                 }
             }
             for (value, column) in [
@@ -733,7 +1053,7 @@ impl MptUpdateConfig {
 
     fn assign_storage(
         &self,
-        region: &mut Region<'_, Fr>,
+        region: &mut impl AssignRegion<Fr>,
         offset: usize,
         storage: &StorageProof,
         randomness: Value<Fr>,
@@ -747,7 +1067,9 @@ impl MptUpdateConfig {
                 new_leaf,
                 ..
             } => {
-                let other_key = storage.other_key();
+                let other_key = storage
+                    .other_key()
+                    .expect("storage update has an other key");
                 let n_trie_rows = self.assign_storage_trie_rows(region, offset, trie_rows);
                 let n_leaf_rows = self.assign_storage_leaf_row(
                     region,
@@ -772,7 +1094,7 @@ impl MptUpdateConfig {
 
     fn assign_empty_storage_proof(
         &self,
-        region: &mut Region<'_, Fr>,
+        region: &mut impl AssignRegion<Fr>,
         offset: usize,
         key: Fr,
         other_key: Fr,
@@ -821,7 +1143,7 @@ impl MptUpdateConfig {
 
     fn assign_storage_leaf_row(
         &self,
-        region: &mut Region<'_, Fr>,
+        region: &mut impl AssignRegion<Fr>,
         offset: usize,
         key: Fr,
         other_key: Fr,
@@ -938,6 +1260,114 @@ impl MptUpdateConfig {
 
         1
     }
+
+    /// Segment type, path type, and depth assigned at `row` of `table`.
+    pub fn assigned_row(
+        &self,
+        table: &WitnessTable<Fr>,
+        row: usize,
+    ) -> (SegmentType, PathType, Fr) {
+        (
+            self.segment_type.assigned_value(table, row),
+            self.path_type.assigned_value(table, row),
+            table.advice(self.depth.0, row),
+        )
+    }
+
+    /// Columns of the gadget, by name, e.g. to dump their assigned values. One hot columns are
+    /// named by variant, e.g. `segment_type.AccountTrie`.
+    pub fn columns(&self) -> Vec<(String, Column<Any>)> {
+        let mut columns: Vec<(String, Column<Advice>)> = [
+            ("domain", self.domain.0),
+            ("old_hash", self.old_hash.0),
+            ("new_hash", self.new_hash.0),
+            ("old_value", self.old_value.0),
+            ("new_value", self.new_value.0),
+            ("storage_key_rlc", self.storage_key_rlc.0),
+            ("depth", self.depth.0),
+            ("key", self.key.0),
+            ("other_key", self.other_key.0),
+            ("direction", self.direction.0),
+            ("sibling", self.sibling.0),
+        ]
+        .into_iter()
+        .map(|(name, column)| (name.to_string(), column))
+        .collect();
+        for (i, column) in self.intermediate_values.iter().enumerate() {
+            columns.push((format!("intermediate_values.{i}"), column.0));
+        }
+        for (i, column) in self.second_phase_intermediate_values.iter().enumerate() {
+            columns.push((format!("second_phase_intermediate_values.{i}"), column.0));
+        }
+        for (i, gadget) in self.is_zero_gadgets.iter().enumerate() {
+            columns.push((format!("is_zero_gadgets.{i}.value"), gadget.value.0));
+            columns.push((
+                format!("is_zero_gadgets.{i}.inverse_or_zero"),
+                gadget.inverse_or_zero.0,
+            ));
+        }
+        for (variant, column) in self.proof_type.columns() {
+            columns.push((format!("proof_type.{variant:?}"), column));
+        }
+        for (variant, column) in self.segment_type.columns() {
+            columns.push((format!("segment_type.{variant:?}"), column));
+        }
+        for (variant, column) in self.path_type.columns() {
+            columns.push((format!("path_type.{variant:?}"), column));
+        }
+        if let Some(root_transitions) = self.root_transitions {
+            columns.extend(
+                [
+                    ("is_padding", root_transitions.is_padding.0),
+                    ("first_old_root", root_transitions.first_old_root.0),
+                    ("last_new_root", root_transitions.last_new_root.0),
+                    ("n_updates", root_transitions.n_updates.0),
+                    ("key_inverse", root_transitions.padding_form_inverses[0].0),
+                    (
+                        "old_root_inverse",
+                        root_transitions.padding_form_inverses[1].0,
+                    ),
+                ]
+                .map(|(name, column)| (format!("root_transitions.{name}"), column)),
+            );
+        }
+        columns
+            .into_iter()
+            .map(|(name, column)| (name, column.into()))
+            .collect()
+    }
+
+    /// Proof type assigned at `row` of `table`.
+    pub fn assigned_proof_type(&self, table: &WitnessTable<Fr>, row: usize) -> MPTProofType {
+        self.proof_type.assigned_value(table, row)
+    }
+}
+
+#[cfg(test)]
+impl MptUpdateConfig {
+    /// Columns that hold a single value per row, by name.
+    pub fn named_columns(&self) -> [(&'static str, Column<Advice>); 10] {
+        [
+            ("domain", self.domain.0),
+            ("old_hash", self.old_hash.0),
+            ("new_hash", self.new_hash.0),
+            ("old_value", self.old_value.0),
+            ("new_value", self.new_value.0),
+            ("depth", self.depth.0),
+            ("key", self.key.0),
+            ("other_key", self.other_key.0),
+            ("direction", self.direction.0),
+            ("sibling", self.sibling.0),
+        ]
+    }
+
+    pub fn segment_type(&self) -> &OneHot<SegmentType> {
+        &self.segment_type
+    }
+
+    pub fn path_type(&self) -> &OneHot<PathType> {
+        &self.path_type
+    }
 }
 
 fn old_left<F: FromUniformBytes<64> + Ord>(config: &MptUpdateConfig) -> Query<F> {
@@ -1185,7 +1615,7 @@ fn configure_extension_old<F: FromUniformBytes<64> + Ord>(
     poseidon: &impl PoseidonLookup,
 ) {
     cb.assert(
-        "can only delete existing storage trie nodes for storage proofs",
+        "can only delete existing nodes for storage proofs and account destructed proofs",
         config
             .proof_type
             .current_matches(&[MPTProofType::StorageChanged])
@@ -1193,7 +1623,15 @@ fn configure_extension_old<F: FromUniformBytes<64> + Ord>(
                 config
                     .segment_type
                     .current_matches(&[SegmentType::StorageTrie, SegmentType::StorageLeaf0]),
-            ),
+            )
+            .or(config
+                .proof_type
+                .current_matches(&[MPTProofType::AccountDestructed])
+                .and(
+                    config
+                        .segment_type
+                        .current_matches(&[SegmentType::AccountTrie, SegmentType::AccountLeaf0]),
+                )),
     );
     cb.assert_zero(
         "new value is 0 when deleting node",
@@ -1215,30 +1653,33 @@ fn configure_extension_old<F: FromUniformBytes<64> + Ord>(
         poseidon,
     );
     cb.assert(
-        "common -> extension old switch only allowed in storage trie segments",
+        "common -> extension old switch only allowed in trie segments",
         config
             .path_type
             .previous_matches(&[PathType::ExtensionOld])
-            .or(config
-                .segment_type
-                .current_matches(&[SegmentType::StorageTrie, SegmentType::StorageLeaf0])),
+            .or(config.segment_type.current_matches(&[
+                SegmentType::AccountTrie,
+                SegmentType::AccountLeaf0,
+                SegmentType::StorageTrie,
+                SegmentType::StorageLeaf0,
+            ])),
     );
-    let is_storage_trie_segment = config
+    let is_trie_segment = config
         .segment_type
-        .current_matches(&[SegmentType::StorageTrie]);
-    cb.condition(is_storage_trie_segment, |cb| {
-        let is_final_storage_trie_segment = config
+        .current_matches(&[SegmentType::AccountTrie, SegmentType::StorageTrie]);
+    cb.condition(is_trie_segment, |cb| {
+        let is_final_trie_segment = !config
             .segment_type
-            .next_matches(&[SegmentType::StorageLeaf0]);
-        cb.condition(!is_final_storage_trie_segment.clone(), |cb| {
+            .next_matches(&[SegmentType::AccountTrie, SegmentType::StorageTrie]);
+        cb.condition(!is_final_trie_segment.clone(), |cb| {
             cb.assert_zero(
                 "sibling is zero for non-final old extension path segments",
                 config.sibling.current(),
             );
         });
-        cb.condition(is_final_storage_trie_segment, |cb| {
+        cb.condition(is_final_trie_segment, |cb| {
             cb.assert_equal(
-                "sibling is new leaf hash for final new extension path segments",
+                "sibling is new leaf hash for final old extension path segments",
                 config.sibling.current(),
                 config.new_hash.previous(),
             );
@@ -1247,7 +1688,7 @@ fn configure_extension_old<F: FromUniformBytes<64> + Ord>(
     cb.condition(
         config
             .segment_type
-            .current_matches(&[SegmentType::StorageLeaf0]),
+            .current_matches(&[SegmentType::AccountLeaf0, SegmentType::StorageLeaf0]),
         |cb| {
             let [.., key_equals_other_key, new_hash_is_zero] = config.is_zero_gadgets;
             let [.., other_leaf_data_hash] = config.intermediate_values;
@@ -2035,6 +2476,31 @@ fn configure_empty_account<F: FromUniformBytes<64> + Ord>(
     }
 }
 
+fn configure_self_destruct<F: FromUniformBytes<64> + Ord>(
+    cb: &mut ConstraintBuilder<F>,
+    config: &MptUpdateConfig,
+) {
+    cb.assert_zero("old value is 0", config.old_value.current());
+    cb.assert_zero("new value is 0", config.new_value.current());
+    cb.condition(
+        config
+            .segment_type
+            .current_matches(&[SegmentType::AccountLeaf0]),
+        |cb| {
+            cb.assert_equal("direction is 1", config.direction.current(), Query::one());
+            cb.assert_equal(
+                "sibling is account key for destructed account",
+                config.sibling.current(),
+                config.key.current(),
+            );
+            cb.assert(
+                "account leaf is deleted for account destructed proof",
+                config.path_type.current_matches(&[PathType::ExtensionOld]),
+            );
+        },
+    );
+}
+
 fn address_high(a: Address) -> u128 {
     let high_bytes: [u8; 16] = a.0[..16].try_into().unwrap();
     u128::from_be_bytes(high_bytes)
@@ -2045,6 +2511,54 @@ fn address_low(a: Address) -> u32 {
     u32::from_be_bytes(low_bytes)
 }
 
+/// Old root of the first update, new root of the last update, and number of updates. These are
+/// the values exposed as public inputs by `MptCircuitConfig` when it is configured to do so.
+pub fn root_transition_values(proofs: &[Proof]) -> [Fr; 3] {
+    [
+        proofs
+            .first()
+            .map_or(Fr::zero(), |proof| proof.claim.old_root),
+        proofs
+            .last()
+            .map_or(Fr::zero(), |proof| proof.claim.new_root),
+        Fr::from(u64::try_from(proofs.len()).unwrap()),
+    ]
+}
+
+/// Number of rows each segment of `proof` uses in the mpt update gadget. These sum to
+/// `proof.n_rows()`.
+pub fn segment_rows(proof: &Proof) -> Vec<(SegmentType, usize)> {
+    let n_account_trie_rows = proof.address_hash_traces.len();
+    let mut rows = vec![
+        (SegmentType::Start, 1),
+        (SegmentType::AccountTrie, n_account_trie_rows),
+    ];
+    if proof.old_account.is_none() && proof.new_account.is_none() {
+        return rows;
+    }
+
+    let n_account_leaf_rows = proof.n_rows() - 1 - n_account_trie_rows - proof.storage.n_rows();
+    rows.extend(
+        [
+            SegmentType::AccountLeaf0,
+            SegmentType::AccountLeaf1,
+            SegmentType::AccountLeaf2,
+            SegmentType::AccountLeaf3,
+        ]
+        .into_iter()
+        .take(n_account_leaf_rows)
+        .map(|segment_type| (segment_type, 1)),
+    );
+    if let StorageProof::Update { trie_rows, .. } = &proof.storage {
+        rows.push((SegmentType::StorageTrie, trie_rows.len()));
+        rows.push((
+            SegmentType::StorageLeaf0,
+            proof.storage.n_rows() - trie_rows.len(),
+        ));
+    }
+    rows
+}
+
 // ... the return traces: ([inp;2], domain, hash)
 pub fn hash_traces(proofs: &[Proof]) -> Vec<([Fr; 2], Fr, Fr)> {
     let mut hash_traces = vec![(
@@ -136,7 +136,21 @@ pub fn transitions(proof: MPTProofType) -> HashMap<SegmentType, Vec<SegmentType>
             ),
         ]
         .into(),
-        MPTProofType::AccountDestructed => [].into(),
+        MPTProofType::AccountDestructed => [
+            (
+                SegmentType::Start,
+                vec![
+                    SegmentType::AccountTrie,  // mpt has > 1 account
+                    SegmentType::AccountLeaf0, // mpt has 1 account
+                ],
+            ),
+            (
+                SegmentType::AccountTrie,
+                vec![SegmentType::AccountTrie, SegmentType::AccountLeaf0],
+            ),
+            (SegmentType::AccountLeaf0, vec![SegmentType::Start]),
+        ]
+        .into(),
     }
 }
 
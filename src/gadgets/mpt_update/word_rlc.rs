@@ -1,5 +1,7 @@
 use crate::{
-    constraint_builder::{AdviceColumn, ConstraintBuilder, Query, SecondPhaseAdviceColumn},
+    constraint_builder::{
+        AdviceColumn, AssignRegion, ConstraintBuilder, Query, SecondPhaseAdviceColumn,
+    },
     gadgets::{
         byte_representation::{BytesLookup, RlcLookup},
         poseidon::PoseidonLookup,
@@ -9,7 +11,7 @@ use crate::{
 };
 use ethers_core::{k256::elliptic_curve::PrimeField, types::U256};
 use halo2_proofs::{
-    circuit::{Region, Value},
+    circuit::Value,
     halo2curves::{bn256::Fr, ff::FromUniformBytes},
 };
 
@@ -62,7 +64,7 @@ pub fn configure<F: FromUniformBytes<64> + Ord>(
 }
 
 pub fn assign(
-    region: &mut Region<'_, Fr>,
+    region: &mut impl AssignRegion<Fr>,
     offset: usize,
     word: U256,
     [high_column, low_column]: [AdviceColumn; 2],
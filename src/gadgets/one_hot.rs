@@ -1,5 +1,10 @@
-use crate::constraint_builder::{BinaryColumn, BinaryQuery, ConstraintBuilder, Query};
-use halo2_proofs::{circuit::Region, halo2curves::ff::FromUniformBytes, plonk::ConstraintSystem};
+use crate::constraint_builder::{
+    AssignRegion, BinaryColumn, BinaryQuery, ConstraintBuilder, Query, WitnessTable,
+};
+use halo2_proofs::{
+    halo2curves::ff::FromUniformBytes,
+    plonk::{Advice, Column, ConstraintSystem},
+};
 use std::{cmp::Eq, collections::BTreeMap, hash::Hash};
 use strum::IntoEnumIterator;
 
@@ -30,7 +35,7 @@ impl<T: IntoEnumIterator + Hash + Eq + PartialOrd + Ord> OneHot<T> {
 
     pub fn assign<F: FromUniformBytes<64> + Ord>(
         &self,
-        region: &mut Region<'_, F>,
+        region: &mut impl AssignRegion<F>,
         offset: usize,
         value: T,
     ) {
@@ -39,6 +44,31 @@ impl<T: IntoEnumIterator + Hash + Eq + PartialOrd + Ord> OneHot<T> {
         }
     }
 
+    /// The variant assigned at `row` of `table`.
+    pub fn assigned_value<F: FromUniformBytes<64> + Ord>(
+        &self,
+        table: &WitnessTable<F>,
+        row: usize,
+    ) -> T
+    where
+        T: Copy,
+    {
+        self.columns
+            .iter()
+            .find(|(_, column)| table.advice(column.0, row) == F::ONE)
+            .map_or_else(|| T::iter().next().unwrap(), |(variant, _)| *variant)
+    }
+
+    /// The binary column of each variant except the first.
+    pub fn columns(&self) -> impl Iterator<Item = (T, Column<Advice>)> + '_
+    where
+        T: Copy,
+    {
+        self.columns
+            .iter()
+            .map(|(variant, column)| (*variant, column.0))
+    }
+
     pub fn previous_matches<F: FromUniformBytes<64> + Ord>(&self, values: &[T]) -> BinaryQuery<F> {
         self.matches(values, -1)
     }
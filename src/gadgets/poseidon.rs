@@ -1,13 +1,22 @@
+#[cfg(any(test, feature = "test-circuit"))]
+use crate::constraint_builder::AssignRegion;
 use crate::constraint_builder::{AdviceColumn, FixedColumn};
-use halo2_proofs::plonk::{Advice, Column, Fixed};
-#[cfg(any(test, feature = "bench"))]
-use halo2_proofs::{circuit::Region, halo2curves::bn256::Fr, plonk::ConstraintSystem};
-#[cfg(any(test, feature = "bench"))]
+use halo2_proofs::{
+    circuit::Layouter,
+    halo2curves::bn256::Fr,
+    plonk::{Advice, Column, ConstraintSystem, Error, Fixed},
+};
+#[cfg(any(test, feature = "test-circuit"))]
 use hash_circuit::hash::Hashable;
+use hash_circuit::hash::{PoseidonHashChip, PoseidonHashConfig, PoseidonHashTable};
 
-#[cfg(any(test, feature = "bench"))]
+#[cfg(any(test, feature = "test-circuit"))]
 const MAX_POSEIDON_ROWS: usize = 200;
 
+// Step size for variable length inputs to the poseidon sponge. The mpt circuit only looks up
+// fixed width hashes, so this does not affect which hashes can be proven.
+const HASH_BLOCK_STEP_SIZE: usize = 32;
+
 /// Lookup  represent the poseidon table in zkevm circuit
 pub trait PoseidonLookup {
     fn lookup_columns(&self) -> (FixedColumn, [AdviceColumn; 6]) {
@@ -20,7 +29,7 @@ pub trait PoseidonLookup {
     }
 }
 
-#[cfg(any(test, feature = "bench"))]
+#[cfg(any(test, feature = "test-circuit"))]
 #[derive(Clone, Copy)]
 pub struct PoseidonTable {
     q_enable: FixedColumn,
@@ -32,7 +41,7 @@ pub struct PoseidonTable {
     head_mark: AdviceColumn,
 }
 
-#[cfg(any(test, feature = "bench"))]
+#[cfg(any(test, feature = "test-circuit"))]
 impl PoseidonTable {
     pub fn configure<F: halo2_proofs::halo2curves::ff::FromUniformBytes<64> + Ord>(
         cs: &mut ConstraintSystem<F>,
@@ -50,7 +59,12 @@ impl PoseidonTable {
         }
     }
 
-    pub fn load(&self, region: &mut Region<'_, Fr>, hash_traces: &[([Fr; 2], Fr, Fr)]) {
+    /// Rows used by `load` for `n_hashes` hashes.
+    pub fn n_rows_required(n_hashes: usize) -> usize {
+        std::cmp::max(n_hashes, MAX_POSEIDON_ROWS)
+    }
+
+    pub fn load(&self, region: &mut impl AssignRegion<Fr>, hash_traces: &[([Fr; 2], Fr, Fr)]) {
         // The test poseidon table starts assigning from the first row, which has a disabled
         // selector, but this is fine because the poseidon_lookup in the ConstraintBuilder
         // doesn't include the mpt circuit's selector column.
@@ -82,7 +96,7 @@ impl PoseidonTable {
     }
 }
 
-#[cfg(any(test, feature = "bench"))]
+#[cfg(any(test, feature = "test-circuit"))]
 impl PoseidonLookup for PoseidonTable {
     fn lookup_columns(&self) -> (FixedColumn, [AdviceColumn; 6]) {
         (
@@ -98,3 +112,60 @@ impl PoseidonLookup for PoseidonTable {
         )
     }
 }
+
+/// Poseidon table whose hashes are constrained by the hash_circuit sponge.
+#[derive(Clone, Debug)]
+pub struct PoseidonConfig {
+    q_enable: Column<Fixed>,
+    table: [Column<Advice>; 6],
+    sponge: PoseidonHashConfig<Fr>,
+}
+
+impl PoseidonConfig {
+    pub fn configure(cs: &mut ConstraintSystem<Fr>) -> Self {
+        let q_enable = cs.fixed_column();
+        let table = [0; 6].map(|_| cs.advice_column());
+        let sponge = PoseidonHashConfig::configure_sub(cs, (q_enable, table), HASH_BLOCK_STEP_SIZE);
+        Self {
+            q_enable,
+            table,
+            sponge,
+        }
+    }
+
+    /// Assigns the hash traces to the table and proves them with the sponge. `max_hashes` is the
+    /// fixed capacity of the table, so it must not depend on the witness.
+    pub fn load(
+        &self,
+        layouter: &mut impl Layouter<Fr>,
+        hash_traces: &[([Fr; 2], Fr, Fr)],
+        max_hashes: usize,
+    ) -> Result<(), Error> {
+        if hash_traces.len() > max_hashes {
+            log::error!(
+                "poseidon table requires {} hashes but only has space for {max_hashes}",
+                hash_traces.len()
+            );
+            return Err(Error::Synthesis);
+        }
+        let checked_hash_traces: Vec<_> = hash_traces
+            .iter()
+            .map(|(inputs, domain, hash)| (*inputs, *domain, Some(*hash)))
+            .collect();
+        let mut data = PoseidonHashTable::default();
+        data.constant_inputs_with_check(&checked_hash_traces);
+
+        PoseidonHashChip::<_, HASH_BLOCK_STEP_SIZE>::construct(
+            self.sponge.clone(),
+            &data,
+            max_hashes,
+        )
+        .load(layouter)
+    }
+}
+
+impl PoseidonLookup for PoseidonConfig {
+    fn lookup_columns_generic(&self) -> (Column<Fixed>, [Column<Advice>; 6]) {
+        (self.q_enable, self.table)
+    }
+}
@@ -0,0 +1,412 @@
+//! Conversion of witnesses in the legacy row-per-line format (see `serde::Row`) into `SMTTrace`s.
+//!
+//! A folded group of rows describes one update of one trie. Row `i` holds the node at depth `i`
+//! on the path: its old and new hashes, the direction taken from it, and the sibling of its child
+//! on the path. The last row on each side is the leaf (or empty node) the path ends at.
+//!
+//! Only groups where the old and new paths have the same length can be converted, i.e. updates of
+//! an existing leaf, reads, and insertions into or deletions from an empty node. Groups with
+//! `leafExt` or `leafExtFinal` rows, which insert or delete a leaf below an existing leaf, are
+//! rejected with `LegacyRowError::UnsupportedHashType`: the shorter `SMTPath` of such an update
+//! ends at the existing leaf, and needs its key and value hash, but the rows only have its hash,
+//! since their `key` is the key of the updated leaf.
+use crate::{
+    serde::{AccountData, HashType, HexBytes, Row, SMTNode, SMTPath, SMTTrace, StateData},
+    types::HashDomain,
+    util::{account_key, domain_hash, storage_key_hash},
+};
+use ethers_core::types::{Address, U256};
+use halo2_proofs::halo2curves::bn256::Fr;
+use num_bigint::BigUint;
+use num_traits::{One, Zero};
+
+#[derive(Clone, Debug, PartialEq, Eq, thiserror::Error)]
+/// Legacy row group conversion errors. `index` is 0 for the old side of the rows and 1 for the
+/// new side.
+pub enum LegacyRowError {
+    #[error("row group is empty")]
+    /// no rows in the group
+    Empty,
+    #[error("row {0} is_first does not match its position in the group")]
+    /// the group is not a single output of `Row::fold_flattern_rows`
+    MisplacedFirstRow(usize),
+    #[error("path {index} does not end in a leaf or empty node")]
+    /// the group ends before the leaf row
+    Incomplete { index: usize },
+    #[error("row {row} has hash type {hash_type:?} in path {index}, which is not supported")]
+    /// leaf extensions (insertions and deletions below an existing leaf) cannot be converted,
+    /// because the rows do not have the key and value hash of the existing leaf
+    UnsupportedHashType {
+        row: usize,
+        index: usize,
+        hash_type: HashType,
+    },
+    #[error("row {0} path is not a single bit")]
+    /// path of a middle row is not 0 or 1
+    InvalidPathBit(usize),
+    #[error("row {0} has a hash that is not a canonical field element")]
+    /// hash bytes are not the little endian representation of a field element
+    NonCanonical(usize),
+    #[error("row {row} hash in path {index} is not the hash of a branch node")]
+    /// no branch node type hashes the child and sibling to the row's hash, e.g. because the
+    /// witness was generated with an older hash scheme
+    UnknownNodeType { row: usize, index: usize },
+    #[error("row {row} hash in path {index} is not the hash of its leaf")]
+    /// leaf hash does not match the key and value of the leaf row
+    InconsistentLeaf { row: usize, index: usize },
+}
+
+/// Old and new paths of a folded group of rows, which must not contain leaf extensions.
+pub fn paths_from_rows(rows: &[Row]) -> Result<[SMTPath; 2], LegacyRowError> {
+    if rows.is_empty() {
+        return Err(LegacyRowError::Empty);
+    }
+    if let Some(row) = rows
+        .iter()
+        .enumerate()
+        .position(|(i, row)| row.is_first != (i == 0))
+    {
+        return Err(LegacyRowError::MisplacedFirstRow(row));
+    }
+    Ok([path_from_rows(rows, 0)?, path_from_rows(rows, 1)?])
+}
+
+/// Trace for an account update from a folded group of account trie rows. The rows only hold the
+/// account trie path, so the account fields before and after the update, and the storage root of
+/// the account, have to be supplied separately.
+pub fn account_trace_from_rows(
+    rows: &[Row],
+    address: Address,
+    account_update: [Option<AccountData>; 2],
+    storage_root: Fr,
+) -> Result<SMTTrace, LegacyRowError> {
+    Ok(SMTTrace {
+        address: HexBytes(address.0),
+        account_key: HexBytes(account_key(address).to_bytes()),
+        account_path: paths_from_rows(rows)?,
+        account_update,
+        state_path: [None, None],
+        common_state_root: Some(HexBytes(storage_root.to_bytes())),
+        state_key: None,
+        state_update: None,
+    })
+}
+
+/// Trace for a storage update from the folded groups of account trie rows and storage trie rows
+/// for it. The account fields, which the update does not change, and the storage slot and its
+/// values before and after the update, have to be supplied separately.
+pub fn storage_trace_from_rows(
+    account_rows: &[Row],
+    storage_rows: &[Row],
+    address: Address,
+    account: AccountData,
+    key: U256,
+    [old_value, new_value]: [U256; 2],
+) -> Result<SMTTrace, LegacyRowError> {
+    let state_data = |value: U256| {
+        let [mut key_bytes, mut value_bytes] = [[0; 32]; 2];
+        key.to_big_endian(&mut key_bytes);
+        value.to_big_endian(&mut value_bytes);
+        Some(StateData {
+            key: HexBytes(key_bytes),
+            value: HexBytes(value_bytes),
+        })
+    };
+    let [old_path, new_path] = paths_from_rows(storage_rows)?;
+    Ok(SMTTrace {
+        address: HexBytes(address.0),
+        account_key: HexBytes(account_key(address).to_bytes()),
+        account_path: paths_from_rows(account_rows)?,
+        account_update: [Some(account.clone()), Some(account)],
+        state_path: [Some(old_path), Some(new_path)],
+        common_state_root: None,
+        state_key: Some(HexBytes(storage_key_hash(key).to_bytes())),
+        state_update: Some([state_data(old_value), state_data(new_value)]),
+    })
+}
+
+fn path_from_rows(rows: &[Row], index: usize) -> Result<SMTPath, LegacyRowError> {
+    let side = |row: &Row| {
+        if index == 0 {
+            (row.old_hash_type, row.old_hash, row.old_value)
+        } else {
+            (row.new_hash_type, row.new_hash, row.new_value)
+        }
+    };
+    let fr = |row: usize, bytes: HexBytes<32>| {
+        Option::from(Fr::from_bytes(&bytes.0)).ok_or(LegacyRowError::NonCanonical(row))
+    };
+
+    let mut path = vec![];
+    let mut path_part = BigUint::default();
+    for (i, row) in rows.iter().enumerate() {
+        let (hash_type, hash, value) = side(row);
+        let leaf = match hash_type {
+            HashType::Middle => {
+                let (_, child, _) = side(
+                    rows.get(i + 1)
+                        .ok_or(LegacyRowError::Incomplete { index })?,
+                );
+                let direction = if row.path.is_zero() {
+                    false
+                } else if row.path.is_one() {
+                    true
+                } else {
+                    return Err(LegacyRowError::InvalidPathBit(i));
+                };
+                if direction {
+                    path_part += BigUint::one() << i;
+                }
+
+                let [left, right] = if direction {
+                    [fr(i, row.sib)?, fr(i + 1, child)?]
+                } else {
+                    [fr(i + 1, child)?, fr(i, row.sib)?]
+                };
+                let hash = fr(i, hash)?;
+                let domain = [
+                    HashDomain::Branch0,
+                    HashDomain::Branch1,
+                    HashDomain::Branch2,
+                    HashDomain::Branch3,
+                ]
+                .into_iter()
+                .find(|domain| domain_hash(left, right, *domain) == hash)
+                .ok_or(LegacyRowError::UnknownNodeType { row: i, index })?;
+
+                path.push(SMTNode {
+                    value: child,
+                    sibling: row.sib,
+                    node_type: domain.into(),
+                });
+                continue;
+            }
+            HashType::Leaf => {
+                if domain_hash(fr(i, row.key)?, fr(i, value)?, HashDomain::Leaf) != fr(i, hash)? {
+                    return Err(LegacyRowError::InconsistentLeaf { row: i, index });
+                }
+                Some(SMTNode {
+                    value,
+                    sibling: row.key,
+                    node_type: HashDomain::Leaf.into(),
+                })
+            }
+            HashType::Empty => None,
+            HashType::Start | HashType::LeafExt | HashType::LeafExtFinal => {
+                return Err(LegacyRowError::UnsupportedHashType {
+                    row: i,
+                    index,
+                    hash_type,
+                })
+            }
+        };
+        return Ok(SMTPath {
+            root: side(&rows[0]).1,
+            leaf,
+            path,
+            path_part,
+        });
+    }
+    Err(LegacyRowError::Incomplete { index })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        types::Proof,
+        zktrie::{StateOp, WitnessGenerator},
+        MPTProofType,
+    };
+    use ethers_core::types::U256;
+
+    // Rows for an update where the old and new paths have the same shape.
+    fn rows_from_paths([old, new]: &[SMTPath; 2], key: HexBytes<32>) -> Vec<Row> {
+        let zero = HexBytes([0; 32]);
+        let leaf_hash = |path: &SMTPath| {
+            path.leaf.map_or(zero, |leaf| {
+                HexBytes(
+                    domain_hash(
+                        Fr::from_bytes(&leaf.sibling.0).unwrap(),
+                        Fr::from_bytes(&leaf.value.0).unwrap(),
+                        HashDomain::Leaf,
+                    )
+                    .to_bytes(),
+                )
+            })
+        };
+        let hash_type = |path: &SMTPath| {
+            if path.leaf.is_some() {
+                HashType::Leaf
+            } else {
+                HashType::Empty
+            }
+        };
+        let row = |depth: usize, sib, path, old_hash_type, old_hash, new_hash_type, new_hash| Row {
+            is_first: depth == 0,
+            sib,
+            depth,
+            path,
+            path_acc: BigUint::default(),
+            old_hash_type,
+            old_hash,
+            old_value: zero,
+            new_hash_type,
+            new_hash,
+            new_value: zero,
+            key,
+            new_root: new.root,
+        };
+
+        let mut rows = vec![];
+        let (mut old_hash, mut new_hash) = (old.root, new.root);
+        for (depth, (old_node, new_node)) in old.path.iter().zip(&new.path).enumerate() {
+            let direction = (&old.path_part >> depth) % 2u8;
+            rows.push(row(
+                depth,
+                old_node.sibling,
+                direction,
+                HashType::Middle,
+                old_hash,
+                HashType::Middle,
+                new_hash,
+            ));
+            (old_hash, new_hash) = (old_node.value, new_node.value);
+        }
+        let mut leaf_row = row(
+            old.path.len(),
+            zero,
+            BigUint::default(),
+            hash_type(old),
+            leaf_hash(old),
+            hash_type(new),
+            leaf_hash(new),
+        );
+        leaf_row.old_value = old.leaf.map_or(zero, |leaf| leaf.value);
+        leaf_row.new_value = new.leaf.map_or(zero, |leaf| leaf.value);
+        rows.push(leaf_row);
+        rows
+    }
+
+    fn balance_update() -> (Address, SMTTrace) {
+        let mut generator = WitnessGenerator::default();
+        for i in 1..10 {
+            generator
+                .apply(StateOp::SetBalance {
+                    address: Address::repeat_byte(i),
+                    balance: U256::one(),
+                })
+                .unwrap();
+        }
+        let address = Address::repeat_byte(3);
+        let (_, trace) = generator
+            .apply(StateOp::SetBalance {
+                address,
+                balance: U256::from(200),
+            })
+            .unwrap();
+        (address, trace)
+    }
+
+    #[test]
+    fn account_trace_round_trip() {
+        let (address, trace) = balance_update();
+        let rows = rows_from_paths(&trace.account_path, trace.account_key);
+
+        let converted =
+            account_trace_from_rows(&rows, address, trace.account_update.clone(), Fr::zero())
+                .unwrap();
+        assert_eq!(
+            serde_json::to_string(&converted).unwrap(),
+            serde_json::to_string(&trace).unwrap()
+        );
+        Proof::try_from((MPTProofType::BalanceChanged, converted))
+            .unwrap()
+            .check();
+    }
+
+    #[test]
+    fn recorded_storage_rows() {
+        // Account trie rows followed by storage trie rows for existing_storage_update.json.
+        let groups = Row::fold_flattern_rows(
+            Row::from_lines(include_str!("traces/existing_storage_update_rows.jsonl")).unwrap(),
+        );
+        assert_eq!(groups.len(), 2);
+        let [account_rows, storage_rows] = [&groups[0], &groups[1]];
+
+        let trace: SMTTrace =
+            serde_json::from_str(include_str!("traces/existing_storage_update.json")).unwrap();
+        let converted = storage_trace_from_rows(
+            account_rows,
+            storage_rows,
+            Address::repeat_byte(1),
+            trace.account_update[0].clone().unwrap(),
+            U256::from(40),
+            [U256::from(1), U256::from(20)],
+        )
+        .unwrap();
+        assert_eq!(converted, trace);
+        Proof::try_from((MPTProofType::StorageChanged, converted))
+            .unwrap()
+            .check();
+    }
+
+    #[test]
+    fn leaf_extension_rows() {
+        let (_, trace) = balance_update();
+        let mut rows = rows_from_paths(&trace.account_path, trace.account_key);
+        rows[1].new_hash_type = HashType::LeafExt;
+
+        assert_eq!(
+            paths_from_rows(&rows).unwrap_err(),
+            LegacyRowError::UnsupportedHashType {
+                row: 1,
+                index: 1,
+                hash_type: HashType::LeafExt
+            }
+        );
+    }
+
+    #[test]
+    fn incomplete_group() {
+        let (_, trace) = balance_update();
+        let mut rows = rows_from_paths(&trace.account_path, trace.account_key);
+        rows.pop();
+
+        assert_eq!(
+            paths_from_rows(&rows).unwrap_err(),
+            LegacyRowError::Incomplete { index: 0 }
+        );
+        assert_eq!(paths_from_rows(&[]).unwrap_err(), LegacyRowError::Empty);
+    }
+
+    #[test]
+    fn wrong_sibling() {
+        let (_, trace) = balance_update();
+        let mut rows = rows_from_paths(&trace.account_path, trace.account_key);
+        rows[1].sib = rows[0].sib;
+
+        assert_eq!(
+            paths_from_rows(&rows).unwrap_err(),
+            LegacyRowError::UnknownNodeType { row: 1, index: 0 }
+        );
+    }
+
+    #[test]
+    fn folded_rows() {
+        let (_, trace) = balance_update();
+        let rows = rows_from_paths(&trace.account_path, trace.account_key);
+        let n_rows = rows.len();
+
+        let groups = Row::fold_flattern_rows(
+            rows.into_iter()
+                .chain(rows_from_paths(&trace.account_path, trace.account_key))
+                .collect(),
+        );
+        assert_eq!(groups.len(), 2);
+        assert_eq!(groups[1].len(), n_rows);
+        for group in groups {
+            assert_eq!(paths_from_rows(&group).unwrap(), trace.account_path);
+        }
+    }
+}
@@ -1,22 +1,33 @@
 #![allow(clippy::too_many_arguments)]
 #![deny(unsafe_code, unused_imports)]
 
-#[cfg(any(test, feature = "bench"))]
-mod circuit;
+pub mod circuit;
 pub mod constraint_builder;
+pub mod diagnostics;
 pub mod gadgets;
+pub mod legacy;
 mod mpt_table;
 #[cfg(test)]
+mod mutation;
+#[cfg(test)]
 mod tests;
+pub mod trie_graph;
 pub mod types;
 mod util;
+pub mod validation;
+pub mod witness_dump;
+pub mod zktrie;
 
 pub mod mpt;
+pub mod planner;
+pub mod prover;
 pub mod serde;
 
+pub use circuit::{MptCircuit, StateTransitionCircuit};
 pub use gadgets::mpt_update::hash_traces;
-pub use mpt::MptCircuitConfig;
+pub use mpt::{MptCircuitConfig, MptCircuitOptions, RowUsage};
 pub use mpt_table::MPTProofType;
+pub use validation::{check_proof_type, infer_proof_types, validate, ValidationReport};
 
-#[cfg(feature = "bench")]
+#[cfg(feature = "test-circuit")]
 pub use circuit::TestCircuit;
@@ -1,31 +1,167 @@
 use crate::{
-    constraint_builder::{ConstraintBuilder, Query, SelectorColumn},
+    constraint_builder::{
+        format_constant, AssignRegion, ConstraintBuilder, ConstraintSystemDescription, Query,
+        SelectorColumn, WitnessTable,
+    },
+    diagnostics::{Diagnostics, UpdateRegion},
     gadgets::{
         byte_bit::ByteBitGadget,
         byte_representation::ByteRepresentationConfig,
         canonical_representation::CanonicalRepresentationConfig,
         key_bit::KeyBitConfig,
         mpt_update::{
-            byte_representations, key_bit_lookups, mpt_update_keys, MptUpdateConfig,
-            MptUpdateLookup,
+            byte_representations, key_bit_lookups, mpt_update_keys, root_transition_values,
+            segment_rows, MptUpdateConfig, MptUpdateLookup, SegmentType,
         },
         poseidon::PoseidonLookup,
         rlc_randomness::RlcRandomness,
     },
     mpt_table::MPTProofType,
     types::Proof,
+    witness_dump::{WitnessDump, WitnessDumpRow},
 };
 use halo2_proofs::{
-    circuit::Layouter,
+    circuit::{Layouter, Region, Value},
     halo2curves::{bn256::Fr, ff::FromUniformBytes},
-    plonk::{Challenge, ConstraintSystem, Error, Expression, VirtualCells},
+    plonk::{Challenge, Column, ConstraintSystem, Error, Expression, Instance, VirtualCells},
 };
 use itertools::Itertools;
-use std::time::Instant;
+use std::{
+    collections::BTreeMap,
+    fmt::{Display, Formatter},
+    time::Instant,
+};
+
+/// Optional features of MptCircuitConfig. The default is what's needed when the mpt circuit is
+/// part of a larger circuit that looks up its updates.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct MptCircuitOptions {
+    /// Constrain the old root of the first update and the new root of the last update to be the
+    /// first two cells of an instance column.
+    pub expose_roots: bool,
+    /// Also constrain the number of updates to be the third cell of the instance column. Requires
+    /// `expose_roots`.
+    pub expose_update_count: bool,
+    /// Constrain the old root of each update to be the new root of the update before it, so the
+    /// updates prove a single state transition instead of independent claims about states.
+    pub chain_roots: bool,
+}
+
+impl MptCircuitOptions {
+    /// The instance column values for a circuit with these options, assigned `proofs`.
+    pub fn instance(&self, proofs: &[Proof]) -> Vec<Fr> {
+        let n_instances = match (self.expose_roots, self.expose_update_count) {
+            (false, _) => 0,
+            (true, false) => 2,
+            (true, true) => 3,
+        };
+        root_transition_values(proofs)[..n_instances].to_vec()
+    }
+}
+
+/// The gadgets in MptCircuitConfig that use a number of rows that depends on the proofs assigned.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Gadget {
+    MptUpdate,
+    CanonicalRepresentation,
+    KeyBit,
+    ByteRepresentation,
+    ByteBit,
+}
+
+impl Display for Gadget {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::MptUpdate => write!(f, "mpt_update"),
+            Self::CanonicalRepresentation => write!(f, "canonical_representation"),
+            Self::KeyBit => write!(f, "key_bit"),
+            Self::ByteRepresentation => write!(f, "byte_representation"),
+            Self::ByteBit => write!(f, "byte_bit"),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, thiserror::Error)]
+#[error("{gadget} requires {required} rows, but only {available} rows are available")]
+/// The proofs assigned to MptCircuitConfig do not fit in the rows available.
+pub struct CapacityError {
+    pub gadget: Gadget,
+    pub required: usize,
+    pub available: usize,
+}
+
+#[derive(Debug, thiserror::Error)]
+/// MptCircuitConfig assignment errors.
+pub enum AssignError {
+    #[error(transparent)]
+    /// not enough rows for the proofs
+    Capacity(#[from] CapacityError),
+    #[error(transparent)]
+    /// error from the layouter
+    Synthesis(#[from] Error),
+}
+
+/// halo2 errors have no room for the gadget that ran out of rows, so it is logged. Use
+/// `MptCircuitConfig::check_capacity` before synthesis to get it as a `CapacityError`.
+impl From<AssignError> for Error {
+    fn from(error: AssignError) -> Self {
+        match error {
+            AssignError::Capacity(error) => {
+                log::error!("{error}");
+                Error::Synthesis
+            }
+            AssignError::Synthesis(error) => error,
+        }
+    }
+}
+
+/// Breakdown of the rows MptCircuitConfig needs to assign a set of proofs.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RowUsage {
+    /// Rows required by each gadget, including the initial all-zero row and final padding row.
+    pub gadgets: [(Gadget, usize); 5],
+    /// Proof type of each proof and the number of mpt update rows it uses, in assignment order.
+    pub proofs: Vec<(MPTProofType, usize)>,
+    /// Total mpt update rows used by the proofs of each proof type.
+    pub proof_types: BTreeMap<MPTProofType, usize>,
+    /// Total mpt update rows used by each segment type, over all proofs.
+    pub segment_types: BTreeMap<SegmentType, usize>,
+}
+
+impl RowUsage {
+    /// The number of rows required, i.e. the rows of `MptCircuitConfig::bottleneck`.
+    pub fn n_rows(&self) -> usize {
+        self.bottleneck_gadget().1
+    }
+
+    /// The gadget that requires the most rows.
+    pub fn bottleneck(&self) -> Gadget {
+        self.bottleneck_gadget().0
+    }
+
+    fn bottleneck_gadget(&self) -> (Gadget, usize) {
+        *self
+            .gadgets
+            .iter()
+            .max_by_key(|(_, n_rows)| n_rows)
+            .unwrap()
+    }
+
+    /// The smallest k for which a circuit with constraint system `cs` and 2^k rows has enough
+    /// usable rows, i.e. rows that are not reserved for blinding factors, for both the mpt circuit
+    /// and the `poseidon_rows` rows of the poseidon table or circuit that it looks hashes up in.
+    pub fn min_k(&self, cs: &ConstraintSystem<Fr>, poseidon_rows: usize) -> u32 {
+        let usable_rows = std::cmp::max(self.n_rows(), poseidon_rows);
+        let n_rows = std::cmp::max(usable_rows + cs.blinding_factors() + 1, cs.minimum_rows());
+        n_rows.next_power_of_two().trailing_zeros()
+    }
+}
 
 /// Config for MptCircuit
 #[derive(Clone)]
 pub struct MptCircuitConfig {
+    options: MptCircuitOptions,
+    instance: Option<Column<Instance>>,
     selector: SelectorColumn,
     is_final_row: SelectorColumn,
     rlc_randomness: RlcRandomness,
@@ -42,6 +178,62 @@ impl MptCircuitConfig {
         evm_word_challenge: Challenge,
         poseidon: &impl PoseidonLookup,
     ) -> Self {
+        Self::configure_with_options(
+            cs,
+            evm_word_challenge,
+            poseidon,
+            MptCircuitOptions::default(),
+        )
+    }
+
+    pub fn configure_with_options(
+        cs: &mut ConstraintSystem<Fr>,
+        evm_word_challenge: Challenge,
+        poseidon: &impl PoseidonLookup,
+        options: MptCircuitOptions,
+    ) -> Self {
+        let (config, cb) = Self::configure_builder(cs, evm_word_challenge, poseidon, options);
+        cb.build(cs);
+        config
+    }
+
+    /// Configures like `configure_with_options`, and also describes the columns, constraints, and
+    /// lookups of the constraint system, e.g. to export them for an audit.
+    pub fn configure_with_description(
+        cs: &mut ConstraintSystem<Fr>,
+        evm_word_challenge: Challenge,
+        poseidon: &impl PoseidonLookup,
+        options: MptCircuitOptions,
+    ) -> (Self, ConstraintSystemDescription) {
+        let (config, cb) = Self::configure_builder(cs, evm_word_challenge, poseidon, options);
+        let description = cb.description(cs);
+        cb.build(cs);
+        (config, description)
+    }
+
+    /// Configures like `configure_with_options`, and also returns the constraints, so that a
+    /// `WitnessTable` filled by `assign_native` can be checked with `ConstraintBuilder::evaluate`.
+    pub fn configure_with_constraints(
+        cs: &mut ConstraintSystem<Fr>,
+        evm_word_challenge: Challenge,
+        poseidon: &impl PoseidonLookup,
+        options: MptCircuitOptions,
+    ) -> (Self, ConstraintBuilder<Fr>) {
+        let (config, cb) = Self::configure_builder(cs, evm_word_challenge, poseidon, options);
+        cb.build(cs);
+        (config, cb)
+    }
+
+    fn configure_builder(
+        cs: &mut ConstraintSystem<Fr>,
+        evm_word_challenge: Challenge,
+        poseidon: &impl PoseidonLookup,
+        options: MptCircuitOptions,
+    ) -> (Self, ConstraintBuilder<Fr>) {
+        assert!(
+            options.expose_roots || !options.expose_update_count,
+            "expose_update_count requires expose_roots"
+        );
         let selector = SelectorColumn(cs.fixed_column());
         let rlc_randomness = RlcRandomness(evm_word_challenge);
         let mut cb = ConstraintBuilder::new(selector);
@@ -60,7 +252,7 @@ impl MptCircuitConfig {
             &byte_bit,
         );
 
-        let mpt_update = MptUpdateConfig::configure(
+        let mut mpt_update = MptUpdateConfig::configure(
             cs,
             &mut cb,
             poseidon,
@@ -97,9 +289,21 @@ impl MptCircuitConfig {
             }
         });
 
-        cb.build(cs);
+        if options.expose_roots || options.chain_roots {
+            mpt_update.configure_root_transitions(cs, &mut cb, selector, is_final_row);
+        }
+        if options.chain_roots {
+            mpt_update.configure_root_chaining(&mut cb, selector);
+        }
+        let instance = options.expose_roots.then(|| {
+            let instance = cs.instance_column();
+            cs.enable_equality(instance);
+            instance
+        });
 
-        Self {
+        let config = Self {
+            options,
+            instance,
             selector,
             is_final_row,
             rlc_randomness,
@@ -108,58 +312,214 @@ impl MptCircuitConfig {
             byte_bit,
             canonical_representation,
             byte_representation,
-        }
+        };
+        (config, cb)
     }
 
+    /// Assigns `proofs`, and returns the regions of the mpt update rows, for `diagnostics`.
     pub fn assign(
         &self,
         layouter: &mut impl Layouter<Fr>,
         proofs: &[Proof],
         n_rows: usize,
-    ) -> Result<(), Error> {
+    ) -> Result<Vec<UpdateRegion>, AssignError> {
+        self.assign_with(layouter, proofs, n_rows, use_par(), |_| {})
+    }
+
+    /// Assigns sequentially, and then calls `mutate` on the mpt update region, which starts at the
+    /// first row of the circuit. Soundness tests use this to overwrite cells of a valid witness.
+    #[cfg(test)]
+    pub fn assign_mutated(
+        &self,
+        layouter: &mut impl Layouter<Fr>,
+        proofs: &[Proof],
+        n_rows: usize,
+        mutate: impl Fn(&mut Region<'_, Fr>),
+    ) -> Result<Vec<UpdateRegion>, AssignError> {
+        self.assign_with(layouter, proofs, n_rows, false, mutate)
+    }
+
+    /// Like `assign`, but always in parallel, whatever `PARALLEL_SYN` is.
+    #[cfg(test)]
+    pub fn assign_par(
+        &self,
+        layouter: &mut impl Layouter<Fr>,
+        proofs: &[Proof],
+        n_rows: usize,
+    ) -> Result<Vec<UpdateRegion>, AssignError> {
+        self.assign_with(layouter, proofs, n_rows, true, |_| {})
+    }
+
+    /// Assigns `proofs` to `table` like the sequential `assign`, but without a halo2 layouter.
+    /// `randomness` is the value of the rlc challenge.
+    pub fn assign_native(
+        &self,
+        table: &mut WitnessTable<Fr>,
+        proofs: &[Proof],
+        n_rows: usize,
+        randomness: Fr,
+    ) -> Result<(), CapacityError> {
+        Self::check_capacity(proofs, n_rows)?;
+        let randomness = Value::known(randomness);
+
+        self.assign_mpt_update(table, proofs, n_rows, randomness);
+        let mut keys = mpt_update_keys(proofs);
+        keys.sort();
+        keys.dedup();
+        self.assign_keys(table, proofs, &keys, n_rows, randomness, false);
+
+        Ok(())
+    }
+
+    /// Records which update, segment, and path own each row of the mpt update gadget, by running
+    /// its assignment for `proofs` into a `WitnessTable`. `regions` are the regions that `assign`
+    /// returned, which map MockProver failures in them to rows. Parallel and sequential assignment
+    /// use the same rows, but not the same regions. Native failures do not need any regions.
+    pub fn diagnostics(&self, proofs: &[Proof], regions: &[UpdateRegion]) -> Diagnostics {
+        let n_rows = MptUpdateConfig::n_rows_required(proofs);
+        let mut table = WitnessTable::new(n_rows);
+        // The rlc randomness does not affect the segment and path types.
+        self.mpt_update.assign(&mut table, proofs, Value::unknown());
+        let rows: Vec<_> = (0..n_rows)
+            .map(|row| self.mpt_update.assigned_row(&table, row))
+            .collect();
+        Diagnostics::new(proofs, &rows, regions)
+    }
+
+    /// Assigns `proofs` like `assign_native`, and returns the values of the mpt_update, key_bit,
+    /// canonical_representation, and byte_representation columns, e.g. to write to a file.
+    pub fn witness_dump(
+        &self,
+        proofs: &[Proof],
+        n_rows: usize,
+        randomness: Fr,
+    ) -> Result<WitnessDump, CapacityError> {
+        let mut table = WitnessTable::new(n_rows);
+        self.assign_native(&mut table, proofs, n_rows, randomness)?;
+
+        let columns: Vec<_> = [
+            ("mpt_update", self.mpt_update.columns()),
+            ("key_bit", self.key_bit.columns()),
+            (
+                "canonical_representation",
+                self.canonical_representation.columns(),
+            ),
+            ("byte_representation", self.byte_representation.columns()),
+        ]
+        .into_iter()
+        .flat_map(|(gadget, columns)| {
+            columns
+                .into_iter()
+                .map(move |(name, column)| (format!("{gadget}.{name}"), column))
+        })
+        .collect();
+        let rows = (0..n_rows)
+            .map(|row| {
+                let (segment_type, path_type, _) = self.mpt_update.assigned_row(&table, row);
+                WitnessDumpRow {
+                    row,
+                    proof_type: format!("{:?}", self.mpt_update.assigned_proof_type(&table, row)),
+                    segment_type: format!("{segment_type:?}"),
+                    path_type: format!("{path_type:?}"),
+                    values: columns
+                        .iter()
+                        .map(|(_, column)| format_constant(&table.cell(*column, row)))
+                        .collect(),
+                }
+            })
+            .collect();
+        Ok(WitnessDump {
+            columns: columns.into_iter().map(|(name, _)| name).collect(),
+            rows,
+        })
+    }
+
+    #[cfg(test)]
+    pub fn mpt_update(&self) -> &MptUpdateConfig {
+        &self.mpt_update
+    }
+
+    // `mutate` is only called if `use_par` is false.
+    fn assign_with(
+        &self,
+        layouter: &mut impl Layouter<Fr>,
+        proofs: &[Proof],
+        n_rows: usize,
+        use_par: bool,
+        mutate: impl Fn(&mut Region<'_, Fr>),
+    ) -> Result<Vec<UpdateRegion>, AssignError> {
+        Self::check_capacity(proofs, n_rows)?;
+
         let randomness = self.rlc_randomness.value(layouter);
-        let (u32s, u64s, u128s, frs) = byte_representations(proofs);
 
         let mpt_updates_assign_dur = Instant::now();
-        let use_par = std::env::var("PARALLEL_SYN").map_or(true, |s| s == *"true");
-        if use_par {
-            let n_assigned_rows = self.mpt_update.assign_par(layouter, proofs, randomness);
+        // The all-zero row and the padding rows have an old_hash of 0, for `region_index`.
+        let (final_cells, regions) = if use_par {
+            let mut regions = self.mpt_update.assign_par(layouter, proofs, randomness);
+            let n_assigned_rows = proofs.iter().map(Proof::n_rows).sum::<usize>();
 
-            layouter.assign_region(
+            let (final_cells, padding_region) = layouter.assign_region(
                 || "mpt update padding rows",
                 |mut region| {
-                    if n_assigned_rows == 0 {
+                    let (first_row, padding_offsets) = if n_assigned_rows == 0 {
                         // first row is all-zeroes row
-                        for offset in 1..n_rows {
-                            self.mpt_update.assign_padding_row(&mut region, offset);
-                        }
+                        (0, 1..n_rows)
                     } else {
-                        for offset in 0..(n_rows - (1 + n_assigned_rows)) {
-                            self.mpt_update.assign_padding_row(&mut region, offset);
-                        }
+                        (1 + n_assigned_rows, 0..(n_rows - (1 + n_assigned_rows)))
+                    };
+                    let padding_region = UpdateRegion {
+                        index: self.mpt_update.region_index(&mut region, 0, Fr::zero()),
+                        proofs: proofs.len()..proofs.len(),
+                        first_row,
+                    };
+                    let mut final_cells = None;
+                    for offset in padding_offsets {
+                        self.mpt_update.assign_padding_row(&mut region, offset);
+                        final_cells = self.mpt_update.assign_padding_root_transitions(
+                            &mut region,
+                            offset,
+                            proofs,
+                        );
                     }
-                    Ok(())
+                    Ok((final_cells, padding_region))
                 },
             )?;
+            regions.push(padding_region);
+            (final_cells, regions)
         } else {
             layouter.assign_region(
                 || "mpt update",
                 |mut region| {
-                    let n_assigned_rows = self.mpt_update.assign(&mut region, proofs, randomness);
-
-                    assert!(
-                        2 + n_assigned_rows <= n_rows,
-                        "mpt circuit requires {n_assigned_rows} rows for mpt updates + 1 initial \
-                    all-zero row + at least 1 final padding row. Only {n_rows} rows available."
+                    self.assign_mpt_update(&mut region, proofs, n_rows, randomness);
+                    let update_region = UpdateRegion {
+                        index: self.mpt_update.region_index(&mut region, 0, Fr::zero()),
+                        proofs: 0..proofs.len(),
+                        first_row: 0,
+                    };
+                    // `AssignRegion` does not return cells, so the root transitions of the final
+                    // padding row, which has the instance cells, are assigned again on the region.
+                    let final_cells = self.mpt_update.assign_padding_root_transitions(
+                        &mut region,
+                        n_rows - 1,
+                        proofs,
                     );
+                    mutate(&mut region);
 
-                    for offset in (1 + n_assigned_rows)..n_rows {
-                        self.mpt_update.assign_padding_row(&mut region, offset);
-                    }
-
-                    Ok(())
+                    Ok((final_cells, vec![update_region]))
                 },
-            )?;
+            )?
+        };
+        if let Some(instance) = self.instance {
+            // The instance cells are on the final padding row, which check_capacity requires.
+            let final_cells = final_cells.ok_or(CapacityError {
+                gadget: Gadget::MptUpdate,
+                required: 1 + MptUpdateConfig::n_rows_required(proofs),
+                available: n_rows,
+            })?;
+            let n_instances = self.options.instance(proofs).len();
+            for (row, cell) in final_cells.iter().take(n_instances).enumerate() {
+                layouter.constrain_instance(cell.cell(), instance, row)?;
+            }
         }
         log::debug!(
             "mpt updates assignment(use_par = {}) took {:?}",
@@ -186,12 +546,6 @@ impl MptCircuitConfig {
             keys.dedup();
             (keys, dur.elapsed())
         };
-        let total_rep_size = n_rows / 32 - 1;
-        assert!(
-            total_rep_size >= keys.len(),
-            "no enough space for canonical representation of all keys (need {})",
-            keys.len()
-        );
         log::debug!("get keys took {:?}", get_keys_time);
 
         if use_par {
@@ -207,50 +561,76 @@ impl MptCircuitConfig {
         layouter.assign_region(
             || "mpt keys",
             |mut region| {
-                for offset in 1..n_rows {
-                    self.selector.enable(&mut region, offset);
-                }
+                self.assign_keys(&mut region, proofs, &keys, n_rows, randomness, use_par);
+                Ok(())
+            },
+        )?;
+        Ok(regions)
+    }
 
-                let keys_assign_dur = Instant::now();
-                if !use_par {
-                    self.canonical_representation
-                        .assign(&mut region, randomness, &keys, n_rows);
-                    self.key_bit.assign(&mut region, &key_bit_lookups(proofs));
-                }
+    /// Assigns the mpt update rows for `proofs`, and the padding rows after them up to `n_rows`.
+    fn assign_mpt_update(
+        &self,
+        region: &mut impl AssignRegion<Fr>,
+        proofs: &[Proof],
+        n_rows: usize,
+        randomness: Value<Fr>,
+    ) {
+        let n_assigned_rows = self.mpt_update.assign(region, proofs, randomness);
+        for offset in (1 + n_assigned_rows)..n_rows {
+            self.mpt_update.assign_padding_row(region, offset);
+            self.mpt_update
+                .assign_padding_root_transition_values(region, offset, proofs);
+        }
+    }
 
-                let byte_bit_time = {
-                    let dur = Instant::now();
-                    self.byte_bit.assign(&mut region);
-                    dur.elapsed()
-                };
-                let byte_repr_time = {
-                    let dur = Instant::now();
-                    self.byte_representation.assign(
-                        &mut region,
-                        &u32s,
-                        &u64s,
-                        &u128s,
-                        &frs,
-                        randomness,
-                    );
-                    dur.elapsed()
-                };
-                let keys_assign_time = keys_assign_dur.elapsed();
-                log::debug!("keys assignment took {:?}", keys_assign_time);
-                log::debug!(
-                    "byte_bit: {}",
-                    byte_bit_time.as_micros() as f64 / keys_assign_time.as_micros() as f64
-                );
-                log::debug!(
-                    "byte_repr: {}",
-                    byte_repr_time.as_micros() as f64 / keys_assign_time.as_micros() as f64
-                );
-
-                self.is_final_row.enable(&mut region, n_rows - 1);
+    /// Assigns the selectors and the lookup tables of the mpt update rows, except for the
+    /// canonical_representation and key_bit tables if `use_par`, since those are then assigned in
+    /// regions of their own.
+    fn assign_keys(
+        &self,
+        region: &mut impl AssignRegion<Fr>,
+        proofs: &[Proof],
+        keys: &[Fr],
+        n_rows: usize,
+        randomness: Value<Fr>,
+        use_par: bool,
+    ) {
+        for offset in 1..n_rows {
+            self.selector.enable(region, offset);
+        }
 
-                Ok(())
-            },
-        )
+        let keys_assign_dur = Instant::now();
+        if !use_par {
+            self.canonical_representation
+                .assign(region, randomness, keys, n_rows);
+            self.key_bit.assign(region, &key_bit_lookups(proofs));
+        }
+
+        let byte_bit_time = {
+            let dur = Instant::now();
+            self.byte_bit.assign(region);
+            dur.elapsed()
+        };
+        let byte_repr_time = {
+            let dur = Instant::now();
+            let (u32s, u64s, u128s, frs) = byte_representations(proofs);
+            self.byte_representation
+                .assign(region, &u32s, &u64s, &u128s, &frs, randomness);
+            dur.elapsed()
+        };
+        let keys_assign_time = keys_assign_dur.elapsed();
+        log::debug!("keys assignment took {:?}", keys_assign_time);
+        log::debug!(
+            "byte_bit: {}",
+            byte_bit_time.as_micros() as f64 / keys_assign_time.as_micros() as f64
+        );
+        log::debug!(
+            "byte_repr: {}",
+            byte_repr_time.as_micros() as f64 / keys_assign_time.as_micros() as f64
+        );
+
+        self.is_final_row.enable(region, n_rows - 1);
     }
 
     pub fn lookup_exprs<F: FromUniformBytes<64> + Ord>(
@@ -265,21 +645,88 @@ impl MptCircuitConfig {
             .unwrap()
     }
 
-    /// The number of minimum number of rows required for the mpt circuit.
+    /// The minimum number of rows required for the mpt circuit, i.e. the smallest `n_rows` that
+    /// `check_capacity` accepts.
     pub fn n_rows_required(proofs: &[Proof]) -> usize {
+        Self::bottleneck(proofs).1
+    }
+
+    /// The gadget that requires the most rows for `proofs`, and the number of rows it requires,
+    /// i.e. the smallest `n_rows` that `check_capacity` accepts.
+    pub fn bottleneck(proofs: &[Proof]) -> (Gadget, usize) {
+        Self::gadget_rows_required(proofs)
+            .into_iter()
+            .max_by_key(|(_, required)| *required)
+            .unwrap()
+    }
+
+    /// The rows required for `proofs`, by gadget, proof type, and segment type.
+    pub fn row_usage(proofs: &[Proof]) -> RowUsage {
+        let mut proof_types = BTreeMap::new();
+        let mut segment_types = BTreeMap::new();
+        for proof in proofs {
+            *proof_types
+                .entry(MPTProofType::from(proof.claim))
+                .or_default() += proof.n_rows();
+            for (segment_type, n_rows) in segment_rows(proof) {
+                *segment_types.entry(segment_type).or_default() += n_rows;
+            }
+        }
+        RowUsage {
+            gadgets: Self::gadget_rows_required(proofs),
+            proofs: proofs
+                .iter()
+                .map(|proof| (MPTProofType::from(proof.claim), proof.n_rows()))
+                .collect(),
+            proof_types,
+            segment_types,
+        }
+    }
+
+    /// Checks that every gadget fits in `n_rows` rows when `proofs` are assigned.
+    pub fn check_capacity(proofs: &[Proof], n_rows: usize) -> Result<(), CapacityError> {
+        for (gadget, required) in Self::gadget_rows_required(proofs) {
+            if required > n_rows {
+                return Err(CapacityError {
+                    gadget,
+                    required,
+                    available: n_rows,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    fn gadget_rows_required(proofs: &[Proof]) -> [(Gadget, usize); 5] {
         let (u32s, u64s, u128s, frs) = byte_representations(proofs);
+        let mut keys = mpt_update_keys(proofs);
+        keys.sort();
+        keys.dedup();
 
-        // +1 for the final padding row to satisfy the "final mpt update is padding" constraint.
-        1 + *[
-            MptUpdateConfig::n_rows_required(proofs),
-            CanonicalRepresentationConfig::n_rows_required(&mpt_update_keys(proofs)),
-            KeyBitConfig::n_rows_required(&key_bit_lookups(proofs)),
+        [
+            // +1 for the final padding row to satisfy the "final mpt update is padding" constraint.
+            (
+                Gadget::MptUpdate,
+                1 + MptUpdateConfig::n_rows_required(proofs),
+            ),
+            // Assignment pads the keys with one extra value, and each value uses 32 rows.
+            (Gadget::CanonicalRepresentation, 32 * (keys.len() + 1)),
+            (
+                Gadget::KeyBit,
+                KeyBitConfig::n_rows_required(&key_bit_lookups(proofs)),
+            ),
             // TODO: move rlc lookup for frs into CanonicalRepresentationConfig.
-            ByteRepresentationConfig::n_rows_required(&u32s, &u64s, &u128s, &frs),
-            ByteBitGadget::n_rows_required(),
+            (
+                Gadget::ByteRepresentation,
+                ByteRepresentationConfig::n_rows_required(&u32s, &u64s, &u128s, &frs),
+            ),
+            (Gadget::ByteBit, ByteBitGadget::n_rows_required()),
         ]
-        .iter()
-        .max()
-        .unwrap()
     }
 }
+
+// Whether `assign` uses parallel synthesis, which is the default unless `PARALLEL_SYN` is set to
+// something other than "true".
+fn use_par() -> bool {
+    std::env::var("PARALLEL_SYN").map_or(true, |s| s == *"true")
+}
@@ -44,6 +44,7 @@ impl From<ClaimKind> for MPTProofType {
             ClaimKind::Storage { .. } => MPTProofType::StorageChanged,
             ClaimKind::IsEmpty(None) => MPTProofType::AccountDoesNotExist,
             ClaimKind::IsEmpty(Some(_)) => MPTProofType::StorageDoesNotExist,
+            ClaimKind::AccountDestructed => MPTProofType::AccountDestructed,
         }
     }
 }
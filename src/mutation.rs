@@ -0,0 +1,839 @@
+//! Soundness tests that perturb cells of a valid mpt update assignment, and check that the
+//! MockProver rejects every perturbation. A mutation that survives, i.e. still satisfies the
+//! circuit, points at a cell that is not constrained enough.
+//!
+//! The same mutations also serve as the negative tests for `constraint_audit`, which evaluates
+//! them natively to find the constraints that none of them depend on.
+use crate::{
+    constraint_builder::{ConstraintBuilder, NativeFailure, WitnessTable},
+    diagnostics::UpdateRegion,
+    gadgets::{
+        mpt_update::{PathType, SegmentType},
+        one_hot::OneHot,
+        poseidon::PoseidonTable,
+    },
+    hash_traces,
+    serde::SMTTrace,
+    types::Proof,
+    MPTProofType, MptCircuitConfig, MptCircuitOptions,
+};
+use halo2_proofs::{
+    circuit::{Layouter, SimpleFloorPlanner, Value},
+    dev::{CellValue, MockProver, VerifyFailure},
+    halo2curves::bn256::Fr,
+    plonk::{Advice, Circuit, Column, ConstraintSystem, Error, FirstPhase},
+};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt::{Debug, Display, Formatter},
+    ops::Range,
+    sync::{Arc, Mutex},
+};
+use strum::IntoEnumIterator;
+
+const N_ROWS: usize = 8 * 256 + 1;
+const K: u32 = 12;
+
+/// Cells to overwrite in the mpt update region, which all belong to `column` at `row`.
+#[derive(Clone, Debug)]
+struct Mutation {
+    column: &'static str,
+    row: usize,
+    description: String,
+    cells: Vec<(Column<Advice>, Fr)>,
+}
+
+impl Mutation {
+    /// A copy of `table` with the cells overwritten, for `ConstraintBuilder::evaluate`.
+    fn apply(&self, table: &WitnessTable<Fr>) -> WitnessTable<Fr> {
+        let mut mutated = table.clone();
+        for (column, value) in &self.cells {
+            mutated.set_advice(*column, self.row, *value);
+        }
+        mutated
+    }
+}
+
+impl Display for Mutation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}[{}]: {}", self.column, self.row, self.description)
+    }
+}
+
+#[derive(Clone, Default)]
+struct MutationCircuit {
+    proofs: Vec<Proof>,
+    mutation: Option<Mutation>,
+    // The regions of the mpt update rows, from the last `synthesize`.
+    regions: Arc<Mutex<Vec<UpdateRegion>>>,
+}
+
+impl Circuit<Fr> for MutationCircuit {
+    type Config = (PoseidonTable, MptCircuitConfig);
+    type FloorPlanner = SimpleFloorPlanner;
+
+    fn without_witnesses(&self) -> Self {
+        Self::default()
+    }
+
+    fn configure(cs: &mut ConstraintSystem<Fr>) -> Self::Config {
+        let poseidon = PoseidonTable::configure(cs);
+        let challenge = cs.challenge_usable_after(FirstPhase);
+        let mpt_circuit_config = MptCircuitConfig::configure(cs, challenge, &poseidon);
+        (poseidon, mpt_circuit_config)
+    }
+
+    fn synthesize(
+        &self,
+        config: Self::Config,
+        mut layouter: impl Layouter<Fr>,
+    ) -> Result<(), Error> {
+        let (poseidon, mpt_circuit_config) = config;
+        let regions =
+            mpt_circuit_config.assign_mutated(&mut layouter, &self.proofs, N_ROWS, |region| {
+                if let Some(mutation) = &self.mutation {
+                    for (column, value) in &mutation.cells {
+                        region
+                            .assign_advice(
+                                || "mutation",
+                                *column,
+                                mutation.row,
+                                || Value::known(*value),
+                            )
+                            .unwrap();
+                    }
+                }
+            })?;
+        *self.regions.lock().unwrap() = regions;
+        layouter.assign_region(
+            || "load poseidon table",
+            |mut region| {
+                poseidon.load(&mut region, &hash_traces(&self.proofs));
+                Ok(())
+            },
+        )
+    }
+}
+
+/// Assigns `proofs` in parallel, but only loads the poseidon hashes of the first one, so that the
+/// hash lookups of the others fail in their own regions.
+#[derive(Clone, Default)]
+struct ParallelCircuit {
+    proofs: Vec<Proof>,
+    regions: Arc<Mutex<Vec<UpdateRegion>>>,
+}
+
+impl Circuit<Fr> for ParallelCircuit {
+    type Config = (PoseidonTable, MptCircuitConfig);
+    type FloorPlanner = SimpleFloorPlanner;
+
+    fn without_witnesses(&self) -> Self {
+        Self::default()
+    }
+
+    fn configure(cs: &mut ConstraintSystem<Fr>) -> Self::Config {
+        MutationCircuit::configure(cs)
+    }
+
+    fn synthesize(
+        &self,
+        config: Self::Config,
+        mut layouter: impl Layouter<Fr>,
+    ) -> Result<(), Error> {
+        let (poseidon, mpt_circuit_config) = config;
+        let regions = mpt_circuit_config.assign_par(&mut layouter, &self.proofs, N_ROWS)?;
+        *self.regions.lock().unwrap() = regions;
+        layouter.assign_region(
+            || "load poseidon table",
+            |mut region| {
+                poseidon.load(
+                    &mut region,
+                    &hash_traces(&self.proofs[..self.proofs.len().min(1)]),
+                );
+                Ok(())
+            },
+        )
+    }
+}
+
+/// A valid witness and its assignment, to derive mutations from.
+struct Harness {
+    proofs: Vec<Proof>,
+    config: MptCircuitConfig,
+    prover: MockProver<Fr>,
+}
+
+impl Harness {
+    fn new(witness: Vec<(MPTProofType, SMTTrace)>) -> Self {
+        let proofs: Vec<_> = witness
+            .into_iter()
+            .map(|trace| Proof::try_from(trace).unwrap())
+            .collect();
+        let circuit = MutationCircuit {
+            proofs: proofs.clone(),
+            ..Default::default()
+        };
+        let prover = MockProver::run(K, &circuit, vec![]).unwrap();
+        assert_eq!(prover.verify(), Ok(()), "unmutated witness is not valid");
+
+        let (_, config) = MutationCircuit::configure(&mut ConstraintSystem::default());
+        Self {
+            proofs,
+            config,
+            prover,
+        }
+    }
+
+    /// Rows assigned to the updates. Row 0 is the all-zero row, and the rows after the updates
+    /// are padding.
+    fn update_rows(&self) -> Range<usize> {
+        1..1 + self.proofs.iter().map(Proof::n_rows).sum::<usize>()
+    }
+
+    fn value(&self, column: Column<Advice>, row: usize) -> Fr {
+        match self.prover.advice()[column.index()][row] {
+            CellValue::Assigned(value) => value,
+            _ => Fr::zero(),
+        }
+    }
+
+    fn one_hot_value<T: IntoEnumIterator + Copy + Ord + std::hash::Hash>(
+        &self,
+        one_hot: &OneHot<T>,
+        row: usize,
+    ) -> T {
+        one_hot
+            .columns()
+            .find(|(_, column)| self.value(*column, row) == Fr::one())
+            .map_or_else(|| T::iter().next().unwrap(), |(variant, _)| variant)
+    }
+
+    fn segment_type(&self, row: usize) -> SegmentType {
+        self.one_hot_value(self.config.mpt_update().segment_type(), row)
+    }
+
+    fn path_type(&self, row: usize) -> PathType {
+        self.one_hot_value(self.config.mpt_update().path_type(), row)
+    }
+
+    /// Flips cells that are 0 or 1, and adds 1 to all other cells.
+    fn value_mutations(&self, column: &'static str, rows: &[usize]) -> Vec<Mutation> {
+        let (_, advice_column) = self
+            .config
+            .mpt_update()
+            .named_columns()
+            .into_iter()
+            .find(|(name, _)| *name == column)
+            .unwrap();
+        rows.iter()
+            .map(|&row| {
+                let value = self.value(advice_column, row);
+                let (description, mutated) = if value == Fr::zero() || value == Fr::one() {
+                    ("flip bit", Fr::one() - value)
+                } else {
+                    ("add 1", value + Fr::one())
+                };
+                Mutation {
+                    column,
+                    row,
+                    description: description.to_string(),
+                    cells: vec![(advice_column, mutated)],
+                }
+            })
+            .collect()
+    }
+
+    /// Changes the variant of a one hot column to every other variant.
+    fn one_hot_mutations<T: IntoEnumIterator + Copy + Ord + std::hash::Hash + Debug>(
+        &self,
+        column: &'static str,
+        one_hot: &OneHot<T>,
+        rows: &[usize],
+    ) -> Vec<Mutation> {
+        let mut mutations = vec![];
+        for &row in rows {
+            let value = self.one_hot_value(one_hot, row);
+            for variant in T::iter().filter(|variant| *variant != value) {
+                mutations.push(Mutation {
+                    column,
+                    row,
+                    description: format!("{value:?} -> {variant:?}"),
+                    cells: one_hot
+                        .columns()
+                        .map(|(v, column)| (column, Fr::from(u64::from(v == variant))))
+                        .collect(),
+                });
+            }
+        }
+        mutations
+    }
+
+    /// The first update row of each segment type and path type pair.
+    fn sweep_rows(&self) -> Vec<usize> {
+        let mut rows = BTreeMap::new();
+        for row in self.update_rows() {
+            rows.entry((self.segment_type(row), self.path_type(row)))
+                .or_insert(row);
+        }
+        rows.into_values().collect()
+    }
+
+    /// Mutations of every column in `rows`.
+    fn mutations(&self, rows: &[usize]) -> Vec<Mutation> {
+        let mpt_update = self.config.mpt_update();
+
+        let mut mutations: Vec<_> = mpt_update
+            .named_columns()
+            .into_iter()
+            .flat_map(|(column, _)| self.value_mutations(column, rows))
+            .collect();
+        mutations.extend(self.one_hot_mutations("segment_type", mpt_update.segment_type(), rows));
+        mutations.extend(self.one_hot_mutations("path_type", mpt_update.path_type(), rows));
+        mutations
+    }
+
+    /// Mutations of every column in every update row.
+    fn all_mutations(&self) -> Vec<Mutation> {
+        self.mutations(&self.update_rows().collect::<Vec<_>>())
+    }
+
+    /// The failures of the MockProver for the mutation.
+    fn failures(&self, mutation: &Mutation) -> Vec<VerifyFailure> {
+        self.run(mutation).0
+    }
+
+    /// The failures of the MockProver for the mutation, and the regions of the mpt update rows.
+    fn run(&self, mutation: &Mutation) -> (Vec<VerifyFailure>, Vec<UpdateRegion>) {
+        let circuit = MutationCircuit {
+            proofs: self.proofs.clone(),
+            mutation: Some(mutation.clone()),
+            ..Default::default()
+        };
+        let failures = MockProver::run(K, &circuit, vec![])
+            .unwrap()
+            .verify()
+            .err()
+            .unwrap_or_default();
+        let regions = circuit.regions.lock().unwrap().clone();
+        (failures, regions)
+    }
+
+    /// Whether the MockProver accepts the mutation.
+    fn survives(&self, mutation: &Mutation) -> bool {
+        self.failures(mutation).is_empty()
+    }
+
+    /// The mutations that the MockProver accepts.
+    fn surviving_mutations(&self, mutations: Vec<Mutation>) -> Vec<Mutation> {
+        mutations
+            .into_iter()
+            .filter(|mutation| self.survives(mutation))
+            .collect()
+    }
+}
+
+fn assert_no_survivors(survivors: &[Mutation]) {
+    assert!(
+        survivors.is_empty(),
+        "{} mutations satisfy the circuit:\n{}",
+        survivors.len(),
+        survivors
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>()
+            .join("\n")
+    );
+}
+
+/// Cells that no constraint reads, so no mutation of them can be detected, with the reason they are
+/// not read.
+const ALLOWED_SURVIVORS: [(SegmentType, &str, &str); 4] = [
+    (
+        SegmentType::Start,
+        "direction",
+        "the Start row is not on a trie path",
+    ),
+    (
+        SegmentType::Start,
+        "sibling",
+        "the Start row is not on a trie path",
+    ),
+    (
+        SegmentType::AccountLeaf3,
+        "key",
+        "only storage proofs read the key on AccountLeaf3, where it is the storage trie key",
+    ),
+    (
+        SegmentType::AccountLeaf3,
+        "other_key",
+        "only storage proofs read other_key on AccountLeaf3, through the storage rows after it",
+    ),
+];
+
+/// Names of the constraints and lookups that `constraint_audit` allows no mutation of
+/// `sweep_witness` to depend on, with why: the constraint that is always violated with them, or why
+/// the mutations never violate them.
+const ALLOWED_UNDETECTED: &[(&str, &str)] = &[
+    (
+        "balance AccountLeaf0 domain is Leaf",
+        "domain in allowed set for segment type, which only allows Leaf on AccountLeaf0",
+    ),
+    (
+        "balance AccountLeaf1 domain is AccountFields",
+        "domain in allowed set for segment type, which only allows AccountFields on AccountLeaf1",
+    ),
+    (
+        "domain in allowed set for segment type",
+        "the poseidon lookups of the row, which have the domain as an input",
+    ),
+    (
+        "rlc_old_root = rlc(old_root)",
+        "the poseidon lookup of the first trie row, which has the root as its hash",
+    ),
+    (
+        "rlc_new_root = rlc(new_root)",
+        "the poseidon lookup of the first trie row, which has the root as its hash",
+    ),
+    (
+        "account mpt key = h(address_high, address_low << 96)",
+        "key can only change on Start or AccountLeaf3 rows, on the row after",
+    ),
+    (
+        "address_high is 16 bytes",
+        "segment is Start iff path is Start, since only rows mutated to Start read address_high",
+    ),
+    (
+        "address_low is 4 bytes",
+        "segment is Start iff path is Start, since only rows mutated to Start read address_low",
+    ),
+    (
+        "proof type does not change",
+        "segment is Start iff path is Start, since the proof type is not mutated",
+    ),
+    (
+        "storage_key_rlc does not change",
+        "segment is Start iff path is Start, since storage_key_rlc is not mutated",
+    ),
+    (
+        "segment is Start iff path is Start",
+        "the segment or path type transitions into and out of the mutated row",
+    ),
+    (
+        "transition for current segment -> next segment",
+        "the transition into the mutated row, or the depth or Start constraints of the row",
+    ),
+    (
+        "transition for path_type",
+        "the hash constraints of the path type that the row is mutated to",
+    ),
+    (
+        "binary column is 0 or 1",
+        "never violated, since one hot mutations only set cells to 0 or 1",
+    ),
+    (
+        "sum of binary columns in OneHot is 0 or 1",
+        "never violated, since one hot mutations set exactly one column to 1",
+    ),
+    (
+        "old balance is rlc(old_hash) and fits into 31 bytes",
+        "old_value does not change, or the poseidon lookup of the leaf for old_hash",
+    ),
+    (
+        "new balance is rlc(new_hash) and fits into 31 bytes",
+        "new_value does not change, or the poseidon lookup of the leaf for new_hash",
+    ),
+    (
+        "direction is 1",
+        "the poseidon lookups of the row, since the direction orders the hash inputs",
+    ),
+    (
+        "direction is 0",
+        "the poseidon lookups of the row, since the direction orders the hash inputs",
+    ),
+    (
+        "direction is correct for key and depth",
+        "direction is correct for other_key and depth, since key and other_key are equal",
+    ),
+    (
+        "direction is correct for other_key and depth",
+        "direction is correct for key and depth, since key and other_key are equal",
+    ),
+    (
+        "depth increases by 1 in trie segments",
+        "the direction lookups of the row, which have the depth as an input",
+    ),
+    (
+        "can only add new nodes for nonce, balance and storage proofs",
+        "never violated, since every proof in the sweep is a balance or storage update",
+    ),
+    (
+        "old value is 0 if old account is empty",
+        "old_value does not change",
+    ),
+    (
+        "common -> extension new switch only allowed in trie segments",
+        "the segment or path type transitions into and out of the mutated row",
+    ),
+    (
+        "sibling is zero for non-final new extension path segments",
+        "the poseidon lookup of the new extension path, which has the sibling as an input",
+    ),
+    (
+        "sibling is old leaf hash for final new extension path segments",
+        "the poseidon lookup of the new extension path, which has the sibling as an input",
+    ),
+    (
+        "poseidon code hash is 0 for balance extension new at AccountLeaf1",
+        "the poseidon lookup of the new extension path, which has the sibling as an input",
+    ),
+    (
+        "sibling is hash(0, hash(0, 0)) for balance extension new at AccountLeaf2",
+        "the poseidon lookup of the new extension path, which has the sibling as an input",
+    ),
+    (
+        "sibling (code_size + nonce << 64) is 0 for new account)",
+        "nonce and code size are 0 for new account, which is the same constraint",
+    ),
+    (
+        "nonce and code size are 0 for new account",
+        "sibling (code_size + nonce << 64) is 0 for new account), which is the same constraint",
+    ),
+    ("value is 0 for empty node", "old_value does not change"),
+    (
+        "key_minus_other_key = key - other key",
+        "key or other_key can only change on Start or AccountLeaf3 rows",
+    ),
+    (
+        "hash_is_zero input == hash",
+        "old_hash unchanged for path_type=New, on the leaf row of the new extension path",
+    ),
+    (
+        "Empty account is either type 1 xor type 2",
+        "the segment or path type transitions, since the is zero gadgets are not mutated",
+    ),
+    (
+        "hash == h(other_key, other_leaf_data_hash)",
+        "other_key can only change on Start or AccountLeaf3 rows, or hash_is_zero input == hash",
+    ),
+    (
+        "word_hash = poseidon(high, low)",
+        "key can only change on Start or AccountLeaf3 rows, or the poseidon lookup of the leaf",
+    ),
+    (
+        "old_high is 16 bytes",
+        "segment transitions, since the high and low halves of the word are not mutated",
+    ),
+    (
+        "old_low is 16 bytes",
+        "segment transitions, since the high and low halves of the word are not mutated",
+    ),
+    (
+        "rlc_high = rlc(high) and high is 16 bytes",
+        "segment transitions, since the high and low halves of the word are not mutated",
+    ),
+    (
+        "rlc_low = rlc(low) and low is 16 bytes",
+        "segment transitions, since the high and low halves of the word are not mutated",
+    ),
+    (
+        "word_rlc = rlc(high) * randomness ^ 16 + rlc(low)",
+        "old_value or new_value does not change",
+    ),
+    (
+        "old_hash_minus_zero_storage_hash = old_hash - hash(0, 0)",
+        "word_hash = poseidon(high, low), or the poseidon lookup of the leaf",
+    ),
+    (
+        "new_hash_minus_zero_storage_hash = new_hash - hash(0, 0)",
+        "word_hash = poseidon(high, low), or the poseidon lookup of the leaf",
+    ),
+    (
+        "old hash != hash(0, 0)",
+        "segment transitions, since the is zero gadgets are not mutated",
+    ),
+    (
+        "new hash != hash(0, 0)",
+        "segment transitions, since the is zero gadgets are not mutated",
+    ),
+];
+
+/// Assigns `proofs` to a table for `ConstraintBuilder::evaluate`, with the same columns as the
+/// `MutationCircuit` so that mutations apply to it.
+fn native_witness(proofs: &[Proof], randomness: Fr) -> (ConstraintBuilder<Fr>, WitnessTable<Fr>) {
+    let mut cs = ConstraintSystem::default();
+    let poseidon = PoseidonTable::configure(&mut cs);
+    let challenge = cs.challenge_usable_after(FirstPhase);
+    let (config, cb) = MptCircuitConfig::configure_with_constraints(
+        &mut cs,
+        challenge,
+        &poseidon,
+        MptCircuitOptions::default(),
+    );
+
+    let mut table = WitnessTable::new(N_ROWS);
+    config
+        .assign_native(&mut table, proofs, N_ROWS, randomness)
+        .unwrap();
+    poseidon.load(&mut table, &hash_traces(proofs));
+    (cb, table)
+}
+
+fn balance_update() -> Vec<(MPTProofType, SMTTrace)> {
+    vec![(
+        MPTProofType::BalanceChanged,
+        serde_json::from_str(include_str!("traces/existing_account_balance_update.json")).unwrap(),
+    )]
+}
+
+/// Updates of existing leaves and insertions below an existing leaf, in both the account and
+/// storage tries, so that every segment type has a row with both the Common and ExtensionNew path
+/// types.
+fn sweep_witness() -> Vec<(MPTProofType, SMTTrace)> {
+    [
+        (
+            MPTProofType::BalanceChanged,
+            include_str!("traces/existing_account_balance_update.json"),
+        ),
+        (
+            MPTProofType::BalanceChanged,
+            include_str!("traces/empty_account_type_1_balance_update.json"),
+        ),
+        (
+            MPTProofType::StorageChanged,
+            include_str!("traces/existing_storage_update.json"),
+        ),
+        (
+            MPTProofType::StorageChanged,
+            include_str!("traces/empty_storage_type_1_update_a.json"),
+        ),
+    ]
+    .into_iter()
+    .map(|(proof_type, trace)| (proof_type, serde_json::from_str(trace).unwrap()))
+    .collect()
+}
+
+#[test]
+fn mutate_account_trie() {
+    let harness = Harness::new(balance_update());
+    // Rows in the middle of the account trie path, where the hashes of both the row and the row
+    // above it are looked up.
+    let rows: Vec<_> = harness
+        .update_rows()
+        .filter(|row| {
+            harness.segment_type(*row) == SegmentType::AccountTrie
+                && harness.path_type(*row) == PathType::Common
+                && harness.path_type(row + 1) == PathType::Common
+        })
+        .collect();
+    assert!(!rows.is_empty());
+
+    let mutations = ["old_hash", "new_hash", "sibling", "direction"]
+        .into_iter()
+        .flat_map(|column| harness.value_mutations(column, &rows))
+        .collect();
+    assert_no_survivors(&harness.surviving_mutations(mutations));
+}
+
+// Mutates every column of one row per segment type and path type, which runs the MockProver about
+// 300 times.
+#[test]
+fn mutation_sweep() {
+    let harness = Harness::new(sweep_witness());
+    let rows = harness.sweep_rows();
+    assert_eq!(rows.len(), 15);
+
+    let survivors: Vec<_> = harness
+        .surviving_mutations(harness.mutations(&rows))
+        .into_iter()
+        .filter(|mutation| {
+            let segment_type = harness.segment_type(mutation.row);
+            !ALLOWED_SURVIVORS
+                .iter()
+                .any(|(s, column, _)| *s == segment_type && *column == mutation.column)
+        })
+        .collect();
+    assert_no_survivors(&survivors);
+}
+
+#[test]
+fn explain_failure() {
+    let harness = Harness::new(balance_update());
+    // Only the constraint that the depth is 0 outside of the tries reads the depth of a leaf row.
+    let row = harness
+        .update_rows()
+        .find(|row| harness.segment_type(*row) == SegmentType::AccountLeaf0)
+        .unwrap();
+    let mutation = harness.value_mutations("depth", &[row]).remove(0);
+    let (failures, regions) = harness.run(&mutation);
+
+    // `MutationCircuit` is assigned sequentially, in one region that starts at the first row.
+    assert_eq!(regions.len(), 1);
+    assert_eq!(regions[0].first_row, 0);
+    let diagnostics = harness.config.diagnostics(&harness.proofs, &regions);
+    let explanation = format!(
+        "row {row}, proof #0 (BalanceChanged, {}), AccountLeaf0, Common path: constraint \"depth \
+         is 0 in non-trie segments\" is not satisfied",
+        harness.proofs[0].claim.address
+    );
+    let failure = failures
+        .iter()
+        .find(|failure| diagnostics.explain(failure) == explanation)
+        .unwrap_or_else(|| panic!("no failure is explained as {explanation:?}: {failures:?}"));
+
+    // Without the regions, the offset of the failure cannot be mapped to a row.
+    let diagnostics = harness.config.diagnostics(&harness.proofs, &[]);
+    assert_eq!(diagnostics.failure_row(failure), None);
+}
+
+#[test]
+fn explain_parallel_failure() {
+    let proofs: Vec<_> = [
+        (
+            MPTProofType::BalanceChanged,
+            include_str!("traces/existing_account_balance_update.json"),
+        ),
+        (
+            MPTProofType::StorageChanged,
+            include_str!("traces/existing_storage_update.json"),
+        ),
+    ]
+    .into_iter()
+    .map(|(proof_type, trace)| {
+        Proof::from_trace_unchecked((proof_type, serde_json::from_str(trace).unwrap()))
+    })
+    .collect();
+    let circuit = ParallelCircuit {
+        proofs: proofs.clone(),
+        ..Default::default()
+    };
+    let failures = MockProver::run(K, &circuit, vec![])
+        .unwrap()
+        .verify()
+        .unwrap_err();
+
+    // One region per update, and one for the padding rows.
+    let regions = circuit.regions.lock().unwrap().clone();
+    let first_rows: Vec<_> = regions.iter().map(|region| region.first_row).collect();
+    let storage_rows = 1 + proofs[0].n_rows()..1 + proofs[0].n_rows() + proofs[1].n_rows();
+    assert_eq!(first_rows, [0, storage_rows.start, storage_rows.end]);
+
+    // Only the hashes of the storage update are missing, so every failure in the mpt update
+    // regions is in its rows.
+    let (_, config) = ParallelCircuit::configure(&mut ConstraintSystem::default());
+    let diagnostics = config.diagnostics(&proofs, &regions);
+    let rows: Vec<_> = failures
+        .iter()
+        .filter_map(|failure| diagnostics.failure_row(failure))
+        .collect();
+    assert!(!rows.is_empty(), "{failures:?}");
+    assert!(
+        rows.iter().all(|row| storage_rows.contains(row)),
+        "{rows:?}"
+    );
+    assert!(failures
+        .iter()
+        .filter(|failure| diagnostics.failure_row(failure).is_some())
+        .all(|failure| diagnostics
+            .explain(failure)
+            .contains(", proof #1 (StorageChanged, ")));
+}
+
+// Checks that `ConstraintBuilder::evaluate` accepts exactly the mutations that the MockProver
+// accepts. Values of second phase columns depend on the challenge, which the MockProver picks, so
+// only first phase columns are mutated.
+#[test]
+fn native_parity() {
+    let harness = Harness::new(balance_update());
+    let randomness = Fr::from(0x1234);
+    let (cb, table) = native_witness(&harness.proofs, randomness);
+    assert_eq!(cb.evaluate(&table, &[randomness]), Ok(()));
+
+    let mismatches: Vec<_> = harness
+        .mutations(&harness.sweep_rows())
+        .into_iter()
+        .filter(|mutation| !["old_value", "new_value"].contains(&mutation.column))
+        .filter(|mutation| {
+            let is_ok = cb.evaluate(&mutation.apply(&table), &[randomness]).is_ok();
+            is_ok != harness.survives(mutation)
+        })
+        .collect();
+    assert!(
+        mismatches.is_empty(),
+        "native evaluation and the MockProver disagree on {} mutations:\n{}",
+        mismatches.len(),
+        mismatches
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>()
+            .join("\n")
+    );
+}
+
+// Evaluates the mutations of `mutation_sweep` natively, and checks that each constraint and lookup
+// that applies at a mutated row and reads a mutated column is the only one violated by some
+// mutation, i.e. that removing it lets a mutation through. Those that are not are either
+// redundant, only violated together with another constraint, or never violated at all, and must
+// be in `ALLOWED_UNDETECTED`. Constraints of gadgets that only read other columns, e.g. the key
+// bit and byte tables, are not audited.
+#[test]
+fn constraint_audit() {
+    let harness = Harness::new(sweep_witness());
+    let randomness = Fr::from(0x1234);
+    let (cb, table) = native_witness(&harness.proofs, randomness);
+    assert_eq!(cb.evaluate(&table, &[randomness]), Ok(()));
+
+    let mpt_update = harness.config.mpt_update();
+    let mutated_columns: BTreeSet<_> = mpt_update
+        .named_columns()
+        .into_iter()
+        .map(|(_, column)| column)
+        .chain(
+            mpt_update
+                .segment_type()
+                .columns()
+                .map(|(_, column)| column),
+        )
+        .chain(mpt_update.path_type().columns().map(|(_, column)| column))
+        .map(|column| column.index())
+        .collect();
+
+    let rows = harness.sweep_rows();
+    let names = cb.names();
+    let audited: BTreeSet<_> = (0..names.len())
+        .filter(|index| !cb.queried_advice(*index).is_disjoint(&mutated_columns))
+        .filter(|index| {
+            rows.iter()
+                .any(|row| cb.is_enabled(*index, &table, *row, &[randomness]))
+        })
+        .collect();
+
+    let mut needed = BTreeSet::new();
+    for mutation in harness.mutations(&rows) {
+        let failures: BTreeSet<_> = cb
+            .evaluate(&mutation.apply(&table), &[randomness])
+            .err()
+            .unwrap_or_default()
+            .iter()
+            .map(NativeFailure::index)
+            .collect();
+        if failures.len() == 1 {
+            needed.extend(failures);
+        }
+    }
+
+    let undetected: Vec<_> = audited
+        .difference(&needed)
+        .filter(|index| {
+            !ALLOWED_UNDETECTED
+                .iter()
+                .any(|(name, _)| *name == names[**index])
+        })
+        .map(|index| format!("{index}: {}", names[*index]))
+        .collect();
+    assert!(
+        undetected.is_empty(),
+        "{} constraints are not needed to reject any mutation:\n{}",
+        undetected.len(),
+        undetected.join("\n")
+    );
+}
@@ -0,0 +1,196 @@
+//! Splits updates that need more rows than one mpt circuit has into multiple circuits.
+use crate::{
+    gadgets::mpt_update::hash_traces,
+    mpt::{CapacityError, MptCircuitConfig},
+    types::Proof,
+};
+use halo2_proofs::halo2curves::bn256::Fr;
+use std::ops::Range;
+
+/// Consecutive proofs that fit in one mpt circuit.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ProofChunk {
+    /// Indices of the proofs in the chunk.
+    pub proofs: Range<usize>,
+    /// Old root of the first proof in the chunk.
+    pub old_root: Fr,
+    /// New root of the last proof in the chunk.
+    pub new_root: Fr,
+    /// Rows required for the chunk, i.e. the rows of `MptCircuitConfig::bottleneck`.
+    pub n_rows: usize,
+    /// Poseidon hashes required for the chunk, i.e. the length of its `hash_traces`.
+    pub n_hashes: usize,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, thiserror::Error)]
+/// A single proof does not fit in one circuit.
+pub enum SplitError {
+    #[error(transparent)]
+    /// the proof requires more rows than the circuit has
+    Capacity(#[from] CapacityError),
+    #[error("{required} poseidon hashes are required, but only {available} are available")]
+    /// the proof requires more poseidon hashes than the circuit has
+    PoseidonCapacity { required: usize, available: usize },
+}
+
+impl ProofChunk {
+    /// Number of proofs in the chunk.
+    pub fn n_updates(&self) -> usize {
+        self.proofs.len()
+    }
+
+    /// Whether `next` starts from the root this chunk ends at.
+    pub fn links_to(&self, next: &Self) -> bool {
+        self.proofs.end == next.proofs.start && self.new_root == next.old_root
+    }
+}
+
+/// Splits `proofs` into consecutive chunks that each fit in a circuit with `n_rows` rows and
+/// `max_poseidon_hashes` poseidon hashes. Each chunk is as long as possible, so the number of
+/// chunks is minimal. Fails if a single proof does not fit.
+pub fn split_proofs(
+    proofs: &[Proof],
+    n_rows: usize,
+    max_poseidon_hashes: usize,
+) -> Result<Vec<ProofChunk>, SplitError> {
+    let fits = |range: Range<usize>| -> Result<(), SplitError> {
+        let chunk = &proofs[range];
+        MptCircuitConfig::check_capacity(chunk, n_rows)?;
+        let required = hash_traces(chunk).len();
+        if required > max_poseidon_hashes {
+            return Err(SplitError::PoseidonCapacity {
+                required,
+                available: max_poseidon_hashes,
+            });
+        }
+        Ok(())
+    };
+
+    let mut chunks = vec![];
+    let mut start = 0;
+    while start < proofs.len() {
+        fits(start..start + 1)?;
+
+        // Adding a proof never decreases the rows required by any gadget, or the poseidon hashes
+        // required, so the end of the chunk can be found by binary search.
+        let (mut fitting_end, mut end) = (start + 1, proofs.len() + 1);
+        while end - fitting_end > 1 {
+            let middle = (fitting_end + end) / 2;
+            if fits(start..middle).is_ok() {
+                fitting_end = middle;
+            } else {
+                end = middle;
+            }
+        }
+
+        let chunk = &proofs[start..fitting_end];
+        chunks.push(ProofChunk {
+            proofs: start..fitting_end,
+            old_root: chunk[0].claim.old_root,
+            new_root: chunk[chunk.len() - 1].claim.new_root,
+            n_rows: MptCircuitConfig::bottleneck(chunk).1,
+            n_hashes: hash_traces(chunk).len(),
+        });
+        start = fitting_end;
+    }
+    Ok(chunks)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{mpt::Gadget, serde::SMTTrace, MPTProofType};
+
+    const MAX_POSEIDON_HASHES: usize = 1 << 20;
+
+    fn benchmark_proofs() -> Vec<Proof> {
+        let traces: Vec<(MPTProofType, SMTTrace)> =
+            serde_json::from_str(include_str!("../benches/traces.json")).unwrap();
+        traces
+            .into_iter()
+            .map(|trace| Proof::try_from(trace).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn split_benchmark_trace() {
+        let proofs = benchmark_proofs();
+        let n_rows = 4096;
+
+        let chunks = split_proofs(&proofs, n_rows, MAX_POSEIDON_HASHES).unwrap();
+        assert!(chunks.len() > 1);
+        assert_eq!(chunks.first().unwrap().proofs.start, 0);
+        assert_eq!(chunks.last().unwrap().proofs.end, proofs.len());
+        for chunk in &chunks {
+            assert!(chunk.n_rows <= n_rows);
+            assert!(chunk.n_hashes <= MAX_POSEIDON_HASHES);
+            assert_eq!(chunk.old_root, proofs[chunk.proofs.start].claim.old_root);
+            assert_eq!(chunk.new_root, proofs[chunk.proofs.end - 1].claim.new_root);
+        }
+        for (chunk, next) in chunks.iter().zip(&chunks[1..]) {
+            assert_eq!(chunk.proofs.end, next.proofs.start);
+            // The chunk could not have included the first proof of the next one.
+            let extended = chunk.proofs.start..next.proofs.start + 1;
+            assert!(MptCircuitConfig::check_capacity(&proofs[extended], n_rows).is_err());
+        }
+    }
+
+    #[test]
+    fn single_chunk() {
+        let proofs = benchmark_proofs();
+        let (_, n_rows) = MptCircuitConfig::bottleneck(&proofs);
+
+        let chunks = split_proofs(&proofs, n_rows, MAX_POSEIDON_HASHES).unwrap();
+        assert_eq!(chunks.len(), 1);
+        assert_eq!(chunks[0].n_updates(), proofs.len());
+        assert_eq!(chunks[0].n_rows, n_rows);
+    }
+
+    #[test]
+    fn too_few_rows() {
+        assert_eq!(
+            split_proofs(&benchmark_proofs(), 1000, MAX_POSEIDON_HASHES),
+            Err(SplitError::Capacity(CapacityError {
+                gadget: Gadget::ByteBit,
+                required: 8 * 256 + 1,
+                available: 1000,
+            }))
+        );
+    }
+
+    #[test]
+    fn split_by_poseidon_hashes() {
+        let proofs = benchmark_proofs();
+        let (_, n_rows) = MptCircuitConfig::bottleneck(&proofs);
+        let max_poseidon_hashes = hash_traces(&proofs).len() / 2;
+
+        // The proofs fit in one circuit's rows, but not in its poseidon hashes.
+        let chunks = split_proofs(&proofs, n_rows, max_poseidon_hashes).unwrap();
+        assert!(chunks.len() > 1);
+        for (chunk, next) in chunks.iter().zip(&chunks[1..]) {
+            assert!(chunk.n_hashes <= max_poseidon_hashes);
+            let extended = chunk.proofs.start..next.proofs.start + 1;
+            assert!(hash_traces(&proofs[extended]).len() > max_poseidon_hashes);
+        }
+    }
+
+    #[test]
+    fn too_few_poseidon_hashes() {
+        let proofs = benchmark_proofs();
+        let (_, n_rows) = MptCircuitConfig::bottleneck(&proofs);
+        let required = hash_traces(&proofs[..1]).len();
+
+        assert_eq!(
+            split_proofs(&proofs, n_rows, required - 1),
+            Err(SplitError::PoseidonCapacity {
+                required,
+                available: required - 1,
+            })
+        );
+    }
+
+    #[test]
+    fn no_proofs() {
+        assert_eq!(split_proofs(&[], 1000, MAX_POSEIDON_HASHES), Ok(vec![]));
+    }
+}
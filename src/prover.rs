@@ -0,0 +1,323 @@
+//! Real (KZG) proofs for the standalone `StateTransitionCircuit`.
+use crate::{
+    mpt::CapacityError,
+    serde::SMTTrace,
+    types::{Proof, TraceError},
+    MPTProofType, MptCircuitConfig, StateTransitionCircuit,
+};
+use halo2_proofs::{
+    halo2curves::{
+        bn256::{Bn256, Fr, G1Affine},
+        ff::PrimeField,
+    },
+    plonk::{create_proof, keygen_pk, keygen_vk, verify_proof, Error, ProvingKey, VerifyingKey},
+    poly::{
+        commitment::ParamsProver,
+        kzg::{
+            commitment::{KZGCommitmentScheme, ParamsKZG},
+            multiopen::{ProverSHPLONK, VerifierSHPLONK},
+            strategy::SingleStrategy,
+        },
+    },
+    transcript::{
+        Blake2bRead, Blake2bWrite, Challenge255, TranscriptReadBuffer, TranscriptWriterBuffer,
+    },
+};
+use rand::rngs::OsRng;
+
+/// Proof for a `StateTransitionCircuit`, along with its public inputs.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MptProof {
+    /// Old root of the first update, new root of the last update, and number of updates.
+    pub instance: Vec<Fr>,
+    /// The proof transcript.
+    pub proof: Vec<u8>,
+}
+
+#[derive(Debug, thiserror::Error)]
+/// Proof bytes decoding errors.
+pub enum ProofBytesError {
+    #[error("proof bytes end before the public inputs")]
+    /// not enough bytes for the number of instances declared
+    Truncated,
+    #[error("public input {0} is not a canonical field element")]
+    /// instance bytes are not the canonical representation of an Fr
+    NonCanonicalInstance(usize),
+}
+
+#[derive(Debug, thiserror::Error)]
+/// Proving errors.
+pub enum ProveError {
+    #[error("trace {index} is invalid: {error}")]
+    /// trace cannot be converted into a `Proof`
+    InvalidTrace { index: usize, error: TraceError },
+    #[error("trace {index} does not start at the new root of the trace before it")]
+    /// the circuit chains the roots of consecutive updates, so they have to link up
+    UnchainedRoots { index: usize },
+    #[error(transparent)]
+    /// the traces do not fit in the circuit
+    Capacity(#[from] CapacityError),
+    #[error("proving failed: {0:?}")]
+    /// error from keygen, synthesis, or the prover
+    Proof(#[from] Error),
+}
+
+impl MptProof {
+    /// Encodes the proof as the number of public inputs (u32 little endian), followed by the public
+    /// inputs (32 bytes little endian each), followed by the proof transcript.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = u32::try_from(self.instance.len())
+            .unwrap()
+            .to_le_bytes()
+            .to_vec();
+        for value in &self.instance {
+            bytes.extend_from_slice(&value.to_repr());
+        }
+        bytes.extend_from_slice(&self.proof);
+        bytes
+    }
+
+    /// Inverse of `to_bytes`.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, ProofBytesError> {
+        let n_instances = bytes
+            .get(..4)
+            .and_then(|n_instances| <[u8; 4]>::try_from(n_instances).ok())
+            .ok_or(ProofBytesError::Truncated)?;
+        let rest = &bytes[4..];
+        let instance_length = usize::try_from(u32::from_le_bytes(n_instances))
+            .ok()
+            .and_then(|n_instances| n_instances.checked_mul(32))
+            .filter(|length| *length <= rest.len())
+            .ok_or(ProofBytesError::Truncated)?;
+        let (instance_bytes, proof) = rest.split_at(instance_length);
+
+        let instance = instance_bytes
+            .chunks_exact(32)
+            .enumerate()
+            .map(|(i, repr)| {
+                <[u8; 32]>::try_from(repr)
+                    .ok()
+                    .and_then(|repr| Option::from(Fr::from_repr(repr)))
+                    .ok_or(ProofBytesError::NonCanonicalInstance(i))
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok(Self {
+            instance,
+            proof: proof.to_vec(),
+        })
+    }
+}
+
+/// Proving key for `StateTransitionCircuit`s of a fixed shape, along with the parameters it was
+/// generated with.
+pub struct MptProver {
+    params: ParamsKZG<Bn256>,
+    pk: ProvingKey<G1Affine>,
+    n_rows: usize,
+    max_poseidon_hashes: usize,
+}
+
+impl MptProver {
+    /// Generates the verifying and proving keys for circuits with `n_rows` rows and
+    /// `max_poseidon_hashes` poseidon hashes. This is slow, so reuse the prover (or its keys) for
+    /// multiple proofs.
+    pub fn new(
+        params: ParamsKZG<Bn256>,
+        n_rows: usize,
+        max_poseidon_hashes: usize,
+    ) -> Result<Self, Error> {
+        let circuit = StateTransitionCircuit::new(n_rows, max_poseidon_hashes, vec![]);
+        let vk = keygen_vk(&params, &circuit)?;
+        let pk = keygen_pk(&params, vk, &circuit)?;
+        Ok(Self::from_proving_key(
+            params,
+            pk,
+            n_rows,
+            max_poseidon_hashes,
+        ))
+    }
+
+    /// Reuses a proving key generated for circuits with the same `params`, `n_rows`, and
+    /// `max_poseidon_hashes`.
+    pub fn from_proving_key(
+        params: ParamsKZG<Bn256>,
+        pk: ProvingKey<G1Affine>,
+        n_rows: usize,
+        max_poseidon_hashes: usize,
+    ) -> Self {
+        Self {
+            params,
+            pk,
+            n_rows,
+            max_poseidon_hashes,
+        }
+    }
+
+    pub fn params(&self) -> &ParamsKZG<Bn256> {
+        &self.params
+    }
+
+    pub fn proving_key(&self) -> &ProvingKey<G1Affine> {
+        &self.pk
+    }
+
+    pub fn verifying_key(&self) -> &VerifyingKey<G1Affine> {
+        self.pk.get_vk()
+    }
+
+    /// Proves the state transition of `traces`, which have to be consecutive updates, i.e. each
+    /// update's old root is the new root of the update before it. `create_proof` does not check
+    /// that the circuit is satisfied, so this checks it up front instead of returning a proof that
+    /// `verify` rejects.
+    pub fn prove(&self, traces: Vec<(MPTProofType, SMTTrace)>) -> Result<MptProof, ProveError> {
+        let proofs = traces
+            .into_iter()
+            .enumerate()
+            .map(|(index, trace)| {
+                Proof::try_from(trace).map_err(|error| ProveError::InvalidTrace { index, error })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        MptCircuitConfig::check_capacity(&proofs, self.n_rows)?;
+        if let Some(index) =
+            (1..proofs.len()).find(|i| proofs[*i].claim.old_root != proofs[i - 1].claim.new_root)
+        {
+            return Err(ProveError::UnchainedRoots { index });
+        }
+        let circuit =
+            StateTransitionCircuit::from_proofs(self.n_rows, self.max_poseidon_hashes, proofs);
+        let [instance]: [Vec<Fr>; 1] = circuit.instances().try_into().unwrap();
+
+        let mut transcript = Blake2bWrite::<_, G1Affine, Challenge255<_>>::init(vec![]);
+        create_proof::<KZGCommitmentScheme<Bn256>, ProverSHPLONK<'_, Bn256>, _, _, _, _>(
+            &self.params,
+            &self.pk,
+            &[circuit],
+            &[&[&instance]],
+            OsRng,
+            &mut transcript,
+        )?;
+
+        Ok(MptProof {
+            instance,
+            proof: transcript.finalize(),
+        })
+    }
+
+    pub fn verify(&self, proof: &MptProof) -> Result<(), Error> {
+        verify(&self.params, self.verifying_key(), proof)
+    }
+}
+
+/// Verifies `proof` against the verifying key of the `StateTransitionCircuit` shape it was created
+/// for.
+pub fn verify(
+    params: &ParamsKZG<Bn256>,
+    vk: &VerifyingKey<G1Affine>,
+    proof: &MptProof,
+) -> Result<(), Error> {
+    let verifier_params = params.verifier_params();
+    let mut transcript = Blake2bRead::<_, G1Affine, Challenge255<_>>::init(&proof.proof[..]);
+    verify_proof::<KZGCommitmentScheme<Bn256>, VerifierSHPLONK<'_, Bn256>, _, _, _>(
+        verifier_params,
+        vk,
+        SingleStrategy::new(verifier_params),
+        &[&[&proof.instance]],
+        &mut transcript,
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rand_chacha::rand_core::SeedableRng;
+
+    const N_ROWS: usize = 8 * 256 + 1;
+    const K: u32 = 14;
+
+    fn traces() -> Vec<(MPTProofType, SMTTrace)> {
+        vec![(
+            MPTProofType::BalanceChanged,
+            serde_json::from_str(include_str!(
+                "traces/empty_account_type_1_balance_update.json"
+            ))
+            .unwrap(),
+        )]
+    }
+
+    fn prover() -> MptProver {
+        let params = ParamsKZG::<Bn256>::setup(K, rand_chacha::ChaCha20Rng::seed_from_u64(2));
+        MptProver::new(params, N_ROWS, 100).unwrap()
+    }
+
+    #[test]
+    fn prove_and_verify() {
+        let prover = prover();
+        let proof = prover.prove(traces()).unwrap();
+        prover.verify(&proof).unwrap();
+
+        let decoded = MptProof::from_bytes(&proof.to_bytes()).unwrap();
+        assert_eq!(decoded, proof);
+        verify(prover.params(), prover.verifying_key(), &decoded).unwrap();
+    }
+
+    #[test]
+    fn wrong_public_inputs() {
+        let prover = prover();
+        let mut proof = prover.prove(traces()).unwrap();
+        proof.instance.swap(0, 1);
+        assert!(prover.verify(&proof).is_err());
+    }
+
+    #[test]
+    fn invalid_trace() {
+        let prover = prover();
+        let mut traces = traces();
+        traces[0].0 = MPTProofType::NonceChanged;
+        assert!(matches!(
+            prover.prove(traces),
+            Err(ProveError::InvalidTrace { index: 0, .. })
+        ));
+    }
+
+    #[test]
+    fn unchained_traces() {
+        let prover = prover();
+        // The same update twice does not start from the root it leaves the trie at.
+        let traces = traces().repeat(2);
+        assert!(matches!(
+            prover.prove(traces),
+            Err(ProveError::UnchainedRoots { index: 1 })
+        ));
+    }
+
+    #[test]
+    fn too_many_traces() {
+        let prover = prover();
+        let traces = traces().repeat(1000);
+        assert!(matches!(
+            prover.prove(traces),
+            Err(ProveError::Capacity(CapacityError {
+                gadget: crate::mpt::Gadget::MptUpdate,
+                ..
+            }))
+        ));
+    }
+
+    #[test]
+    fn truncated_proof_bytes() {
+        let proof = MptProof {
+            instance: vec![Fr::from(1), Fr::from(2)],
+            proof: vec![],
+        };
+        let bytes = proof.to_bytes();
+        assert!(matches!(
+            MptProof::from_bytes(&bytes[..bytes.len() - 1]),
+            Err(ProofBytesError::Truncated)
+        ));
+        assert!(matches!(
+            MptProof::from_bytes(&[0xff; 4]),
+            Err(ProofBytesError::Truncated)
+        ));
+    }
+}
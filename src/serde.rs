@@ -8,6 +8,9 @@ use serde::{
 };
 use std::fmt::{Debug, Display, Formatter};
 
+pub mod binary;
+pub mod reader;
+
 /// Indicate the operation type of a row in MPT circuit
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum HashType {
@@ -325,7 +328,7 @@ pub struct StateData {
 }
 
 /// represent an updating on SMT, can convert into AccountOp
-#[derive(Debug, Deserialize, Serialize, Clone)]
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
 #[serde(rename_all(deserialize = "camelCase", serialize = "camelCase"))]
 pub struct SMTTrace {
     /// Address for the trace
@@ -348,3 +351,90 @@ pub struct SMTTrace {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub state_update: Option<[Option<StateData>; 2]>,
 }
+
+/// (De)serializes values containing field elements, for use with
+/// `#[serde(with = "crate::serde::fr")]`. Each field element is written as the `Hash` of its
+/// little endian bytes, as in `SMTTrace`.
+pub(crate) mod fr {
+    use super::{Hash, HexBytes};
+    use halo2_proofs::halo2curves::bn256::Fr;
+    use serde::{
+        de::{DeserializeOwned, Deserializer, Error},
+        ser::Serializer,
+        Deserialize, Serialize,
+    };
+
+    pub trait HashRepr: Sized {
+        type Repr: Serialize + DeserializeOwned;
+
+        fn to_hash_repr(&self) -> Self::Repr;
+
+        /// None if any of the hashes is not a canonical field element.
+        fn from_hash_repr(repr: Self::Repr) -> Option<Self>;
+    }
+
+    impl HashRepr for Fr {
+        type Repr = Hash;
+
+        fn to_hash_repr(&self) -> Hash {
+            HexBytes(self.to_bytes())
+        }
+
+        fn from_hash_repr(repr: Hash) -> Option<Self> {
+            Self::from_bytes(&repr.0).into()
+        }
+    }
+
+    impl<T: HashRepr> HashRepr for Option<T> {
+        type Repr = Option<T::Repr>;
+
+        fn to_hash_repr(&self) -> Self::Repr {
+            self.as_ref().map(T::to_hash_repr)
+        }
+
+        fn from_hash_repr(repr: Self::Repr) -> Option<Self> {
+            match repr {
+                None => Some(None),
+                Some(repr) => T::from_hash_repr(repr).map(Some),
+            }
+        }
+    }
+
+    impl<T: HashRepr> HashRepr for Vec<T> {
+        type Repr = Vec<T::Repr>;
+
+        fn to_hash_repr(&self) -> Self::Repr {
+            self.iter().map(T::to_hash_repr).collect()
+        }
+
+        fn from_hash_repr(repr: Self::Repr) -> Option<Self> {
+            repr.into_iter().map(T::from_hash_repr).collect()
+        }
+    }
+
+    impl<T: HashRepr, const N: usize> HashRepr for [T; N] {
+        type Repr = Vec<T::Repr>;
+
+        fn to_hash_repr(&self) -> Self::Repr {
+            self.iter().map(T::to_hash_repr).collect()
+        }
+
+        fn from_hash_repr(repr: Self::Repr) -> Option<Self> {
+            Vec::<T>::from_hash_repr(repr)?.try_into().ok()
+        }
+    }
+
+    pub fn serialize<T: HashRepr, S: Serializer>(
+        value: &T,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        value.to_hash_repr().serialize(serializer)
+    }
+
+    pub fn deserialize<'de, T: HashRepr, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<T, D::Error> {
+        T::from_hash_repr(T::Repr::deserialize(deserializer)?)
+            .ok_or_else(|| D::Error::custom("hash is not a canonical field element"))
+    }
+}
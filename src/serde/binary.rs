@@ -0,0 +1,483 @@
+//! Compact binary encoding of `SMTTrace`s.
+//!
+//! A file starts with the magic bytes `SMTT`, a version byte, and a flags byte (bit 0 is set if
+//! every trace is preceded by its proof type). Then comes a table of the distinct hashes in the
+//! file, in order of first use, followed by the traces. Hashes in the traces are indices into the
+//! table, so that siblings shared between the paths of consecutive updates are only stored once.
+//!
+//! Integers (lengths, hash indices, nonces, code sizes, and node types) are unsigned LEB128.
+//! `BigUint`s are a length followed by their little endian bytes, and `Option`s are a tag byte (0
+//! for `None` and 1 for `Some`) followed by the value if there is one.
+use super::{AccountData, Address, Hash, HexBytes, SMTNode, SMTPath, SMTTrace, StateData};
+use crate::MPTProofType;
+use num_bigint::BigUint;
+use std::collections::HashMap;
+use strum::IntoEnumIterator;
+
+const MAGIC: [u8; 4] = *b"SMTT";
+const VERSION: u8 = 1;
+const HAS_PROOF_TYPES: u8 = 1;
+
+#[derive(Clone, Debug, PartialEq, Eq, thiserror::Error)]
+/// Binary trace decoding errors. `offset` is the position of the byte in the input.
+pub enum BinaryTraceError {
+    #[error("input does not start with the binary trace magic bytes")]
+    /// not a binary trace file
+    BadMagic,
+    #[error("binary trace version {0} is not supported")]
+    /// file was written by a newer encoder
+    UnsupportedVersion(u8),
+    #[error("binary trace flags {0:#04x} are not supported")]
+    /// unknown bits are set in the flags byte
+    UnsupportedFlags(u8),
+    #[error("binary traces do not have proof types")]
+    /// decoding with proof types a file that was encoded without them
+    MissingProofTypes,
+    #[error("binary traces have proof types")]
+    /// decoding without proof types a file that was encoded with them
+    UnexpectedProofTypes,
+    #[error("input ends in the middle of a trace")]
+    /// not enough bytes for the value being decoded
+    Truncated,
+    #[error("integer at byte {offset} does not fit in 64 bits")]
+    /// LEB128 integer is too long
+    IntegerOverflow { offset: usize },
+    #[error("byte {offset} is {tag}, which is not an option tag")]
+    /// option tag is not 0 or 1
+    InvalidTag { offset: usize, tag: u8 },
+    #[error("byte {offset} is {index}, which is not a proof type")]
+    /// no proof type has the index as its discriminant
+    InvalidProofType { offset: usize, index: u64 },
+    #[error("hash index {index} at byte {offset} is not in the hash table")]
+    /// hash index is out of range
+    InvalidHashIndex { offset: usize, index: u64 },
+    #[error("{0} bytes left over after the last trace")]
+    /// input continues after the declared number of traces
+    TrailingBytes(usize),
+}
+
+/// Encodes `traces` in the binary format.
+pub fn to_bytes(traces: &[SMTTrace]) -> Vec<u8> {
+    let mut encoder = Encoder::default();
+    encoder.write_len(traces.len());
+    for trace in traces {
+        encoder.write_trace(trace);
+    }
+    encoder.finish(0)
+}
+
+/// Inverse of `to_bytes`.
+pub fn from_bytes(bytes: &[u8]) -> Result<Vec<SMTTrace>, BinaryTraceError> {
+    let mut decoder = Decoder::new(bytes, false)?;
+    let n_traces = decoder.read_len()?;
+    let traces = (0..n_traces)
+        .map(|_| decoder.read_trace())
+        .collect::<Result<_, _>>()?;
+    decoder.finish()?;
+    Ok(traces)
+}
+
+/// Encodes `traces` along with their proof types, as taken by `MptCircuit::new`.
+pub fn typed_to_bytes(traces: &[(MPTProofType, SMTTrace)]) -> Vec<u8> {
+    let mut encoder = Encoder::default();
+    encoder.write_len(traces.len());
+    for (proof_type, trace) in traces {
+        encoder.write_proof_type(*proof_type);
+        encoder.write_trace(trace);
+    }
+    encoder.finish(HAS_PROOF_TYPES)
+}
+
+/// Inverse of `typed_to_bytes`.
+pub fn typed_from_bytes(bytes: &[u8]) -> Result<Vec<(MPTProofType, SMTTrace)>, BinaryTraceError> {
+    let mut decoder = Decoder::new(bytes, true)?;
+    let n_traces = decoder.read_len()?;
+    let traces = (0..n_traces)
+        .map(|_| Ok::<_, BinaryTraceError>((decoder.read_proof_type()?, decoder.read_trace()?)))
+        .collect::<Result<_, _>>()?;
+    decoder.finish()?;
+    Ok(traces)
+}
+
+#[derive(Default)]
+struct Encoder {
+    hashes: Vec<Hash>,
+    hash_indices: HashMap<Hash, usize>,
+    body: Vec<u8>,
+}
+
+impl Encoder {
+    fn finish(self, flags: u8) -> Vec<u8> {
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend([VERSION, flags]);
+        write_u64(&mut bytes, self.hashes.len() as u64);
+        for hash in &self.hashes {
+            bytes.extend_from_slice(&hash.0);
+        }
+        bytes.extend(self.body);
+        bytes
+    }
+
+    fn write_u64(&mut self, x: u64) {
+        write_u64(&mut self.body, x);
+    }
+
+    fn write_len(&mut self, len: usize) {
+        self.write_u64(len as u64);
+    }
+
+    fn write_bytes(&mut self, bytes: &[u8]) {
+        self.write_len(bytes.len());
+        self.body.extend_from_slice(bytes);
+    }
+
+    fn write_option<T>(&mut self, x: &Option<T>, write: impl FnOnce(&mut Self, &T)) {
+        self.body.push(x.is_some().into());
+        if let Some(x) = x {
+            write(self, x);
+        }
+    }
+
+    fn write_hash(&mut self, hash: &Hash) {
+        let next_index = self.hashes.len();
+        let index = *self.hash_indices.entry(*hash).or_insert(next_index);
+        if index == next_index {
+            self.hashes.push(*hash);
+        }
+        self.write_len(index);
+    }
+
+    // Proof types are encoded by discriminant, as in the mpt table, so that reordering the
+    // variants of MPTProofType does not change the encoding.
+    fn write_proof_type(&mut self, proof_type: MPTProofType) {
+        self.write_u64(proof_type as u64);
+    }
+
+    fn write_trace(&mut self, trace: &SMTTrace) {
+        self.body.extend_from_slice(&trace.address.0);
+        self.write_hash(&trace.account_key);
+        for path in &trace.account_path {
+            self.write_path(path);
+        }
+        for account in &trace.account_update {
+            self.write_option(account, Self::write_account);
+        }
+        for path in &trace.state_path {
+            self.write_option(path, Self::write_path);
+        }
+        self.write_option(&trace.common_state_root, Self::write_hash);
+        self.write_option(&trace.state_key, Self::write_hash);
+        self.write_option(&trace.state_update, |encoder, state_update| {
+            for state in state_update {
+                encoder.write_option(state, |encoder, state| {
+                    encoder.write_hash(&state.key);
+                    encoder.write_hash(&state.value);
+                });
+            }
+        });
+    }
+
+    fn write_path(&mut self, path: &SMTPath) {
+        self.write_hash(&path.root);
+        self.write_option(&path.leaf, Self::write_node);
+        self.write_len(path.path.len());
+        for node in &path.path {
+            self.write_node(node);
+        }
+        self.write_bytes(&path.path_part.to_bytes_le());
+    }
+
+    fn write_node(&mut self, node: &SMTNode) {
+        self.write_hash(&node.value);
+        self.write_hash(&node.sibling);
+        self.write_u64(node.node_type);
+    }
+
+    fn write_account(&mut self, account: &AccountData) {
+        self.write_u64(account.nonce);
+        self.write_bytes(&account.balance.to_bytes_le());
+        self.write_bytes(&account.code_hash.to_bytes_le());
+        self.write_bytes(&account.poseidon_code_hash.to_bytes_le());
+        self.write_u64(account.code_size);
+    }
+}
+
+fn write_u64(bytes: &mut Vec<u8>, mut x: u64) {
+    while x >= 0x80 {
+        bytes.push((x as u8) | 0x80);
+        x >>= 7;
+    }
+    bytes.push(x as u8);
+}
+
+struct Decoder<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+    hashes: Vec<Hash>,
+}
+
+impl<'a> Decoder<'a> {
+    fn new(bytes: &'a [u8], proof_types: bool) -> Result<Self, BinaryTraceError> {
+        let mut decoder = Self {
+            bytes,
+            offset: 0,
+            hashes: vec![],
+        };
+        if decoder.read_slice(MAGIC.len()) != Ok(&MAGIC[..]) {
+            return Err(BinaryTraceError::BadMagic);
+        }
+        let version = decoder.read_u8()?;
+        if version != VERSION {
+            return Err(BinaryTraceError::UnsupportedVersion(version));
+        }
+        match (decoder.read_u8()?, proof_types) {
+            (0, false) | (HAS_PROOF_TYPES, true) => {}
+            (0, true) => return Err(BinaryTraceError::MissingProofTypes),
+            (HAS_PROOF_TYPES, false) => return Err(BinaryTraceError::UnexpectedProofTypes),
+            (flags, _) => return Err(BinaryTraceError::UnsupportedFlags(flags)),
+        }
+
+        let n_hashes = decoder.read_len()?;
+        // Check the length before allocating, so a corrupt count cannot cause a huge allocation.
+        if n_hashes > decoder.bytes.len() / 32 {
+            return Err(BinaryTraceError::Truncated);
+        }
+        let hashes = (0..n_hashes)
+            .map(|_| decoder.read_array().map(HexBytes))
+            .collect::<Result<_, _>>()?;
+        decoder.hashes = hashes;
+        Ok(decoder)
+    }
+
+    fn finish(self) -> Result<(), BinaryTraceError> {
+        match self.bytes.len() - self.offset {
+            0 => Ok(()),
+            n => Err(BinaryTraceError::TrailingBytes(n)),
+        }
+    }
+
+    fn read_slice(&mut self, len: usize) -> Result<&'a [u8], BinaryTraceError> {
+        let end = self
+            .offset
+            .checked_add(len)
+            .filter(|end| *end <= self.bytes.len())
+            .ok_or(BinaryTraceError::Truncated)?;
+        let slice = &self.bytes[self.offset..end];
+        self.offset = end;
+        Ok(slice)
+    }
+
+    fn read_array<const LEN: usize>(&mut self) -> Result<[u8; LEN], BinaryTraceError> {
+        Ok(self.read_slice(LEN)?.try_into().unwrap())
+    }
+
+    fn read_u8(&mut self) -> Result<u8, BinaryTraceError> {
+        let [byte] = self.read_array()?;
+        Ok(byte)
+    }
+
+    fn read_u64(&mut self) -> Result<u64, BinaryTraceError> {
+        let offset = self.offset;
+        let mut x = 0;
+        for shift in (0..64).step_by(7) {
+            let byte = self.read_u8()?;
+            let bits = u64::from(byte & 0x7f);
+            if bits << shift >> shift != bits {
+                return Err(BinaryTraceError::IntegerOverflow { offset });
+            }
+            x |= bits << shift;
+            if byte & 0x80 == 0 {
+                return Ok(x);
+            }
+        }
+        Err(BinaryTraceError::IntegerOverflow { offset })
+    }
+
+    fn read_len(&mut self) -> Result<usize, BinaryTraceError> {
+        let offset = self.offset;
+        let len = self.read_u64()?;
+        usize::try_from(len).map_err(|_| BinaryTraceError::IntegerOverflow { offset })
+    }
+
+    fn read_bytes(&mut self) -> Result<&'a [u8], BinaryTraceError> {
+        let len = self.read_len()?;
+        self.read_slice(len)
+    }
+
+    fn read_biguint(&mut self) -> Result<BigUint, BinaryTraceError> {
+        Ok(BigUint::from_bytes_le(self.read_bytes()?))
+    }
+
+    fn read_option<T>(
+        &mut self,
+        read: impl FnOnce(&mut Self) -> Result<T, BinaryTraceError>,
+    ) -> Result<Option<T>, BinaryTraceError> {
+        let offset = self.offset;
+        match self.read_u8()? {
+            0 => Ok(None),
+            1 => read(self).map(Some),
+            tag => Err(BinaryTraceError::InvalidTag { offset, tag }),
+        }
+    }
+
+    fn read_hash(&mut self) -> Result<Hash, BinaryTraceError> {
+        let offset = self.offset;
+        let index = self.read_u64()?;
+        usize::try_from(index)
+            .ok()
+            .and_then(|i| self.hashes.get(i))
+            .copied()
+            .ok_or(BinaryTraceError::InvalidHashIndex { offset, index })
+    }
+
+    fn read_proof_type(&mut self) -> Result<MPTProofType, BinaryTraceError> {
+        let offset = self.offset;
+        let index = self.read_u64()?;
+        MPTProofType::iter()
+            .find(|proof_type| *proof_type as u64 == index)
+            .ok_or(BinaryTraceError::InvalidProofType { offset, index })
+    }
+
+    fn read_trace(&mut self) -> Result<SMTTrace, BinaryTraceError> {
+        let address: Address = HexBytes(self.read_array()?);
+        let account_key = self.read_hash()?;
+        let account_path = [self.read_path()?, self.read_path()?];
+        let account_update = [
+            self.read_option(Self::read_account)?,
+            self.read_option(Self::read_account)?,
+        ];
+        let state_path = [
+            self.read_option(Self::read_path)?,
+            self.read_option(Self::read_path)?,
+        ];
+        let common_state_root = self.read_option(Self::read_hash)?;
+        let state_key = self.read_option(Self::read_hash)?;
+        let state_update = self.read_option(|decoder| {
+            Ok([
+                decoder.read_option(Self::read_state)?,
+                decoder.read_option(Self::read_state)?,
+            ])
+        })?;
+        Ok(SMTTrace {
+            address,
+            account_key,
+            account_path,
+            account_update,
+            state_path,
+            common_state_root,
+            state_key,
+            state_update,
+        })
+    }
+
+    fn read_path(&mut self) -> Result<SMTPath, BinaryTraceError> {
+        let root = self.read_hash()?;
+        let leaf = self.read_option(Self::read_node)?;
+        let n_nodes = self.read_len()?;
+        let path = (0..n_nodes)
+            .map(|_| self.read_node())
+            .collect::<Result<_, _>>()?;
+        let path_part = self.read_biguint()?;
+        Ok(SMTPath {
+            root,
+            leaf,
+            path,
+            path_part,
+        })
+    }
+
+    fn read_node(&mut self) -> Result<SMTNode, BinaryTraceError> {
+        Ok(SMTNode {
+            value: self.read_hash()?,
+            sibling: self.read_hash()?,
+            node_type: self.read_u64()?,
+        })
+    }
+
+    fn read_account(&mut self) -> Result<AccountData, BinaryTraceError> {
+        Ok(AccountData {
+            nonce: self.read_u64()?,
+            balance: self.read_biguint()?,
+            code_hash: self.read_biguint()?,
+            poseidon_code_hash: self.read_biguint()?,
+            code_size: self.read_u64()?,
+        })
+    }
+
+    fn read_state(&mut self) -> Result<StateData, BinaryTraceError> {
+        Ok(StateData {
+            key: self.read_hash()?,
+            value: self.read_hash()?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn benchmark_traces() -> Vec<(MPTProofType, SMTTrace)> {
+        serde_json::from_str(include_str!("../../benches/traces.json")).unwrap()
+    }
+
+    #[test]
+    fn round_trip() {
+        let traces = benchmark_traces();
+        let bytes = typed_to_bytes(&traces);
+        assert_eq!(typed_from_bytes(&bytes).unwrap(), traces);
+
+        let json = serde_json::to_vec(&traces).unwrap();
+        assert!(bytes.len() * 4 < json.len());
+
+        let traces: Vec<_> = traces.into_iter().map(|(_, trace)| trace).collect();
+        assert_eq!(from_bytes(&to_bytes(&traces)).unwrap(), traces);
+    }
+
+    #[test]
+    fn deduplicates_hashes() {
+        let trace: SMTTrace =
+            serde_json::from_str(include_str!("../traces/existing_storage_update.json")).unwrap();
+        let one = to_bytes(&[trace.clone()]);
+        let two = to_bytes(&[trace.clone(), trace]);
+
+        // The second copy of the trace only adds indices into the hash table.
+        assert!(two.len() - one.len() < (one.len() - MAGIC.len()) / 4);
+    }
+
+    #[test]
+    fn empty() {
+        assert_eq!(from_bytes(&to_bytes(&[])), Ok(vec![]));
+        assert_eq!(typed_from_bytes(&typed_to_bytes(&[])), Ok(vec![]));
+    }
+
+    #[test]
+    fn invalid_input() {
+        let traces = benchmark_traces();
+        let bytes = typed_to_bytes(&traces[..3]);
+
+        assert_eq!(
+            typed_from_bytes(&bytes[..bytes.len() - 1]),
+            Err(BinaryTraceError::Truncated)
+        );
+        assert_eq!(
+            typed_from_bytes(&[&bytes[..], &[0]].concat()),
+            Err(BinaryTraceError::TrailingBytes(1))
+        );
+        assert_eq!(
+            from_bytes(&bytes),
+            Err(BinaryTraceError::UnexpectedProofTypes)
+        );
+        assert_eq!(
+            typed_from_bytes(&to_bytes(&[])),
+            Err(BinaryTraceError::MissingProofTypes)
+        );
+        assert_eq!(typed_from_bytes(b"{}"), Err(BinaryTraceError::BadMagic));
+
+        let mut newer = bytes.clone();
+        newer[MAGIC.len()] = VERSION + 1;
+        assert_eq!(
+            typed_from_bytes(&newer),
+            Err(BinaryTraceError::UnsupportedVersion(VERSION + 1))
+        );
+    }
+}
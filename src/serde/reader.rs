@@ -0,0 +1,414 @@
+//! Streaming reader for witness files, which yields one trace at a time so that files with many
+//! blocks of traces can be processed without holding them all in memory.
+//!
+//! The raw bytes of each item are collected and then deserialized on their own. The rest of the
+//! file is only scanned for the JSON structure around the items.
+use super::SMTTrace;
+use crate::{validation::check_proof_type, MPTProofType};
+use std::{
+    fs::File,
+    io::{self, BufRead, BufReader},
+    path::Path,
+};
+use strum::IntoEnumIterator;
+
+/// Layout of the traces in a witness file.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TraceFormat {
+    /// A json array of `[proof_type, trace]` pairs, e.g. `benches/traces.json`.
+    JsonArray,
+    /// One `[proof_type, trace]` pair per line. Blank lines are skipped.
+    JsonLines,
+    /// An l2geth `BlockResult` object, with the traces in its `mptwitness` array. The traces have
+    /// no proof types, so each one gets the first proof type that `check_proof_type` accepts.
+    BlockResult,
+}
+
+#[derive(Debug, thiserror::Error)]
+/// Trace reading errors. `index` is the position of the item being read when the error happened,
+/// counting from 0.
+pub enum TraceReadError {
+    #[error("item {index}: {source}")]
+    /// the underlying reader failed
+    Io { index: usize, source: io::Error },
+    #[error("item {index}: expected {expected}")]
+    /// the file does not have the json structure of its format
+    Syntax {
+        index: usize,
+        expected: &'static str,
+    },
+    #[error("item {index}: {source}")]
+    /// the item is valid json, but not a trace
+    Json {
+        index: usize,
+        source: serde_json::Error,
+    },
+    #[error("item {index}: no proof type is consistent with the trace")]
+    /// the trace in a `BlockResult` changes several account fields at once, or is invalid
+    UnknownProofType { index: usize },
+}
+
+impl TraceReadError {
+    /// Index of the item the error is for.
+    pub fn index(&self) -> usize {
+        match self {
+            Self::Io { index, .. }
+            | Self::Syntax { index, .. }
+            | Self::Json { index, .. }
+            | Self::UnknownProofType { index } => *index,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum State {
+    Start,
+    // Before an item of the traces array. `first` is true if no items have been read yet.
+    InArray { first: bool },
+    Done,
+}
+
+/// Iterator over the `(MPTProofType, SMTTrace)` items of a witness file. It stops after the first
+/// error.
+pub struct TraceReader<R> {
+    reader: R,
+    format: TraceFormat,
+    state: State,
+    index: usize,
+    buffer: Vec<u8>,
+}
+
+impl TraceReader<BufReader<File>> {
+    /// Opens the witness file at `path`.
+    pub fn open(path: impl AsRef<Path>, format: TraceFormat) -> io::Result<Self> {
+        Ok(Self::new(BufReader::new(File::open(path)?), format))
+    }
+}
+
+impl<R: BufRead> TraceReader<R> {
+    pub fn new(reader: R, format: TraceFormat) -> Self {
+        Self {
+            reader,
+            format,
+            state: State::Start,
+            index: 0,
+            buffer: vec![],
+        }
+    }
+
+    /// Number of items read so far.
+    pub fn n_items(&self) -> usize {
+        self.index
+    }
+
+    fn next_item(&mut self) -> Result<Option<(MPTProofType, SMTTrace)>, TraceReadError> {
+        if self.format == TraceFormat::JsonLines && self.state != State::Done {
+            return self.next_line();
+        }
+        loop {
+            match self.state {
+                State::Done => return Ok(None),
+                State::Start => {
+                    self.skip_whitespace()?;
+                    self.state = match self.format {
+                        TraceFormat::BlockResult => self.find_witness_array()?,
+                        _ => {
+                            self.expect(b'[', "'[' at the start of the traces")?;
+                            State::InArray { first: true }
+                        }
+                    };
+                }
+                State::InArray { first } => {
+                    self.skip_whitespace()?;
+                    if self.peek()? == Some(b']') {
+                        self.reader.consume(1);
+                        self.state = State::Done;
+                        continue;
+                    }
+                    if !first {
+                        self.expect(b',', "',' or ']' after a trace")?;
+                        self.skip_whitespace()?;
+                    }
+                    self.state = State::InArray { first: false };
+
+                    self.buffer.clear();
+                    self.read_value(true)?;
+                    return self.parse_item().map(Some);
+                }
+            }
+        }
+    }
+
+    fn next_line(&mut self) -> Result<Option<(MPTProofType, SMTTrace)>, TraceReadError> {
+        loop {
+            self.buffer.clear();
+            let n_bytes = self
+                .reader
+                .read_until(b'\n', &mut self.buffer)
+                .map_err(|source| self.io_error(source))?;
+            if n_bytes == 0 {
+                return Ok(None);
+            }
+            if !self.buffer.iter().all(u8::is_ascii_whitespace) {
+                return self.parse_item().map(Some);
+            }
+        }
+    }
+
+    // Reads the keys of the `BlockResult` object up to the start of the `mptwitness` array.
+    fn find_witness_array(&mut self) -> Result<State, TraceReadError> {
+        self.expect(b'{', "'{' at the start of the block result")?;
+        let mut first = true;
+        loop {
+            self.skip_whitespace()?;
+            if self.peek()? == Some(b'}') {
+                return Ok(State::Done);
+            }
+            if !first {
+                self.expect(b',', "',' or '}' after a block result field")?;
+                self.skip_whitespace()?;
+            }
+            first = false;
+
+            self.buffer.clear();
+            self.read_value(true)?;
+            let key: String = serde_json::from_slice(&self.buffer)
+                .map_err(|_| self.syntax_error("a string key in the block result"))?;
+            self.skip_whitespace()?;
+            self.expect(b':', "':' after a block result key")?;
+            self.skip_whitespace()?;
+
+            if key == "mptwitness" {
+                if self.peek()? == Some(b'n') {
+                    // null, as for blocks without any state changes
+                    self.read_value(false)?;
+                    return Ok(State::Done);
+                }
+                self.expect(b'[', "'[' at the start of mptwitness")?;
+                return Ok(State::InArray { first: true });
+            }
+            self.read_value(false)?;
+        }
+    }
+
+    fn parse_item(&mut self) -> Result<(MPTProofType, SMTTrace), TraceReadError> {
+        let index = self.index;
+        self.index += 1;
+        let json_error = |source| TraceReadError::Json { index, source };
+        if self.format != TraceFormat::BlockResult {
+            return serde_json::from_slice(&self.buffer).map_err(json_error);
+        }
+
+        let trace: SMTTrace = serde_json::from_slice(&self.buffer).map_err(json_error)?;
+        let proof_type = MPTProofType::iter()
+            .find(|proof_type| check_proof_type(&trace, *proof_type).is_ok())
+            .ok_or(TraceReadError::UnknownProofType { index })?;
+        Ok((proof_type, trace))
+    }
+
+    // Reads one json value, appending its bytes to the buffer if `keep` is true. Only the
+    // structure of the value is checked here, serde_json checks the rest.
+    fn read_value(&mut self, keep: bool) -> Result<(), TraceReadError> {
+        let mut depth = 0usize;
+        let mut in_string = false;
+        let mut escaped = false;
+        let mut n_bytes = 0;
+        loop {
+            let byte = match self.peek()? {
+                Some(byte) => byte,
+                // A number or literal can end at the end of the input.
+                None if depth == 0 && !in_string && n_bytes > 0 => return Ok(()),
+                None => return Err(self.syntax_error("the rest of the value")),
+            };
+            let is_scalar = depth == 0 && !in_string && !matches!(byte, b'"' | b'{' | b'[');
+            if is_scalar && (byte.is_ascii_whitespace() || matches!(byte, b',' | b'}' | b']')) {
+                return if n_bytes > 0 {
+                    Ok(())
+                } else {
+                    Err(self.syntax_error("a json value"))
+                };
+            }
+            self.reader.consume(1);
+            n_bytes += 1;
+            if keep {
+                self.buffer.push(byte);
+            }
+
+            if in_string {
+                match (escaped, byte) {
+                    (true, _) => escaped = false,
+                    (false, b'\\') => escaped = true,
+                    (false, b'"') => in_string = false,
+                    _ => {}
+                }
+            } else {
+                match byte {
+                    b'"' => in_string = true,
+                    b'{' | b'[' => depth += 1,
+                    b'}' | b']' => depth -= 1,
+                    _ => {}
+                }
+            }
+            if depth == 0 && !in_string && !is_scalar {
+                return Ok(());
+            }
+        }
+    }
+
+    fn skip_whitespace(&mut self) -> Result<(), TraceReadError> {
+        while self
+            .peek()?
+            .map_or(false, |byte| byte.is_ascii_whitespace())
+        {
+            self.reader.consume(1);
+        }
+        Ok(())
+    }
+
+    fn expect(&mut self, byte: u8, expected: &'static str) -> Result<(), TraceReadError> {
+        if self.peek()? != Some(byte) {
+            return Err(self.syntax_error(expected));
+        }
+        self.reader.consume(1);
+        Ok(())
+    }
+
+    fn peek(&mut self) -> Result<Option<u8>, TraceReadError> {
+        let index = self.index;
+        match self.reader.fill_buf() {
+            Ok(bytes) => Ok(bytes.first().copied()),
+            Err(source) => Err(TraceReadError::Io { index, source }),
+        }
+    }
+
+    fn io_error(&self, source: io::Error) -> TraceReadError {
+        TraceReadError::Io {
+            index: self.index,
+            source,
+        }
+    }
+
+    fn syntax_error(&self, expected: &'static str) -> TraceReadError {
+        TraceReadError::Syntax {
+            index: self.index,
+            expected,
+        }
+    }
+}
+
+impl<R: BufRead> Iterator for TraceReader<R> {
+    type Item = Result<(MPTProofType, SMTTrace), TraceReadError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let item = self.next_item();
+        if item.is_err() {
+            self.state = State::Done;
+        }
+        item.transpose()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn benchmark_json() -> &'static str {
+        include_str!("../../benches/traces.json")
+    }
+
+    fn benchmark_traces() -> Vec<(MPTProofType, SMTTrace)> {
+        serde_json::from_str(benchmark_json()).unwrap()
+    }
+
+    fn read(bytes: &[u8], format: TraceFormat) -> Vec<Result<(MPTProofType, SMTTrace), String>> {
+        TraceReader::new(bytes, format)
+            .map(|item| item.map_err(|e| e.to_string()))
+            .collect()
+    }
+
+    fn read_all(bytes: &[u8], format: TraceFormat) -> Vec<(MPTProofType, SMTTrace)> {
+        TraceReader::new(bytes, format)
+            .collect::<Result<_, _>>()
+            .unwrap()
+    }
+
+    #[test]
+    fn json_array() {
+        let traces = benchmark_traces();
+        assert_eq!(
+            read_all(benchmark_json().as_bytes(), TraceFormat::JsonArray),
+            traces
+        );
+
+        let pretty = serde_json::to_vec_pretty(&traces[..5]).unwrap();
+        assert_eq!(read_all(&pretty, TraceFormat::JsonArray), traces[..5]);
+        assert_eq!(read_all(b" [ ] ", TraceFormat::JsonArray), vec![]);
+    }
+
+    #[test]
+    fn json_lines() {
+        let traces = benchmark_traces();
+        let mut lines = vec![];
+        for trace in &traces[..10] {
+            serde_json::to_writer(&mut lines, trace).unwrap();
+            lines.extend_from_slice(b"\n\n");
+        }
+        assert_eq!(read_all(&lines, TraceFormat::JsonLines), traces[..10]);
+    }
+
+    #[test]
+    fn block_result() {
+        let traces = benchmark_traces();
+        let witness: Vec<_> = traces.iter().map(|(_, trace)| trace).collect();
+        let block = format!(
+            r#"{{"blockTrace": {{"number": "0x1", "coinbase": {{"address": "0x\"}}]"}}}},
+                "txs": [[], {{}}], "gas": 21000, "null": null,
+                "mptwitness": {}, "withdraw_trie_root": "0x00"}}"#,
+            serde_json::to_string(&witness).unwrap()
+        );
+
+        let read = read_all(block.as_bytes(), TraceFormat::BlockResult);
+        assert_eq!(read.len(), traces.len());
+        for ((proof_type, trace), (_, expected)) in read.iter().zip(&traces) {
+            assert_eq!(trace, expected);
+            assert_eq!(check_proof_type(trace, *proof_type), Ok(()));
+        }
+
+        for block in [r#"{"gas": 1}"#, r#"{"mptwitness": null}"#, "{}"] {
+            assert_eq!(read_all(block.as_bytes(), TraceFormat::BlockResult), vec![]);
+        }
+    }
+
+    #[test]
+    fn error_indices() {
+        let traces = benchmark_traces();
+        let mut json = serde_json::to_value(&traces[..4]).unwrap();
+        json[2][1]["accountKey"] = "0xzz".into();
+        let json = json.to_string();
+
+        let items = read(json.as_bytes(), TraceFormat::JsonArray);
+        assert_eq!(items.len(), 3);
+        assert!(items[..2].iter().all(Result::is_ok));
+        assert!(items[2].as_ref().unwrap_err().starts_with("item 2: "));
+
+        let truncated = &benchmark_json()[..benchmark_json().len() / 2];
+        let error = TraceReader::new(truncated.as_bytes(), TraceFormat::JsonArray)
+            .find_map(Result::err)
+            .unwrap();
+        assert!(matches!(error, TraceReadError::Syntax { .. }));
+        assert!(error.index() > 0);
+
+        let item = serde_json::to_string(&traces[0]).unwrap();
+        let missing_comma = format!("[{item} {item}]");
+        assert_eq!(
+            read(missing_comma.as_bytes(), TraceFormat::JsonArray)[1],
+            Err("item 1: expected ',' or ']' after a trace".to_string())
+        );
+        assert_eq!(
+            read(b"{\"mptwitness\": 1}", TraceFormat::BlockResult),
+            vec![Err(
+                "item 0: expected '[' at the start of mptwitness".to_string()
+            )]
+        );
+    }
+}
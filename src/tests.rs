@@ -1,9 +1,20 @@
-use crate::{circuit::TestCircuit, serde::SMTTrace, types::Proof, MPTProofType, MptCircuitConfig};
+use crate::{
+    circuit::TestCircuit,
+    constraint_builder::{ColumnKind, ConstraintBuilder, NativeFailure, WitnessTable},
+    diagnostics::RowOwner,
+    gadgets::{mpt_update::SegmentType, poseidon::PoseidonTable},
+    hash_traces,
+    mpt::{CapacityError, Gadget},
+    serde::SMTTrace,
+    types::Proof,
+    zktrie::StateOp,
+    MPTProofType, MptCircuit, MptCircuitConfig, MptCircuitOptions, StateTransitionCircuit,
+};
 use ethers_core::types::{Address, U256};
 use halo2_proofs::{
     dev::MockProver,
     halo2curves::bn256::{Bn256, Fr},
-    plonk::{keygen_vk, Circuit, ConstraintSystem},
+    plonk::{keygen_vk, Advice, Circuit, Column, ConstraintSystem, FirstPhase},
     poly::kzg::commitment::ParamsKZG,
 };
 use mpt_zktrie::state::{builder::HASH_SCHEME_DONE, witness::WitnessGenerator, ZktrieState};
@@ -59,6 +70,18 @@ fn mock_prove(witness: Vec<(MPTProofType, SMTTrace)>) {
     assert_eq!(prover.verify(), Ok(()),);
 }
 
+// Like `mock_prove`, but evaluates the constraints natively, which is much faster.
+fn native_prove(witness: Vec<(MPTProofType, SMTTrace)>) {
+    let proofs: Vec<_> = witness
+        .into_iter()
+        .map(Proof::from_trace_unchecked)
+        .collect();
+    let (poseidon, config, cb) = native_config();
+    let randomness = Fr::from(0x1234);
+    let table = native_table(&poseidon, &config, &proofs, randomness);
+    assert_eq!(cb.evaluate(&table, &[randomness]), Ok(()));
+}
+
 #[test]
 fn degree() {
     let mut meta = ConstraintSystem::<Fr>::default();
@@ -94,6 +117,103 @@ fn verifying_key_constant() {
     );
 }
 
+#[test]
+fn mpt_circuit_with_poseidon_hash_circuit() {
+    let trace: SMTTrace = serde_json::from_str(include_str!(
+        "traces/empty_account_type_1_balance_update.json"
+    ))
+    .unwrap();
+    let circuit = MptCircuit::new(N_ROWS, 100, vec![(MPTProofType::BalanceChanged, trace)]);
+    let prover = MockProver::<Fr>::run(14, &circuit, vec![]).unwrap();
+    assert_eq!(prover.verify(), Ok(()));
+}
+
+#[test]
+fn mpt_circuit_all_padding() {
+    let circuit = StateTransitionCircuit::new(N_ROWS, 100, vec![]);
+    assert_eq!(circuit.instances(), vec![vec![Fr::from(0); 3]]);
+    let prover = MockProver::<Fr>::run(14, &circuit, circuit.instances()).unwrap();
+    assert_eq!(prover.verify(), Ok(()));
+}
+
+#[test]
+fn mpt_circuit_wrong_public_inputs() {
+    let trace: SMTTrace = serde_json::from_str(include_str!(
+        "traces/empty_account_type_1_balance_update.json"
+    ))
+    .unwrap();
+    let circuit =
+        StateTransitionCircuit::new(N_ROWS, 100, vec![(MPTProofType::BalanceChanged, trace)]);
+    let instances = circuit.instances();
+    assert_eq!(instances[0][2], Fr::from(1));
+
+    for (i, wrong_value) in [(0, instances[0][1]), (1, instances[0][0]), (2, Fr::from(2))] {
+        let mut wrong_instances = instances.clone();
+        wrong_instances[0][i] = wrong_value;
+        let prover = MockProver::<Fr>::run(14, &circuit, wrong_instances).unwrap();
+        assert!(prover.verify().is_err());
+    }
+}
+
+fn consecutive_balance_updates() -> Vec<(MPTProofType, SMTTrace)> {
+    crate::zktrie::WitnessGenerator::default()
+        .apply_all((1..5).map(|i| StateOp::SetBalance {
+            address: Address::repeat_byte(i),
+            balance: U256::from(i),
+        }))
+        .unwrap()
+}
+
+#[test]
+fn mpt_circuit_consecutive_updates() {
+    let traces = consecutive_balance_updates();
+    let circuit = StateTransitionCircuit::new(N_ROWS, 100, traces);
+    let prover = MockProver::<Fr>::run(14, &circuit, circuit.instances()).unwrap();
+    assert_eq!(prover.verify(), Ok(()));
+}
+
+#[test]
+fn block_of_state_ops() {
+    let mut generator = crate::zktrie::WitnessGenerator::default();
+    let [a, b] = [1, 2].map(Address::repeat_byte);
+    let traces = generator.apply_all([
+        StateOp::SetBalance {
+            address: a,
+            balance: U256::from(100),
+        },
+        StateOp::SetNonce {
+            address: b,
+            nonce: 1,
+        },
+        StateOp::WriteStorage {
+            address: a,
+            key: U256::from(3),
+            value: U256::from(4),
+        },
+        StateOp::AddBalance {
+            address: b,
+            amount: U256::from(50),
+        },
+        StateOp::ReadMissingAccount {
+            address: Address::repeat_byte(3),
+        },
+        StateOp::ReadStorage {
+            address: a,
+            key: U256::from(5),
+        },
+    ]);
+    mock_prove(traces.unwrap());
+}
+
+#[test]
+fn mpt_circuit_nonconsecutive_updates() {
+    let mut traces = consecutive_balance_updates();
+    traces.swap(1, 2);
+    let circuit = StateTransitionCircuit::new(N_ROWS, 100, traces);
+    let prover = MockProver::<Fr>::run(14, &circuit, circuit.instances()).unwrap();
+    assert!(prover.verify().is_err());
+}
+
 #[test]
 fn all_padding() {
     mock_prove(vec![]);
@@ -124,7 +244,7 @@ fn empty_account_type_1() {
         assert!(path.leaf.is_some(), "account is not type 1");
     }
 
-    let proof = Proof::from((MPTProofType::AccountDoesNotExist, trace.clone()));
+    let proof = Proof::from_trace_unchecked((MPTProofType::AccountDoesNotExist, trace.clone()));
     proof.check();
 
     mock_prove(vec![(MPTProofType::AccountDoesNotExist, trace)]);
@@ -155,7 +275,7 @@ fn empty_account_type_2() {
         assert!(path.leaf.is_none(), "account is not type 2");
     }
 
-    let proof = Proof::from((MPTProofType::AccountDoesNotExist, trace.clone()));
+    let proof = Proof::from_trace_unchecked((MPTProofType::AccountDoesNotExist, trace.clone()));
     proof.check();
 
     mock_prove(vec![(MPTProofType::AccountDoesNotExist, trace)]);
@@ -256,7 +376,7 @@ fn empty_account_proofs_for_empty_storage_updates() {
             );
         }
 
-        let proof = Proof::from((MPTProofType::StorageDoesNotExist, trace.clone()));
+        let proof = Proof::from_trace_unchecked((MPTProofType::StorageDoesNotExist, trace.clone()));
         proof.check();
         mock_prove(vec![(MPTProofType::StorageDoesNotExist, trace)]);
     }
@@ -281,7 +401,7 @@ fn existing_account_balance_update() {
         json
     );
     let trace: SMTTrace = serde_json::from_str(&json).unwrap();
-    let proof = Proof::from((MPTProofType::BalanceChanged, trace.clone()));
+    let proof = Proof::from_trace_unchecked((MPTProofType::BalanceChanged, trace.clone()));
     proof.check();
 
     mock_prove(vec![(MPTProofType::BalanceChanged, trace)]);
@@ -312,7 +432,7 @@ fn empty_account_type_1_balance_update() {
     );
 
     let trace: SMTTrace = serde_json::from_str(&json).unwrap();
-    let proof = Proof::from((MPTProofType::BalanceChanged, trace.clone()));
+    let proof = Proof::from_trace_unchecked((MPTProofType::BalanceChanged, trace.clone()));
     proof.check();
 
     mock_prove(vec![(MPTProofType::BalanceChanged, trace)]);
@@ -343,12 +463,50 @@ fn empty_account_type_2_balance_update() {
         "old account is not type 2"
     );
 
-    let proof = Proof::from((MPTProofType::BalanceChanged, trace.clone()));
+    let proof = Proof::from_trace_unchecked((MPTProofType::BalanceChanged, trace.clone()));
     proof.check();
 
     mock_prove(vec![(MPTProofType::BalanceChanged, trace)]);
 }
 
+#[test]
+fn account_destructed_type_1() {
+    let trace: SMTTrace = serde_json::from_str(include_str!(
+        "traces/empty_account_type_1_balance_update.json"
+    ))
+    .unwrap();
+    let trace = reverse(trace);
+
+    assert!(
+        trace.account_update[1].is_none() && trace.account_path[1].leaf.is_some(),
+        "new account is not type 1"
+    );
+
+    let proof = Proof::from_trace_unchecked((MPTProofType::AccountDestructed, trace.clone()));
+    proof.check();
+
+    mock_prove(vec![(MPTProofType::AccountDestructed, trace)]);
+}
+
+#[test]
+fn account_destructed_type_2() {
+    let trace: SMTTrace = serde_json::from_str(include_str!(
+        "traces/empty_account_type_2_balance_update.json"
+    ))
+    .unwrap();
+    let trace = reverse(trace);
+
+    assert!(
+        trace.account_update[1].is_none() && trace.account_path[1].leaf.is_none(),
+        "new account is not type 2"
+    );
+
+    let proof = Proof::from_trace_unchecked((MPTProofType::AccountDestructed, trace.clone()));
+    proof.check();
+
+    mock_prove(vec![(MPTProofType::AccountDestructed, trace)]);
+}
+
 #[test]
 fn existing_account_nonce_update() {
     let mut generator = initial_generator();
@@ -368,7 +526,7 @@ fn existing_account_nonce_update() {
         json
     );
     let trace: SMTTrace = serde_json::from_str(&json).unwrap();
-    let proof = Proof::from((MPTProofType::NonceChanged, trace.clone()));
+    let proof = Proof::from_trace_unchecked((MPTProofType::NonceChanged, trace.clone()));
     proof.check();
 
     mock_prove(vec![(MPTProofType::NonceChanged, trace)]);
@@ -399,7 +557,7 @@ fn empty_account_type_1_nonce_update() {
     );
 
     let trace: SMTTrace = serde_json::from_str(&json).unwrap();
-    let proof = Proof::from((MPTProofType::NonceChanged, trace.clone()));
+    let proof = Proof::from_trace_unchecked((MPTProofType::NonceChanged, trace.clone()));
     proof.check();
 
     mock_prove(vec![(MPTProofType::NonceChanged, trace)]);
@@ -430,7 +588,7 @@ fn empty_account_type_2_nonce_update() {
         "old account is not type 2"
     );
 
-    let proof = Proof::from((MPTProofType::NonceChanged, trace.clone()));
+    let proof = Proof::from_trace_unchecked((MPTProofType::NonceChanged, trace.clone()));
     proof.check();
 
     mock_prove(vec![(MPTProofType::NonceChanged, trace)]);
@@ -460,7 +618,7 @@ fn existing_account_code_size_update() {
         json
     );
     let trace: SMTTrace = serde_json::from_str(&json).unwrap();
-    let proof = Proof::from((MPTProofType::CodeSizeExists, trace.clone()));
+    let proof = Proof::from_trace_unchecked((MPTProofType::CodeSizeExists, trace.clone()));
     proof.check();
 
     mock_prove(vec![(MPTProofType::CodeSizeExists, trace)]);
@@ -485,7 +643,7 @@ fn existing_account_keccak_codehash_update() {
         json
     );
     let trace: SMTTrace = serde_json::from_str(&json).unwrap();
-    let proof = Proof::from((MPTProofType::CodeHashExists, trace.clone()));
+    let proof = Proof::from_trace_unchecked((MPTProofType::CodeHashExists, trace.clone()));
     proof.check();
 
     mock_prove(vec![(MPTProofType::CodeHashExists, trace)]);
@@ -510,7 +668,7 @@ fn existing_account_poseidon_codehash_update() {
         json
     );
     let trace: SMTTrace = serde_json::from_str(&json).unwrap();
-    let proof = Proof::from((MPTProofType::PoseidonCodeHashExists, trace.clone()));
+    let proof = Proof::from_trace_unchecked((MPTProofType::PoseidonCodeHashExists, trace.clone()));
     proof.check();
 
     mock_prove(vec![(MPTProofType::PoseidonCodeHashExists, trace)]);
@@ -535,7 +693,7 @@ fn existing_storage_update() {
         json
     );
     let trace: SMTTrace = serde_json::from_str(&json).unwrap();
-    let proof = Proof::from((MPTProofType::StorageChanged, trace.clone()));
+    let proof = Proof::from_trace_unchecked((MPTProofType::StorageChanged, trace.clone()));
     proof.check();
 
     mock_prove(vec![(MPTProofType::StorageChanged, trace)]);
@@ -575,11 +733,13 @@ fn empty_storage_type_1_update_a() {
         7
     );
 
-    let insertion_proof = Proof::from((MPTProofType::StorageChanged, trace.clone()));
+    let insertion_proof =
+        Proof::from_trace_unchecked((MPTProofType::StorageChanged, trace.clone()));
     insertion_proof.check();
     mock_prove(vec![(MPTProofType::StorageChanged, trace.clone())]);
 
-    let deletion_proof = Proof::from((MPTProofType::StorageChanged, reverse(trace.clone())));
+    let deletion_proof =
+        Proof::from_trace_unchecked((MPTProofType::StorageChanged, reverse(trace.clone())));
     deletion_proof.check();
     mock_prove(vec![(MPTProofType::StorageChanged, reverse(trace))]);
 }
@@ -618,11 +778,13 @@ fn empty_storage_type_2_update_a() {
         7
     );
 
-    let insertion_proof = Proof::from((MPTProofType::StorageChanged, trace.clone()));
+    let insertion_proof =
+        Proof::from_trace_unchecked((MPTProofType::StorageChanged, trace.clone()));
     insertion_proof.check();
     mock_prove(vec![(MPTProofType::StorageChanged, trace.clone())]);
 
-    let deletion_proof = Proof::from((MPTProofType::StorageChanged, reverse(trace.clone())));
+    let deletion_proof =
+        Proof::from_trace_unchecked((MPTProofType::StorageChanged, reverse(trace.clone())));
     deletion_proof.check();
     mock_prove(vec![(MPTProofType::StorageChanged, reverse(trace))]);
 }
@@ -661,11 +823,13 @@ fn empty_storage_type_2_update_b() {
         8
     );
 
-    let insertion_proof = Proof::from((MPTProofType::StorageChanged, trace.clone()));
+    let insertion_proof =
+        Proof::from_trace_unchecked((MPTProofType::StorageChanged, trace.clone()));
     insertion_proof.check();
     mock_prove(vec![(MPTProofType::StorageChanged, trace.clone())]);
 
-    let deletion_proof = Proof::from((MPTProofType::StorageChanged, reverse(trace.clone())));
+    let deletion_proof =
+        Proof::from_trace_unchecked((MPTProofType::StorageChanged, reverse(trace.clone())));
     deletion_proof.check();
     mock_prove(vec![(MPTProofType::StorageChanged, reverse(trace))]);
 }
@@ -711,11 +875,13 @@ fn empty_storage_type_1_update_b() {
         8
     );
 
-    let insertion_proof = Proof::from((MPTProofType::StorageChanged, trace.clone()));
+    let insertion_proof =
+        Proof::from_trace_unchecked((MPTProofType::StorageChanged, trace.clone()));
     insertion_proof.check();
     mock_prove(vec![(MPTProofType::StorageChanged, trace.clone())]);
 
-    let deletion_proof = Proof::from((MPTProofType::StorageChanged, reverse(trace.clone())));
+    let deletion_proof =
+        Proof::from_trace_unchecked((MPTProofType::StorageChanged, reverse(trace.clone())));
     deletion_proof.check();
     mock_prove(vec![(MPTProofType::StorageChanged, reverse(trace))]);
 }
@@ -754,19 +920,19 @@ fn empty_storage_type_1_update_c() {
         6
     );
 
-    let insertion_proof = Proof::from((MPTProofType::StorageChanged, trace.clone()));
+    let insertion_proof =
+        Proof::from_trace_unchecked((MPTProofType::StorageChanged, trace.clone()));
     insertion_proof.check();
     mock_prove(vec![(MPTProofType::StorageChanged, trace.clone())]);
 
-    let deletion_proof = Proof::from((MPTProofType::StorageChanged, reverse(trace.clone())));
+    let deletion_proof =
+        Proof::from_trace_unchecked((MPTProofType::StorageChanged, reverse(trace.clone())));
     deletion_proof.check();
     mock_prove(vec![(MPTProofType::StorageChanged, reverse(trace))]);
 }
 
-#[test]
-fn multiple_updates() {
-    env_logger::init();
-    let witness = vec![
+fn multiple_updates_witness() -> Vec<(MPTProofType, SMTTrace)> {
+    vec![
         (
             MPTProofType::StorageChanged,
             serde_json::from_str(include_str!("traces/empty_storage_type_1_update_c.json"))
@@ -790,8 +956,18 @@ fn multiple_updates() {
             MPTProofType::AccountDoesNotExist,
             serde_json::from_str(include_str!("traces/empty_account_type_1.json")).unwrap(),
         ),
-    ];
-    mock_prove(witness);
+    ]
+}
+
+#[test]
+fn multiple_updates() {
+    env_logger::init();
+    mock_prove(multiple_updates_witness());
+}
+
+#[test]
+fn multiple_updates_native() {
+    native_prove(multiple_updates_witness());
 }
 
 #[test]
@@ -808,11 +984,13 @@ fn empty_storage_trie() {
     let json = serde_json::to_string_pretty(&trace).unwrap();
     let trace: SMTTrace = serde_json::from_str(&json).unwrap();
 
-    let insertion_proof = Proof::from((MPTProofType::StorageChanged, trace.clone()));
+    let insertion_proof =
+        Proof::from_trace_unchecked((MPTProofType::StorageChanged, trace.clone()));
     insertion_proof.check();
     mock_prove(vec![(MPTProofType::StorageChanged, trace.clone())]);
 
-    let deletion_proof = Proof::from((MPTProofType::StorageChanged, reverse(trace.clone())));
+    let deletion_proof =
+        Proof::from_trace_unchecked((MPTProofType::StorageChanged, reverse(trace.clone())));
     deletion_proof.check();
     mock_prove(vec![(MPTProofType::StorageChanged, reverse(trace))]);
 }
@@ -838,11 +1016,13 @@ fn singleton_storage_trie() {
     let json = serde_json::to_string_pretty(&trace).unwrap();
     let trace: SMTTrace = serde_json::from_str(&json).unwrap();
 
-    let insertion_proof = Proof::from((MPTProofType::StorageChanged, trace.clone()));
+    let insertion_proof =
+        Proof::from_trace_unchecked((MPTProofType::StorageChanged, trace.clone()));
     insertion_proof.check();
     mock_prove(vec![(MPTProofType::StorageChanged, trace.clone())]);
 
-    let deletion_proof = Proof::from((MPTProofType::StorageChanged, reverse(trace.clone())));
+    let deletion_proof =
+        Proof::from_trace_unchecked((MPTProofType::StorageChanged, reverse(trace.clone())));
     deletion_proof.check();
     mock_prove(vec![(MPTProofType::StorageChanged, reverse(trace))]);
 }
@@ -881,7 +1061,7 @@ fn depth_1_type_1_empty_storage() {
     let json = serde_json::to_string_pretty(&trace).unwrap();
     let trace: SMTTrace = serde_json::from_str(&json).unwrap();
 
-    let proof = Proof::from((MPTProofType::StorageDoesNotExist, trace.clone()));
+    let proof = Proof::from_trace_unchecked((MPTProofType::StorageDoesNotExist, trace.clone()));
     proof.check();
     mock_prove(vec![(MPTProofType::StorageDoesNotExist, trace)]);
 }
@@ -1050,7 +1230,11 @@ fn test_n_rows_required() {
     let trace: SMTTrace = serde_json::from_str(&json).unwrap();
 
     let witness = vec![(MPTProofType::AccountDoesNotExist, trace); 3000];
-    let proofs: Vec<_> = witness.clone().into_iter().map(Proof::from).collect();
+    let proofs: Vec<_> = witness
+        .clone()
+        .into_iter()
+        .map(Proof::from_trace_unchecked)
+        .collect();
 
     let n_rows_required = MptCircuitConfig::n_rows_required(&proofs);
 
@@ -1059,11 +1243,121 @@ fn test_n_rows_required() {
     assert_eq!(prover.verify(), Ok(()));
 }
 
+#[test]
+fn not_enough_rows() {
+    let witness = vec![(
+        MPTProofType::BalanceChanged,
+        serde_json::from_str(include_str!(
+            "traces/empty_account_type_1_balance_update.json"
+        ))
+        .unwrap(),
+    )];
+    let proofs: Vec<_> = witness
+        .clone()
+        .into_iter()
+        .map(Proof::from_trace_unchecked)
+        .collect();
+
+    assert_eq!(
+        MptCircuitConfig::check_capacity(&proofs, 1000),
+        Err(CapacityError {
+            gadget: Gadget::ByteBit,
+            required: 8 * 256 + 1,
+            available: 1000,
+        })
+    );
+
+    let circuit = TestCircuit::new(1000, witness);
+    assert!(MockProver::<Fr>::run(14, &circuit, vec![]).is_err());
+}
+
+#[test]
+fn no_padding_row() {
+    let witness = vec![
+        (
+            MPTProofType::BalanceChanged,
+            serde_json::from_str(include_str!(
+                "traces/empty_account_type_1_balance_update.json"
+            ))
+            .unwrap(),
+        );
+        1000
+    ];
+    let proofs: Vec<_> = witness
+        .into_iter()
+        .map(Proof::from_trace_unchecked)
+        .collect();
+    // The initial all-zero row and the rows of the updates, with no final padding row.
+    let n_rows = 1 + proofs.iter().map(Proof::n_rows).sum::<usize>();
+
+    assert_eq!(
+        MptCircuitConfig::check_capacity(&proofs, n_rows),
+        Err(CapacityError {
+            gadget: Gadget::MptUpdate,
+            required: n_rows + 1,
+            available: n_rows,
+        })
+    );
+}
+
+#[test]
+fn row_usage() {
+    let witness = vec![
+        (
+            MPTProofType::BalanceChanged,
+            serde_json::from_str(include_str!(
+                "traces/empty_account_type_1_balance_update.json"
+            ))
+            .unwrap(),
+        ),
+        (
+            MPTProofType::StorageChanged,
+            serde_json::from_str(include_str!("traces/existing_storage_update.json")).unwrap(),
+        ),
+    ];
+    let proofs: Vec<_> = witness
+        .clone()
+        .into_iter()
+        .map(Proof::from_trace_unchecked)
+        .collect();
+
+    let usage = MptCircuitConfig::row_usage(&proofs);
+    assert_eq!(
+        (usage.bottleneck(), usage.n_rows()),
+        MptCircuitConfig::bottleneck(&proofs)
+    );
+    assert_eq!(usage.bottleneck(), Gadget::ByteBit);
+    assert_eq!(
+        usage.proofs.iter().map(|(_, n_rows)| n_rows).sum::<usize>(),
+        usage.segment_types.values().sum::<usize>()
+    );
+    assert_eq!(
+        usage.proof_types.keys().copied().collect::<Vec<_>>(),
+        vec![MPTProofType::BalanceChanged, MPTProofType::StorageChanged]
+    );
+
+    let mut cs = ConstraintSystem::default();
+    TestCircuit::configure(&mut cs);
+    let k = usage.min_k(
+        &cs,
+        PoseidonTable::n_rows_required(hash_traces(&proofs).len()),
+    );
+    assert_eq!(k, 12);
+
+    let circuit = TestCircuit::new(usage.n_rows(), witness);
+    let prover = MockProver::<Fr>::run(k, &circuit, vec![]).unwrap();
+    assert_eq!(prover.verify(), Ok(()));
+}
+
 #[test]
 fn verify_benchmark_trace() {
     let witness: Vec<(MPTProofType, SMTTrace)> =
         serde_json::from_str(include_str!("../benches/traces.json")).unwrap();
-    let proofs: Vec<_> = witness.clone().into_iter().map(Proof::from).collect();
+    let proofs: Vec<_> = witness
+        .clone()
+        .into_iter()
+        .map(Proof::from_trace_unchecked)
+        .collect();
 
     let n_rows_required = MptCircuitConfig::n_rows_required(&proofs);
 
@@ -1071,3 +1365,297 @@ fn verify_benchmark_trace() {
     let prover = MockProver::<Fr>::run(14, &circuit, vec![]).unwrap();
     assert_eq!(prover.verify(), Ok(()));
 }
+
+#[test]
+fn describe_mpt_circuit() {
+    let mut cs = ConstraintSystem::default();
+    let poseidon = PoseidonTable::configure(&mut cs);
+    let challenge = cs.challenge_usable_after(FirstPhase);
+    let (_, description) = MptCircuitConfig::configure_with_description(
+        &mut cs,
+        challenge,
+        &poseidon,
+        MptCircuitOptions::default(),
+    );
+
+    assert_eq!(description.constraints.len(), cs.gates().len());
+    assert_eq!(description.lookups.len(), cs.lookups().len());
+    assert_eq!(
+        description.columns.len(),
+        cs.num_advice_columns() + cs.num_fixed_columns() + cs.num_instance_columns()
+    );
+    assert!(description
+        .columns
+        .iter()
+        .any(|column| column.kind == ColumnKind::Selector));
+    assert!(description
+        .columns
+        .iter()
+        .any(|column| column.kind == ColumnKind::SecondPhaseAdvice));
+    assert!(description
+        .constraints
+        .iter()
+        .all(|constraint| constraint.degree <= cs.degree()));
+}
+
+fn native_config() -> (PoseidonTable, MptCircuitConfig, ConstraintBuilder<Fr>) {
+    native_config_with_options(MptCircuitOptions::default())
+}
+
+fn native_config_with_options(
+    options: MptCircuitOptions,
+) -> (PoseidonTable, MptCircuitConfig, ConstraintBuilder<Fr>) {
+    let mut cs = ConstraintSystem::default();
+    let poseidon = PoseidonTable::configure(&mut cs);
+    let challenge = cs.challenge_usable_after(FirstPhase);
+    let (config, cb) =
+        MptCircuitConfig::configure_with_constraints(&mut cs, challenge, &poseidon, options);
+    (poseidon, config, cb)
+}
+
+fn native_table(
+    poseidon: &PoseidonTable,
+    config: &MptCircuitConfig,
+    proofs: &[Proof],
+    randomness: Fr,
+) -> WitnessTable<Fr> {
+    let mut table = WitnessTable::new(N_ROWS);
+    config
+        .assign_native(&mut table, proofs, N_ROWS, randomness)
+        .unwrap();
+    poseidon.load(&mut table, &hash_traces(proofs));
+    table
+}
+
+#[test]
+fn native_evaluation() {
+    let witness = vec![(
+        MPTProofType::BalanceChanged,
+        serde_json::from_str(include_str!("traces/existing_account_balance_update.json")).unwrap(),
+    )];
+    let proofs: Vec<_> = witness
+        .into_iter()
+        .map(Proof::from_trace_unchecked)
+        .collect();
+
+    let (poseidon, config, cb) = native_config();
+    let randomness = Fr::from(0x1234);
+    let mut table = native_table(&poseidon, &config, &proofs, randomness);
+    assert_eq!(cb.evaluate(&table, &[randomness]), Ok(()));
+
+    // The old hash of the first account trie row is the hash of its own poseidon lookup, and an
+    // input to the lookup of the row after it.
+    let (_, old_hash) = config.mpt_update().named_columns()[1];
+    let row = 2;
+    table.set_advice(old_hash, row, table.advice(old_hash, row) + Fr::one());
+    let failures = cb.evaluate(&table, &[randomness]).unwrap_err();
+    assert!(
+        failures
+            .iter()
+            .all(|failure| matches!(failure, NativeFailure::Lookup { .. })),
+        "{failures:?}"
+    );
+    assert_eq!(
+        failures
+            .iter()
+            .map(|failure| (failure.name(), failure.row()))
+            .collect::<Vec<_>>(),
+        [
+            ("poseidon hash correct for old common path", row),
+            ("poseidon hash correct for old common path", row + 1),
+        ]
+    );
+}
+
+#[test]
+fn build_without_one_constraint() {
+    let mut cs = ConstraintSystem::default();
+    let poseidon = PoseidonTable::configure(&mut cs);
+    let challenge = cs.challenge_usable_after(FirstPhase);
+    let (_, cb) = MptCircuitConfig::configure_with_constraints(
+        &mut cs,
+        challenge,
+        &poseidon,
+        MptCircuitOptions::default(),
+    );
+    let n_built = |cs: &ConstraintSystem<Fr>| cs.gates().len() + cs.lookups().len();
+
+    // Only the constraint at the index is left out, and not the others with the same name.
+    let names = cb.names();
+    let name = "binary column is 0 or 1";
+    assert!(names.iter().filter(|n| **n == name).count() > 1);
+    let index = names.iter().position(|n| *n == name).unwrap();
+
+    let n_before = n_built(&cs);
+    cb.build_without(&mut cs, &[index]);
+    assert_eq!(n_built(&cs) - n_before, names.len() - 1);
+}
+
+#[test]
+fn padding_update_cannot_be_counted() {
+    let witness = vec![(
+        MPTProofType::BalanceChanged,
+        serde_json::from_str(include_str!("traces/existing_account_balance_update.json")).unwrap(),
+    )];
+    let proofs: Vec<_> = witness
+        .into_iter()
+        .map(Proof::from_trace_unchecked)
+        .collect();
+
+    let (poseidon, config, cb) = native_config_with_options(MptCircuitOptions {
+        expose_roots: true,
+        expose_update_count: true,
+        chain_roots: false,
+    });
+    let randomness = Fr::from(0x1234);
+    let mut table = native_table(&poseidon, &config, &proofs, randomness);
+    assert_eq!(cb.evaluate(&table, &[randomness]), Ok(()));
+
+    // Count the first padding update as a non-padding update, and update the running values of
+    // the rows after it to match, so that only the padding form constraint is violated.
+    let columns = config.mpt_update().columns();
+    let [is_padding, last_new_root, n_updates] =
+        ["is_padding", "last_new_root", "n_updates"].map(|name| {
+            let (_, column) = columns
+                .iter()
+                .find(|(column_name, _)| *column_name == format!("root_transitions.{name}"))
+                .unwrap();
+            Column::<Advice>::try_from(*column).unwrap()
+        });
+    let first_padding_row = 1 + proofs[0].n_rows();
+    table.set_advice(is_padding, first_padding_row, Fr::zero());
+    for row in first_padding_row..N_ROWS {
+        table.set_advice(last_new_root, row, Fr::zero());
+        table.set_advice(n_updates, row, table.advice(n_updates, row) + Fr::one());
+    }
+
+    let failures = cb.evaluate(&table, &[randomness]).unwrap_err();
+    assert!(
+        failures.iter().any(|failure| matches!(
+            failure,
+            NativeFailure::Constraint {
+                name: "non-padding update is not proof that address 0 does not exist in empty mpt",
+                row,
+                ..
+            } if *row == first_padding_row
+        )),
+        "{failures:?}"
+    );
+}
+
+#[test]
+fn diagnostics() {
+    let witness = vec![
+        (
+            MPTProofType::BalanceChanged,
+            serde_json::from_str(include_str!(
+                "traces/empty_account_type_1_balance_update.json"
+            ))
+            .unwrap(),
+        ),
+        (
+            MPTProofType::StorageChanged,
+            serde_json::from_str(include_str!("traces/existing_storage_update.json")).unwrap(),
+        ),
+    ];
+    let proofs: Vec<_> = witness
+        .into_iter()
+        .map(Proof::from_trace_unchecked)
+        .collect();
+
+    let (poseidon, config, cb) = native_config();
+    let diagnostics = config.diagnostics(&proofs, &[]);
+    let storage_rows = 1 + proofs[0].n_rows()..1 + proofs[0].n_rows() + proofs[1].n_rows();
+    assert_eq!(diagnostics.row_owner(0), RowOwner::AllZero);
+    assert!(matches!(
+        diagnostics.row_owner(storage_rows.start),
+        RowOwner::Update {
+            proof: 1,
+            proof_type: MPTProofType::StorageChanged,
+            storage_key: Some(_),
+            segment_type: SegmentType::Start,
+            ..
+        }
+    ));
+    assert_eq!(diagnostics.row_owner(storage_rows.end), RowOwner::Padding);
+
+    let storage_trie_row = storage_rows
+        .clone()
+        .find(|row| {
+            matches!(
+                diagnostics.row_owner(*row),
+                RowOwner::Update {
+                    segment_type: SegmentType::StorageTrie,
+                    ..
+                }
+            )
+        })
+        .unwrap();
+    assert!(diagnostics
+        .row_owner(storage_trie_row)
+        .to_string()
+        .starts_with("proof #1 (StorageChanged, "));
+
+    let randomness = Fr::from(0x1234);
+    let mut table = native_table(&poseidon, &config, &proofs, randomness);
+    let (_, old_hash) = config.mpt_update().named_columns()[1];
+    table.set_advice(
+        old_hash,
+        storage_trie_row,
+        table.advice(old_hash, storage_trie_row) + Fr::one(),
+    );
+    let failures = cb.evaluate(&table, &[randomness]).unwrap_err();
+    assert!(failures.iter().any(|failure| diagnostics
+        .explain_native(failure)
+        .contains("proof #1 (StorageChanged, ")));
+}
+
+#[test]
+fn witness_dump() {
+    let witness = vec![(
+        MPTProofType::StorageChanged,
+        serde_json::from_str(include_str!("traces/existing_storage_update.json")).unwrap(),
+    )];
+    let proofs: Vec<_> = witness
+        .into_iter()
+        .map(Proof::from_trace_unchecked)
+        .collect();
+
+    let (_, config, _) = native_config();
+    let dump = config
+        .witness_dump(&proofs, N_ROWS, Fr::from(0x1234))
+        .unwrap();
+    for column in [
+        "mpt_update.old_hash",
+        "mpt_update.segment_type.StorageTrie",
+        "key_bit.bit",
+        "canonical_representation.rlc",
+        "byte_representation.byte",
+    ] {
+        assert!(dump.columns.iter().any(|name| name == column), "{column}");
+    }
+    assert_eq!(dump.rows.len(), N_ROWS);
+    assert_eq!(dump.rows[1].proof_type, "StorageChanged");
+    assert_eq!(dump.rows[1].segment_type, "Start");
+    assert_eq!(dump.rows[1].path_type, "Start");
+    assert!(dump
+        .rows
+        .iter()
+        .all(|row| row.values.len() == dump.columns.len()));
+
+    let mut csv = vec![];
+    dump.write_csv(&mut csv).unwrap();
+    let csv = String::from_utf8(csv).unwrap();
+    assert_eq!(csv.lines().count(), 1 + N_ROWS);
+    assert!(csv.starts_with("row,proof_type,segment_type,path_type,mpt_update.domain,"));
+    assert!(csv
+        .lines()
+        .nth(2)
+        .unwrap()
+        .starts_with("1,StorageChanged,Start,Start,"));
+
+    let mut json = vec![];
+    dump.write_json(&mut json).unwrap();
+    let json: serde_json::Value = serde_json::from_slice(&json).unwrap();
+    assert_eq!(json["rows"][1]["segment_type"], "Start");
+}
@@ -0,0 +1,351 @@
+//! Graphviz export of the account and storage trie paths that a set of updates touches.
+use crate::{
+    constraint_builder::format_constant,
+    gadgets::mpt_update::PathType,
+    types::{
+        storage::{StorageLeaf, StorageProof},
+        trie::TrieRows,
+        HashDomain, Proof,
+    },
+    util::hash_prefix,
+};
+use ethers_core::types::Address;
+use halo2_proofs::halo2curves::bn256::Fr;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    io::{self, Write},
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum Trie {
+    Account,
+    Storage(Address),
+}
+
+/// A trie node, identified by its trie and the directions from the root to it.
+type NodeId = (Trie, Vec<bool>);
+
+#[derive(Clone, Debug, Default)]
+struct TrieNode {
+    /// Domains of the node, in the order they were seen: its branch domains, and `Leaf` if an old
+    /// or new path ends at it.
+    domains: Vec<HashDomain>,
+    /// Old and new hash of the node, for each update whose path goes through it.
+    values: Vec<(usize, Fr, Fr)>,
+    /// Hash of the node, for each update that has it as a sibling.
+    siblings: Vec<(usize, Fr)>,
+    /// Values of the leaf, for each update whose old or new path ends at it.
+    leaves: Vec<(usize, &'static str, String)>,
+}
+
+/// Union of the trie paths of a set of updates, e.g. those of a block. Nodes at the same position
+/// in the same trie are merged, so a node lists the old and new hashes from every update that
+/// touched it.
+#[derive(Clone, Debug, Default)]
+pub struct TrieGraph {
+    nodes: BTreeMap<NodeId, TrieNode>,
+    /// Path types of the updates that go from a node to its child. Edges to siblings that are not
+    /// on any path have no path types.
+    edges: BTreeMap<(NodeId, NodeId), BTreeSet<PathType>>,
+}
+
+impl TrieGraph {
+    pub fn new(proofs: &[Proof]) -> Self {
+        let mut graph = Self::default();
+        for (i, proof) in proofs.iter().enumerate() {
+            let account_leaves = [
+                (&proof.old_account, &proof.old),
+                (&proof.new_account, &proof.new),
+            ]
+            .map(|(account, path)| match (account, path.leaf_data_hash) {
+                (Some(account), _) => Some(format!(
+                    "nonce {}, balance {}, code size {}",
+                    account.nonce,
+                    format_constant(&account.balance),
+                    account.code_size
+                )),
+                (None, Some(value_hash)) => Some(other_leaf(path.key, value_hash)),
+                (None, None) => None,
+            });
+            graph.add_path(
+                Trie::Account,
+                i,
+                &proof.account_trie_rows,
+                [proof.claim.old_root, proof.claim.new_root],
+                account_leaves,
+            );
+            if let StorageProof::Update {
+                trie_rows,
+                old_leaf,
+                new_leaf,
+                ..
+            } = &proof.storage
+            {
+                let storage_leaves = [old_leaf, new_leaf].map(|leaf| match leaf {
+                    StorageLeaf::Entry { storage_key, value } => {
+                        Some(format!("slot {storage_key:#x} = {value:#x}"))
+                    }
+                    StorageLeaf::Leaf {
+                        mpt_key,
+                        value_hash,
+                    } => Some(other_leaf(*mpt_key, *value_hash)),
+                    StorageLeaf::Empty { .. } => None,
+                });
+                graph.add_path(
+                    Trie::Storage(proof.claim.address),
+                    i,
+                    trie_rows,
+                    [proof.storage.old_root(), proof.storage.new_root()],
+                    storage_leaves,
+                );
+            }
+        }
+        graph
+    }
+
+    /// Adds the nodes on the path of `rows`, and `leaves`, the values of the leaves that the old
+    /// and new paths end at, if they do not end at an empty node.
+    fn add_path(
+        &mut self,
+        trie: Trie,
+        update: usize,
+        rows: &TrieRows,
+        [old_root, new_root]: [Fr; 2],
+        leaves: [Option<String>; 2],
+    ) {
+        let mut prefix = vec![];
+        self.node(trie, &prefix)
+            .values
+            .push((update, old_root, new_root));
+        for row in &rows.0 {
+            let parent = (trie, prefix.clone());
+            let domains = &mut self.node(trie, &prefix).domains;
+            if !domains.contains(&row.domain) {
+                domains.push(row.domain);
+            }
+
+            let mut sibling = prefix.clone();
+            sibling.push(!row.direction);
+            self.node(trie, &sibling)
+                .siblings
+                .push((update, row.sibling));
+            self.edges
+                .entry((parent.clone(), (trie, sibling)))
+                .or_default();
+
+            prefix.push(row.direction);
+            self.node(trie, &prefix)
+                .values
+                .push((update, row.old, row.new));
+            self.edges
+                .entry((parent, (trie, prefix.clone())))
+                .or_default()
+                .insert(row.path_type);
+        }
+
+        // A path that is extended in the other trie ends where the extension starts.
+        let n_common = rows
+            .0
+            .iter()
+            .take_while(|row| row.path_type == PathType::Common)
+            .count();
+        for ((side, leaf), extension) in ["old", "new"]
+            .into_iter()
+            .zip(leaves)
+            .zip([PathType::ExtensionNew, PathType::ExtensionOld])
+        {
+            let Some(leaf) = leaf else {
+                continue;
+            };
+            let depth = if rows.0.iter().any(|row| row.path_type == extension) {
+                n_common
+            } else {
+                rows.len()
+            };
+            let node = self.node(trie, &prefix[..depth]);
+            if !node.domains.contains(&HashDomain::Leaf) {
+                node.domains.push(HashDomain::Leaf);
+            }
+            node.leaves.push((update, side, leaf));
+        }
+    }
+
+    fn node(&mut self, trie: Trie, prefix: &[bool]) -> &mut TrieNode {
+        self.nodes.entry((trie, prefix.to_vec())).or_default()
+    }
+
+    /// Writes the graph in the DOT language, with one cluster per trie. Edges where the old path is
+    /// extended (`PathType::ExtensionOld`) are red, edges where the new path is extended
+    /// (`PathType::ExtensionNew`) are blue, and edges to siblings that are not on any path are
+    /// dashed.
+    pub fn write_dot(&self, mut writer: impl Write) -> io::Result<()> {
+        writeln!(writer, "digraph tries {{")?;
+        writeln!(writer, "    node [shape=box, fontname=monospace];")?;
+        let tries: BTreeSet<_> = self.nodes.keys().map(|(trie, _)| *trie).collect();
+        for (i, trie) in tries.into_iter().enumerate() {
+            let label = match trie {
+                Trie::Account => "account trie".to_string(),
+                Trie::Storage(address) => format!("storage trie of {address}"),
+            };
+            writeln!(writer, "    subgraph cluster_{i} {{")?;
+            writeln!(writer, "        label=\"{label}\";")?;
+            for (id, node) in self.nodes.iter().filter(|((t, _), _)| *t == trie) {
+                writeln!(
+                    writer,
+                    "        {} [label=\"{}\"];",
+                    node_name(id),
+                    node_label(id, node)
+                )?;
+            }
+            writeln!(writer, "    }}")?;
+        }
+        for ((parent, child), path_types) in &self.edges {
+            let attributes = match (
+                path_types.contains(&PathType::ExtensionOld),
+                path_types.contains(&PathType::ExtensionNew),
+            ) {
+                (false, false) if path_types.is_empty() => "style=dashed".to_string(),
+                (false, false) => String::new(),
+                (true, false) => "color=red, penwidth=2, label=\"ExtensionOld\"".to_string(),
+                (false, true) => "color=blue, penwidth=2, label=\"ExtensionNew\"".to_string(),
+                (true, true) => {
+                    "color=purple, penwidth=2, label=\"ExtensionOld/ExtensionNew\"".to_string()
+                }
+            };
+            writeln!(
+                writer,
+                "    {} -> {} [{attributes}];",
+                node_name(parent),
+                node_name(child)
+            )?;
+        }
+        writeln!(writer, "}}")
+    }
+}
+
+fn node_name((trie, prefix): &NodeId) -> String {
+    let trie = match trie {
+        Trie::Account => "account".to_string(),
+        Trie::Storage(address) => format!("storage_{address:?}"),
+    };
+    format!("\"{trie}/{}\"", bits(prefix))
+}
+
+fn bits(prefix: &[bool]) -> String {
+    prefix
+        .iter()
+        .map(|bit| if *bit { '1' } else { '0' })
+        .collect()
+}
+
+/// E.g. "0110\nLeaf\n#0 0x1a2b3c4d…→0x2b3c4d5e…\n#1 sibling 0x3c4d5e6f…\n#0 old leaf:
+/// slot 0x5 = 0x1", where #i is the index of the update.
+fn node_label((_, prefix): &NodeId, node: &TrieNode) -> String {
+    let mut lines = vec![if prefix.is_empty() {
+        "root".to_string()
+    } else {
+        bits(prefix)
+    }];
+    if !node.domains.is_empty() {
+        lines.push(
+            node.domains
+                .iter()
+                .map(|domain| format!("{domain:?}"))
+                .collect::<Vec<_>>()
+                .join("/"),
+        );
+    }
+    for (update, old, new) in &node.values {
+        lines.push(format!(
+            "#{update} {}→{}",
+            hash_prefix(*old),
+            hash_prefix(*new)
+        ));
+    }
+    for (update, hash) in &node.siblings {
+        lines.push(format!("#{update} sibling {}", hash_prefix(*hash)));
+    }
+    for (update, side, leaf) in &node.leaves {
+        lines.push(format!("#{update} {side} leaf: {leaf}"));
+    }
+    lines.join("\\n")
+}
+
+/// Values of a leaf with a different key than the one the path is for, which the path ends at in a
+/// type-1 non-existence proof.
+fn other_leaf(key: Fr, value_hash: Fr) -> String {
+    format!(
+        "key {}, value hash {}",
+        hash_prefix(key),
+        hash_prefix(value_hash)
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{serde::SMTTrace, MPTProofType};
+
+    fn proof(proof_type: MPTProofType, trace: &str) -> Proof {
+        let trace: SMTTrace = serde_json::from_str(trace).unwrap();
+        Proof::try_from((proof_type, trace)).unwrap()
+    }
+
+    #[test]
+    fn storage_insertion_graph() {
+        let proofs = [
+            proof(
+                MPTProofType::StorageChanged,
+                include_str!("traces/empty_storage_type_1_update_a.json"),
+            ),
+            proof(
+                MPTProofType::BalanceChanged,
+                include_str!("traces/existing_account_balance_update.json"),
+            ),
+        ];
+        let graph = TrieGraph::new(&proofs);
+
+        // Both updates go through the account trie root, and only the first has a storage trie.
+        let account_root = graph.nodes.get(&(Trie::Account, vec![])).unwrap();
+        assert_eq!(account_root.values.len(), 2);
+        assert_eq!(
+            graph
+                .nodes
+                .keys()
+                .map(|(trie, _)| *trie)
+                .collect::<BTreeSet<_>>()
+                .len(),
+            2
+        );
+
+        // The storage path is extended over 2 levels.
+        let extensions = graph
+            .edges
+            .values()
+            .filter(|path_types| path_types.contains(&PathType::ExtensionNew))
+            .count();
+        assert_eq!(extensions, 2);
+
+        // The old path ends at the leaf that the new path is extended from, and the new path ends
+        // at the inserted leaf two levels below it.
+        let leaf_depths: Vec<_> = graph
+            .nodes
+            .iter()
+            .filter(|((trie, _), node)| {
+                matches!(trie, Trie::Storage(_)) && node.domains.contains(&HashDomain::Leaf)
+            })
+            .map(|((_, prefix), node)| (prefix.len(), node.leaves[0].1))
+            .collect();
+        assert_eq!(leaf_depths, [(4, "old"), (6, "new")]);
+
+        let mut dot = vec![];
+        graph.write_dot(&mut dot).unwrap();
+        let dot = String::from_utf8(dot).unwrap();
+        assert!(dot.starts_with("digraph tries {"));
+        assert!(dot.contains("label=\"storage trie of 0x0101…0101\";"));
+        assert_eq!(dot.matches("color=blue").count(), 2);
+        assert!(dot.contains("#0 new leaf: slot 0x1653e61 = 0x133"));
+        assert_eq!(dot.matches("#1 old leaf: nonce ").count(), 1);
+        assert!(dot.trim_end().ends_with('}'));
+    }
+}
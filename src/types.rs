@@ -1,9 +1,9 @@
 use crate::{
     gadgets::mpt_update::PathType,
-    serde::{AccountData, HexBytes, SMTNode, SMTPath, SMTTrace},
+    serde::{fr::HashRepr, AccountData, Hash, SMTNode, SMTPath, SMTTrace},
     util::{
-        account_key, check_domain_consistency, domain_hash, fr_from_biguint, rlc,
-        u256_from_biguint, u256_from_hex, u256_to_big_endian,
+        account_key, domain_hash, fr_checked, fr_from_biguint, rlc, u256_from_biguint,
+        u256_from_hex, u256_to_big_endian,
     },
     MPTProofType,
 };
@@ -15,13 +15,17 @@ use halo2_proofs::halo2curves::bn256::Fr;
 use itertools::{EitherOrBoth, Itertools};
 use num_bigint::BigUint;
 use num_traits::identities::Zero;
+use serde::{de::Error as _, Deserialize, Deserializer, Serialize, Serializer};
 
+mod error;
+mod explain;
 pub mod storage;
 pub mod trie;
+pub use error::{PathKind, TraceError, TraceField};
 use storage::StorageProof;
-use trie::TrieRows;
+use trie::{check_depth, common_domain, next_domain, node_domain, node_field, node_fr, TrieRows};
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub enum HashDomain {
     Leaf,
     Branch0, // branch node with both children = leaf or empty
@@ -72,15 +76,17 @@ impl HashDomain {
     }
 }
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
 pub struct Claim {
+    #[serde(with = "crate::serde::fr")]
     pub old_root: Fr,
+    #[serde(with = "crate::serde::fr")]
     pub new_root: Fr,
     pub address: Address,
     pub kind: ClaimKind,
 }
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
 pub enum ClaimKind {
     // TODO: remove Option's and represent type of old and new account elsewhere?
     Nonce {
@@ -100,7 +106,9 @@ pub enum ClaimKind {
         new: Option<u64>,
     },
     PoseidonCodeHash {
+        #[serde(with = "crate::serde::fr")]
         old: Option<Fr>,
+        #[serde(with = "crate::serde::fr")]
         new: Option<Fr>,
     },
     Storage {
@@ -109,6 +117,7 @@ pub enum ClaimKind {
         new_value: Option<U256>,
     },
     IsEmpty(Option<U256>),
+    AccountDestructed,
 }
 
 impl Claim {
@@ -132,7 +141,7 @@ impl Claim {
                 &u256_to_big_endian(&old_value.unwrap_or_default()),
                 randomness,
             ),
-            ClaimKind::IsEmpty(_) => Fr::zero(),
+            ClaimKind::IsEmpty(_) | ClaimKind::AccountDestructed => Fr::zero(),
         }
     }
 
@@ -149,27 +158,35 @@ impl Claim {
                 &u256_to_big_endian(&new_value.unwrap_or_default()),
                 randomness,
             ),
-            ClaimKind::IsEmpty(_) => Fr::zero(),
+            ClaimKind::IsEmpty(_) | ClaimKind::AccountDestructed => Fr::zero(),
         }
     }
 }
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
 struct LeafNode {
+    #[serde(with = "crate::serde::fr")]
     key: Fr,
+    #[serde(with = "crate::serde::fr")]
     value_hash: Fr,
 }
 
-#[derive(Clone, Debug)]
+/// Witness for one update. Field elements serialize as `Hash`es, as in `SMTTrace`, so converted
+/// traces can be cached. Deserialized proofs are checked with `Proof::validate`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(remote = "Self")]
 pub struct Proof {
     pub claim: Claim,
     // direction, open_hash_domain, close_hash_domain, open value, close value, sibling, is_padding_open, is_padding_close
+    #[serde(with = "crate::serde::fr")]
     pub address_hash_traces: Vec<(bool, HashDomain, Fr, Fr, Fr, bool, bool)>,
 
     // TODO: make this optional
     leafs: [Option<LeafNode>; 2],
 
+    #[serde(with = "crate::serde::fr")]
     pub old_account_hash_traces: [[Fr; 3]; 6],
+    #[serde(with = "crate::serde::fr")]
     pub new_account_hash_traces: [[Fr; 3]; 6],
 
     pub storage: StorageProof,
@@ -183,13 +200,31 @@ pub struct Proof {
     pub account_trie_rows: TrieRows,
 }
 
+// The derives above generate the inherent `Proof::serialize` and `Proof::deserialize`, which these
+// wrap, so that a cached proof is as trustworthy as a converted trace.
+impl Serialize for Proof {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        Self::serialize(self, serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Proof {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let proof = Self::deserialize(deserializer)?;
+        proof.validate().map_err(D::Error::custom)?;
+        Ok(proof)
+    }
+}
+
 // TODO: rename to Account
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
 pub struct EthAccount {
     pub nonce: u64,
     pub code_size: u64,
+    #[serde(with = "crate::serde::fr")]
     pub balance: Fr,
     pub keccak_codehash: U256,
+    #[serde(with = "crate::serde::fr")]
     pub storage_root: Fr,
 }
 
@@ -206,6 +241,12 @@ impl From<AccountData> for EthAccount {
 }
 
 impl Proof {
+    /// Like `Proof::try_from`, for traces that are known to be valid, e.g. in tests. Panics with
+    /// the `TraceError` if the trace is invalid.
+    pub fn from_trace_unchecked(trace: (MPTProofType, SMTTrace)) -> Self {
+        Self::try_from(trace).unwrap_or_else(|error| panic!("invalid trace: {error}"))
+    }
+
     pub fn n_rows(&self) -> usize {
         if self.old_account.is_none() && self.new_account.is_none() {
             return 1 + self.address_hash_traces.len();
@@ -219,17 +260,50 @@ impl Proof {
                 ClaimKind::CodeHash { .. } => 4,
                 ClaimKind::Storage { .. } | ClaimKind::IsEmpty(Some(_)) => 4,
                 ClaimKind::IsEmpty(None) => 0,
+                ClaimKind::AccountDestructed => 1,
             }
             + self.storage.n_rows()
     }
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Path {
-    pub key: Fr,                    // pair hash of address or storage key
+    #[serde(with = "crate::serde::fr")]
+    pub key: Fr, // pair hash of address or storage key
+    #[serde(with = "crate::serde::fr")]
     pub leaf_data_hash: Option<Fr>, // leaf data hash for type 0 and type 1, None for type 2.
 }
 
+impl HashRepr for (bool, HashDomain, Fr, Fr, Fr, bool, bool) {
+    type Repr = (bool, HashDomain, Hash, Hash, Hash, bool, bool);
+
+    fn to_hash_repr(&self) -> Self::Repr {
+        let (direction, domain, open, close, sibling, is_padding_open, is_padding_close) = *self;
+        (
+            direction,
+            domain,
+            open.to_hash_repr(),
+            close.to_hash_repr(),
+            sibling.to_hash_repr(),
+            is_padding_open,
+            is_padding_close,
+        )
+    }
+
+    fn from_hash_repr(repr: Self::Repr) -> Option<Self> {
+        let (direction, domain, open, close, sibling, is_padding_open, is_padding_close) = repr;
+        Some((
+            direction,
+            domain,
+            Fr::from_hash_repr(open)?,
+            Fr::from_hash_repr(close)?,
+            Fr::from_hash_repr(sibling)?,
+            is_padding_open,
+            is_padding_close,
+        ))
+    }
+}
+
 impl Path {
     pub fn hash(&self) -> Fr {
         match self.leaf_data_hash {
@@ -239,23 +313,42 @@ impl Path {
     }
 }
 
-impl From<(&MPTProofType, &SMTTrace)> for Claim {
-    fn from((proof_type, trace): (&MPTProofType, &SMTTrace)) -> Self {
-        let [old_root, new_root] = trace.account_path.clone().map(|path| fr(path.root));
+impl TryFrom<(&MPTProofType, &SMTTrace)> for Claim {
+    type Error = TraceError;
+
+    fn try_from((proof_type, trace): (&MPTProofType, &SMTTrace)) -> Result<Self, TraceError> {
+        let [old_root, new_root] = [0, 1].map(|index| {
+            fr_checked(
+                trace.account_path[index].root,
+                TraceField::Root {
+                    kind: PathKind::Account,
+                    index,
+                },
+            )
+        });
         let address = trace.address.0.into();
-        let kind = ClaimKind::from((proof_type, trace));
-        assert_eq!(MPTProofType::from(kind), *proof_type);
-        Self {
-            new_root,
-            old_root,
+        let kind = ClaimKind::try_from((proof_type, trace))?;
+        let found = MPTProofType::from(kind);
+        if found != *proof_type {
+            return Err(TraceError::ProofTypeMismatch {
+                proof_type: *proof_type,
+                found,
+            });
+        }
+        Ok(Self {
+            new_root: new_root?,
+            old_root: old_root?,
             address,
             kind,
-        }
+        })
     }
 }
 
-impl From<(&MPTProofType, &SMTTrace)> for ClaimKind {
-    fn from((proof_type, trace): (&MPTProofType, &SMTTrace)) -> Self {
+impl TryFrom<(&MPTProofType, &SMTTrace)> for ClaimKind {
+    type Error = TraceError;
+
+    fn try_from((proof_type, trace): (&MPTProofType, &SMTTrace)) -> Result<Self, TraceError> {
+        let proof_type = *proof_type;
         let [account_old, account_new] = &trace.account_update;
         let state_update = &trace.state_update;
 
@@ -271,17 +364,25 @@ impl From<(&MPTProofType, &SMTTrace)> for ClaimKind {
                     if !(account_old == account_new
                         || (account_old.is_none() && account_new == &Some(Default::default())))
                     {
-                        assert_eq!(account_old, account_new, "{state_update:?}");
+                        return Err(TraceError::Inconsistent(
+                            TraceField::AccountUpdate { index: 1 },
+                            "account cannot change in a storage update",
+                        ));
                     }
                     let old_value = u256_from_hex(old.value);
                     let new_value = u256_from_hex(new.value);
 
-                    assert_eq!(old.key, new.key);
+                    if old.key != new.key {
+                        return Err(TraceError::Inconsistent(
+                            TraceField::StateUpdate { index: 1 },
+                            "storage key differs from old storage key",
+                        ));
+                    }
                     let key = u256_from_hex(old.key);
                     if old_value.is_zero() && new_value.is_zero() {
-                        return ClaimKind::IsEmpty(Some(key));
+                        return Ok(ClaimKind::IsEmpty(Some(key)));
                     }
-                    return ClaimKind::Storage {
+                    return Ok(ClaimKind::Storage {
                         key,
                         old_value: if old_value.is_zero() {
                             None
@@ -293,14 +394,32 @@ impl From<(&MPTProofType, &SMTTrace)> for ClaimKind {
                         } else {
                             Some(new_value)
                         },
-                    };
+                    });
+                }
+                [None, Some(_)] => {
+                    return Err(TraceError::Missing(TraceField::StateUpdate { index: 0 }))
+                }
+                [Some(_), None] => {
+                    return Err(TraceError::Missing(TraceField::StateUpdate { index: 1 }))
                 }
-                [None, Some(_)] | [Some(_), None] => unreachable!(),
             }
         }
 
-        match &trace.account_update {
-            [None, None] => match *proof_type {
+        let unsupported = |reason| TraceError::UnsupportedProofType { proof_type, reason };
+        let mismatch = |found| TraceError::ProofTypeMismatch { proof_type, found };
+        let unchanged = |is_unchanged: bool, reason| {
+            if is_unchanged {
+                Ok(())
+            } else {
+                Err(TraceError::Inconsistent(
+                    TraceField::AccountUpdate { index: 1 },
+                    reason,
+                ))
+            }
+        };
+
+        Ok(match &trace.account_update {
+            [None, None] => match proof_type {
                 MPTProofType::NonceChanged => ClaimKind::Nonce {
                     old: Some(0),
                     new: Some(0),
@@ -318,115 +437,180 @@ impl From<(&MPTProofType, &SMTTrace)> for ClaimKind {
                     old: Some(0),
                     new: Some(0),
                 },
-                MPTProofType::StorageDoesNotExist => {
-                    ClaimKind::IsEmpty(Some(u256_from_hex(trace.state_key.unwrap())))
+                MPTProofType::StorageDoesNotExist => ClaimKind::IsEmpty(Some(u256_from_hex(
+                    trace
+                        .state_key
+                        .ok_or(TraceError::Missing(TraceField::StateKey))?,
+                ))),
+                MPTProofType::PoseidonCodeHashExists => {
+                    return Err(unsupported("account does not exist"))
+                }
+                MPTProofType::StorageChanged => {
+                    return Err(TraceError::Missing(TraceField::StateUpdate { index: 0 }))
+                }
+                MPTProofType::AccountDestructed => {
+                    return Err(unsupported("cannot destruct account that does not exist"))
                 }
-                MPTProofType::PoseidonCodeHashExists => unreachable!(),
-                MPTProofType::StorageChanged => unreachable!(),
-                MPTProofType::AccountDestructed => unimplemented!(),
             },
             [None, Some(new)] => {
                 if !new.nonce.is_zero() {
-                    assert_eq!(*proof_type, MPTProofType::NonceChanged);
+                    if proof_type != MPTProofType::NonceChanged {
+                        return Err(mismatch(MPTProofType::NonceChanged));
+                    }
                     ClaimKind::Nonce {
                         old: None,
                         new: Some(new.nonce),
                     }
                 } else if !new.balance.is_zero() {
-                    assert_eq!(*proof_type, MPTProofType::BalanceChanged);
+                    if proof_type != MPTProofType::BalanceChanged {
+                        return Err(mismatch(MPTProofType::BalanceChanged));
+                    }
                     ClaimKind::Balance {
                         old: None,
                         new: Some(u256_from_biguint(&new.balance)),
                     }
                 } else {
-                    unimplemented!("nonce or balance must be first field set on empty account");
+                    return Err(TraceError::Inconsistent(
+                        TraceField::AccountUpdate { index: 1 },
+                        "nonce or balance must be first field set on empty account",
+                    ));
                 }
             }
-            [Some(old), Some(new)] => match *proof_type {
+            [Some(old), Some(new)] => match proof_type {
                 MPTProofType::NonceChanged => {
-                    assert_eq!(old.balance, new.balance);
-                    assert_eq!(old.code_size, new.code_size);
-                    assert_eq!(old.code_hash, new.code_hash);
-                    assert_eq!(old.poseidon_code_hash, new.poseidon_code_hash);
+                    unchanged(
+                        old.balance == new.balance
+                            && old.code_size == new.code_size
+                            && old.code_hash == new.code_hash
+                            && old.poseidon_code_hash == new.poseidon_code_hash,
+                        "only the nonce can change in a NonceChanged proof",
+                    )?;
                     ClaimKind::Nonce {
                         old: Some(old.nonce),
                         new: Some(new.nonce),
                     }
                 }
                 MPTProofType::BalanceChanged => {
-                    assert_eq!(old.nonce, new.nonce);
-                    assert_eq!(old.code_size, new.code_size);
-                    assert_eq!(old.code_hash, new.code_hash);
-                    assert_eq!(old.poseidon_code_hash, new.poseidon_code_hash);
+                    unchanged(
+                        old.nonce == new.nonce
+                            && old.code_size == new.code_size
+                            && old.code_hash == new.code_hash
+                            && old.poseidon_code_hash == new.poseidon_code_hash,
+                        "only the balance can change in a BalanceChanged proof",
+                    )?;
                     ClaimKind::Balance {
                         old: Some(u256_from_biguint(&old.balance)),
                         new: Some(u256_from_biguint(&new.balance)),
                     }
                 }
                 MPTProofType::CodeHashExists => {
-                    assert_eq!(old.nonce, new.nonce);
-                    assert_eq!(old.balance, new.balance);
-                    assert_eq!(old.code_size, new.code_size);
-                    assert_eq!(old.poseidon_code_hash, new.poseidon_code_hash);
+                    unchanged(
+                        old.nonce == new.nonce
+                            && old.balance == new.balance
+                            && old.code_size == new.code_size
+                            && old.poseidon_code_hash == new.poseidon_code_hash,
+                        "only the code hash can change in a CodeHashExists proof",
+                    )?;
                     ClaimKind::CodeHash {
                         old: Some(u256_from_biguint(&old.code_hash)),
                         new: Some(u256_from_biguint(&new.code_hash)),
                     }
                 }
                 MPTProofType::CodeSizeExists => {
-                    assert_eq!(old.nonce, new.nonce);
-                    assert_eq!(old.balance, new.balance);
-                    assert_eq!(old.code_hash, new.code_hash);
-                    assert_eq!(old.poseidon_code_hash, new.poseidon_code_hash);
+                    unchanged(
+                        old.nonce == new.nonce
+                            && old.balance == new.balance
+                            && old.code_hash == new.code_hash
+                            && old.poseidon_code_hash == new.poseidon_code_hash,
+                        "only the code size can change in a CodeSizeExists proof",
+                    )?;
                     ClaimKind::CodeSize {
                         old: Some(old.code_size),
                         new: Some(new.code_size),
                     }
                 }
                 MPTProofType::PoseidonCodeHashExists => {
-                    assert_eq!(old.nonce, new.nonce);
-                    assert_eq!(old.balance, new.balance);
-                    assert_eq!(old.code_size, new.code_size);
-                    assert_eq!(old.code_hash, new.code_hash);
+                    unchanged(
+                        old.nonce == new.nonce
+                            && old.balance == new.balance
+                            && old.code_size == new.code_size
+                            && old.code_hash == new.code_hash,
+                        "only the poseidon code hash can change in a PoseidonCodeHashExists proof",
+                    )?;
                     ClaimKind::PoseidonCodeHash {
                         old: Some(big_uint_to_fr(&old.poseidon_code_hash)),
                         new: Some(big_uint_to_fr(&new.poseidon_code_hash)),
                     }
                 }
-                MPTProofType::AccountDoesNotExist
-                | MPTProofType::StorageChanged
-                | MPTProofType::StorageDoesNotExist => unreachable!(),
-                MPTProofType::AccountDestructed => unimplemented!(),
+                MPTProofType::StorageChanged | MPTProofType::StorageDoesNotExist => {
+                    return Err(TraceError::Missing(TraceField::StateUpdate { index: 0 }))
+                }
+                MPTProofType::AccountDoesNotExist | MPTProofType::AccountDestructed => {
+                    return Err(unsupported("account exists after the update"))
+                }
             },
-            [Some(_old), None] => unimplemented!("SELFDESTRUCT"),
-        }
+            [Some(_old), None] => {
+                if proof_type != MPTProofType::AccountDestructed {
+                    return Err(mismatch(MPTProofType::AccountDestructed));
+                }
+                ClaimKind::AccountDestructed
+            }
+        })
     }
 }
 
-impl From<(MPTProofType, SMTTrace)> for Proof {
-    fn from((proof, trace): (MPTProofType, SMTTrace)) -> Self {
-        let claim = Claim::from((&proof, &trace));
+impl TryFrom<(MPTProofType, SMTTrace)> for Proof {
+    type Error = TraceError;
 
-        let storage = StorageProof::from(&trace);
+    fn try_from((proof, trace): (MPTProofType, SMTTrace)) -> Result<Self, TraceError> {
+        let claim = Claim::try_from((&proof, &trace))?;
+
+        let storage = StorageProof::try_from(&trace)?;
+        // Storage proofs of missing accounts only have the (empty) storage root.
+        let account_exists = trace.account_update.iter().any(Option::is_some);
+        if account_exists
+            && matches!(
+                (claim.kind, &storage),
+                (
+                    ClaimKind::Storage { .. } | ClaimKind::IsEmpty(Some(_)),
+                    StorageProof::Root(_)
+                )
+            )
+        {
+            return Err(TraceError::Inconsistent(
+                TraceField::CommonStateRoot,
+                "storage proof of an existing account cannot have a common state root",
+            ));
+        }
 
         let key = account_key(claim.address);
-        assert_eq!(key, fr(trace.account_key));
+        if key != fr_checked(trace.account_key, TraceField::AccountKey)? {
+            return Err(TraceError::Inconsistent(
+                TraceField::AccountKey,
+                "account_key is not the hash of the address",
+            ));
+        }
 
+        let [old_path, new_path] = &trace.account_path;
         let account_trie_rows = TrieRows::new(
-            fr(trace.account_key),
-            &trace.account_path[0].path,
-            &trace.account_path[1].path,
-            trace.account_path[0].leaf,
-            trace.account_path[1].leaf,
-        );
-
-        let leafs = trace.account_path.clone().map(get_leaf);
-        let [open_hash_traces, close_hash_traces] =
-            trace.account_path.clone().map(|path| path.path);
-        let leaf_hashes = trace.account_path.clone().map(leaf_hash);
+            PathKind::Account,
+            key,
+            &old_path.path,
+            &new_path.path,
+            old_path.leaf,
+            new_path.leaf,
+        )?;
+
+        let [old_leaf, new_leaf] = [0, 1].map(|index| get_leaf(index, &trace.account_path[index]));
+        let leafs = [old_leaf?, new_leaf?];
+        let leaf_hashes = leafs.map(|leaf| {
+            leaf.map_or_else(Fr::zero, |leaf| {
+                domain_hash(leaf.key, leaf.value_hash, HashDomain::Leaf)
+            })
+        });
         let address_hash_traces =
-            get_internal_hash_traces(key, leaf_hashes, &open_hash_traces, &close_hash_traces);
-        check_hash_traces_new(&address_hash_traces);
+            get_internal_hash_traces(key, leaf_hashes, &old_path.path, &new_path.path)?;
+        check_hash_traces_new(&address_hash_traces)?;
 
         let [old_account, new_account] = trace.account_update;
         let old_account_hash_traces = match old_account.clone() {
@@ -437,18 +621,29 @@ impl From<(MPTProofType, SMTTrace)> for Proof {
             None => empty_account_hash_traces(leafs[1]),
             Some(account) => account_hash_traces(claim.address, account, storage.new_root()),
         };
-        assert_eq!(old_account_hash_traces[5][2], leaf_hashes[0]);
-        assert_eq!(new_account_hash_traces[5][2], leaf_hashes[1]);
+        for (index, account_hash_traces) in [old_account_hash_traces, new_account_hash_traces]
+            .iter()
+            .enumerate()
+        {
+            if account_hash_traces[5][2] != leaf_hashes[index] {
+                return Err(TraceError::Inconsistent(
+                    TraceField::Leaf {
+                        kind: PathKind::Account,
+                        index,
+                        member: "value",
+                    },
+                    "account leaf does not match account_update and storage root",
+                ));
+            }
+        }
 
-        let [old, new] = trace.account_path.map(|path| {
+        let [old, new] = leafs.map(|leaf| {
             // The account_key(address) if the account exists
             // else: path.leaf.sibling if it's a type 1 non-existence proof
             // otherwise account_key(address) if it's a type 2 non-existence proof
-            let key = path
-                .leaf
-                .map_or_else(|| account_key(claim.address), |l| fr(l.sibling));
+            let key = leaf.map_or(key, |leaf| leaf.key);
 
-            let leaf_data_hash = path.leaf.map(|leaf| fr(leaf.value));
+            let leaf_data_hash = leaf.map(|leaf| leaf.value_hash);
 
             Path {
                 key,
@@ -473,7 +668,7 @@ impl From<(MPTProofType, SMTTrace)> for Proof {
             None => None,
         };
 
-        Self {
+        Ok(Self {
             claim,
             address_hash_traces,
             old_account_hash_traces,
@@ -485,27 +680,32 @@ impl From<(MPTProofType, SMTTrace)> for Proof {
             old_account,
             new_account,
             account_trie_rows,
-        }
+        })
     }
 }
 
 // This should be an optional
-fn get_leaf(path: SMTPath) -> Option<LeafNode> {
-    path.leaf.map(|leaf| LeafNode {
-        key: fr(leaf.sibling),
-        value_hash: fr(leaf.value),
-    })
-}
-
-fn leaf_hash(path: SMTPath) -> Fr {
-    if let Some(leaf) = path.leaf {
-        domain_hash(fr(leaf.sibling), fr(leaf.value), HashDomain::Leaf)
-    } else {
-        Fr::zero()
-    }
+fn get_leaf(index: usize, path: &SMTPath) -> Result<Option<LeafNode>, TraceError> {
+    let leaf_field = |member| TraceField::Leaf {
+        kind: PathKind::Account,
+        index,
+        member,
+    };
+    path.leaf
+        .map(|leaf| {
+            Ok(LeafNode {
+                key: fr_checked(leaf.sibling, leaf_field("sibling"))?,
+                value_hash: fr_checked(leaf.value, leaf_field("value"))?,
+            })
+        })
+        .transpose()
 }
 
-fn account_hash_traces(address: Address, account: AccountData, storage_root: Fr) -> [[Fr; 3]; 6] {
+pub(crate) fn account_hash_traces(
+    address: Address,
+    account: AccountData,
+    storage_root: Fr,
+) -> [[Fr; 3]; 6] {
     let (codehash_hi, codehash_lo) = hi_lo(account.code_hash);
     let h1 = domain_hash(codehash_hi, codehash_lo, HashDomain::Pair);
     let h2 = domain_hash(storage_root, h1, HashDomain::AccountFields);
@@ -541,7 +741,9 @@ fn get_internal_hash_traces(
     leaf_hashes: [Fr; 2],
     open_hash_traces: &[SMTNode],
     close_hash_traces: &[SMTNode],
-) -> Vec<(bool, HashDomain, Fr, Fr, Fr, bool, bool)> {
+) -> Result<Vec<(bool, HashDomain, Fr, Fr, Fr, bool, bool)>, TraceError> {
+    let kind = PathKind::Account;
+    check_depth(kind, [open_hash_traces, close_hash_traces])?;
     let mut address_hash_traces = vec![];
     for (i, e) in open_hash_traces
         .iter()
@@ -551,62 +753,44 @@ fn get_internal_hash_traces(
         let direction = key.bit(i);
         address_hash_traces.push(match e {
             EitherOrBoth::Both(open, close) => {
-                assert_eq!(open.sibling, close.sibling);
-                let open_domain = HashDomain::try_from(open.node_type).unwrap();
-                let close_domain = HashDomain::try_from(close.node_type).unwrap();
-
-                let domain = if open_domain != close_domain {
-                    // This can only happen when inserting or deleting a node.
-                    assert!(open_hash_traces.len() != close_hash_traces.len());
-                    assert!(
-                        i == std::cmp::min(open_hash_traces.len(), close_hash_traces.len()) - 1
-                    );
-
-                    if i == open_hash_traces.len() - 1 {
-                        // Inserting a leaf, so open is before insertion, close is after insertion.
-                        check_domain_consistency(open_domain, close_domain, direction);
-                        open_domain
-                    } else {
-                        // Deleting a leaf, so open is after insertion, close is before insertion.
-                        check_domain_consistency(close_domain, open_domain, direction);
-                        close_domain
-                    }
-                } else {
-                    open_domain
-                };
-
+                if open.sibling != close.sibling {
+                    return Err(TraceError::Inconsistent(
+                        node_field(kind, 1, i, "sibling"),
+                        "sibling differs from old path",
+                    ));
+                }
                 (
                     direction,
-                    domain,
-                    fr(open.value),
-                    fr(close.value),
-                    fr(open.sibling),
+                    common_domain(kind, open_hash_traces, close_hash_traces, i, direction)?,
+                    node_fr(kind, 0, i, "value", open.value)?,
+                    node_fr(kind, 1, i, "value", close.value)?,
+                    node_fr(kind, 0, i, "sibling", open.sibling)?,
                     false,
                     false,
                 )
             }
             EitherOrBoth::Left(open) => (
                 direction,
-                HashDomain::try_from(open.node_type).unwrap(),
-                fr(open.value),
+                node_domain(kind, 0, i, open)?,
+                node_fr(kind, 0, i, "value", open.value)?,
                 leaf_hashes[1],
-                fr(open.sibling),
+                node_fr(kind, 0, i, "sibling", open.sibling)?,
                 false,
                 true,
             ),
             EitherOrBoth::Right(close) => (
                 direction,
-                HashDomain::try_from(close.node_type).unwrap(),
+                node_domain(kind, 1, i, close)?,
                 leaf_hashes[0],
-                fr(close.value),
-                fr(close.sibling),
+                node_fr(kind, 1, i, "value", close.value)?,
+                node_fr(kind, 1, i, "sibling", close.sibling)?,
                 true,
                 false,
             ),
         });
     }
     address_hash_traces.reverse();
-    address_hash_traces
+    Ok(address_hash_traces)
 }
 
 fn empty_account_hash_traces(leaf: Option<LeafNode>) -> [[Fr; 3]; 6] {
@@ -663,6 +847,10 @@ impl Proof {
                 let old_account_hash = old_account_hash_traces[5][1];
                 vec![old_account_hash]
             }),
+            ClaimKind::AccountDestructed => self.old_account.map(|_| {
+                let old_account_hash = old_account_hash_traces[5][1];
+                vec![old_account_hash]
+            }),
         }
     }
 
@@ -706,6 +894,7 @@ impl Proof {
                 let new_account_hash = new_account_hash_traces[5][1];
                 vec![new_account_hash]
             }),
+            ClaimKind::AccountDestructed => None,
         }
     }
 
@@ -777,191 +966,189 @@ impl Proof {
                 vec![account_key, poseidon_codehash, h3, keccak_codehash_hash]
             }
             ClaimKind::IsEmpty(None) => vec![],
+            ClaimKind::AccountDestructed => vec![account_key],
         }
     }
 
     // fn new_account_leaf_hashes(&self) -> Vec<Fr> {}
     // fn account_leaf_siblings(&self) -> Vec<Fr> {}
-    #[cfg(test)]
-    pub fn check(&self) {
-        self.storage.check();
+    /// Recomputes the hashes in the proof and checks that they are consistent with its claim.
+    pub fn validate(&self) -> Result<(), TraceError> {
+        self.storage.validate()?;
 
         // poseidon hashes are correct
-        check_hash_traces_new(&self.address_hash_traces);
+        check_hash_traces_new(&self.address_hash_traces)?;
 
-        // directions match account key.
-        let account_key = account_key(self.claim.address);
-        for (i, (direction, _, _, _, _, _, _)) in self.address_hash_traces.iter().enumerate() {
-            assert_eq!(
-                *direction,
-                account_key.bit(self.address_hash_traces.len() - i - 1)
-            );
-        }
-
-        // old and new roots are correct
-        if let Some((
-            direction,
-            domain,
-            open,
-            close,
-            sibling,
-            _is_padding_open,
-            _is_padding_close,
-        )) = self.address_hash_traces.last()
+        // the leafs hash up to the old and new roots along the account key.
+        let leaf_hashes = self.leafs.map(|leaf| {
+            leaf.map_or_else(Fr::zero, |leaf| {
+                domain_hash(leaf.key, leaf.value_hash, HashDomain::Leaf)
+            })
+        });
+        self.account_trie_rows.validate(
+            PathKind::Account,
+            [self.claim.old_root, self.claim.new_root],
+            leaf_hashes,
+            [self.old.key, self.new.key],
+        )?;
+
+        // the account leafs are the hashes of the account fields.
+        for (index, account_hash_traces) in
+            [self.old_account_hash_traces, self.new_account_hash_traces]
+                .iter()
+                .enumerate()
         {
-            if *direction {
-                assert_eq!(domain_hash(*sibling, *open, *domain), self.claim.old_root);
-                assert_eq!(domain_hash(*sibling, *close, *domain), self.claim.new_root);
-            } else {
-                assert_eq!(domain_hash(*open, *sibling, *domain), self.claim.old_root);
-                assert_eq!(domain_hash(*close, *sibling, *domain), self.claim.new_root);
+            if account_hash_traces[5][2] != leaf_hashes[index] {
+                return Err(TraceError::Inconsistent(
+                    TraceField::Leaf {
+                        kind: PathKind::Account,
+                        index,
+                        member: "value",
+                    },
+                    "account leaf does not match account_update and storage root",
+                ));
             }
-        } else {
-            panic!("no hash traces!!!!");
         }
+        Ok(())
+    }
 
-        // this suggests we want something that keeps 1/2 unchanged if something....
-        // going to have to add an is padding row or something?
-
-        assert_eq!(
-            self.old_account_hash_traces[5][2],
-            self.address_hash_traces.first().unwrap().2
-        );
+    #[cfg(test)]
+    pub fn check(&self) {
+        self.validate().unwrap();
+    }
+}
 
-        assert_eq!(
-            self.new_account_hash_traces[5][2],
-            self.address_hash_traces.first().unwrap().3
-        );
-        if let Some(old_leaf) = self.leafs[0] {
-            assert_eq!(
-                domain_hash(old_leaf.key, old_leaf.value_hash, HashDomain::Leaf),
-                self.old_account_hash_traces[5][2],
-            );
+fn check_hash_traces_new(
+    traces: &[(bool, HashDomain, Fr, Fr, Fr, bool, bool)],
+) -> Result<(), TraceError> {
+    // traces are ordered from the deepest node to the node below the root.
+    let parent_value = |j: usize, index: usize| {
+        node_field(PathKind::Account, index, traces.len() - 2 - j, "value")
+    };
+    let check_hash = |j, index, [left, right]: [Fr; 2], domain, parent: Fr| {
+        if domain_hash(left, right, domain) == parent {
+            Ok(())
         } else {
-            assert_eq!(self.address_hash_traces.first().unwrap().2, Fr::zero())
+            Err(TraceError::Inconsistent(
+                parent_value(j, index),
+                "value is not the hash of the node below it",
+            ))
         }
-        if let Some(new_leaf) = self.leafs[1] {
-            assert_eq!(
-                domain_hash(new_leaf.key, new_leaf.value_hash, HashDomain::Leaf),
-                self.new_account_hash_traces[5][2],
-            );
+    };
+    let children = |direction: bool, child: Fr, sibling: Fr| {
+        if direction {
+            [sibling, child]
         } else {
-            assert_eq!(self.address_hash_traces.first().unwrap().3, Fr::zero())
+            [child, sibling]
         }
-
-        // // storage poseidon hashes are correct
-        // self.storage_hash_traces
-        //     .as_ref()
-        //     .map(|x| check_hash_traces_new(x.as_slice()));
-
-        // // directions match storage key hash.
-        // match self.claim.kind {
-        //     ClaimKind::Storage { key, .. }
-        //     | ClaimKind::Storage { key, .. }
-        //     | ClaimKind::IsEmpty(Some(key)) => {
-        //         let storage_key_hash = storage_key_hash(key);
-        //         for (i, (direction, _, _, _, _, _)) in self
-        //             .storage_hash_traces
-        //             .as_ref()
-        //             .unwrap()
-        //             .iter()
-        //             .enumerate()
-        //         {
-        //             assert_eq!(
-        //                 *direction,
-        //                 storage_key_hash
-        //                     .bit(self.storage_hash_traces.as_ref().unwrap().len() - i - 1)
-        //             );
-        //         }
-        //     }
-        //     _ => {}
-        // }
-    }
-}
-
-fn check_hash_traces_new(traces: &[(bool, HashDomain, Fr, Fr, Fr, bool, bool)]) {
+    };
     let mut previous_path_type: Option<PathType> = None;
 
     let current_hash_traces = traces.iter();
     let mut next_hash_traces = traces.iter();
     next_hash_traces.next();
     for (
-        (direction, domain, open, close, sibling, is_padding_open, is_padding_close),
-        (_, _, next_open, next_close, _, _, _),
-    ) in current_hash_traces.zip(next_hash_traces)
+        j,
+        (
+            (direction, domain, open, close, sibling, is_padding_open, is_padding_close),
+            (_, _, next_open, next_close, _, _, _),
+        ),
+    ) in current_hash_traces.zip(next_hash_traces).enumerate()
     {
         let path_type = match (is_padding_open, is_padding_close) {
-            (false, false) => PathType::Common,
-            (false, true) => PathType::ExtensionOld,
-            (true, false) => PathType::ExtensionNew,
-            (true, true) => unreachable!(),
-        };
-
-        match path_type {
-            PathType::Start => unreachable!(),
-            PathType::Common => {
-                let [open_domain, close_domain] =
-                    if previous_path_type == Some(PathType::ExtensionOld) {
-                        unimplemented!("account leaf deletion");
-                    } else if previous_path_type == Some(PathType::ExtensionNew) {
-                        match *domain {
-                            HashDomain::Branch0 => [
-                                HashDomain::Branch0,
-                                if *direction {
-                                    HashDomain::Branch1
-                                } else {
-                                    HashDomain::Branch2
-                                },
-                            ],
-                            HashDomain::Branch1 => [HashDomain::Branch1, HashDomain::Branch3],
-                            HashDomain::Branch2 => [HashDomain::Branch2, HashDomain::Branch3],
-                            HashDomain::Branch3 => {
-                                unreachable!("both siblings already present")
-                            }
-                            _ => unreachable!(),
+            (true, true) => {
+                return Err(TraceError::Inconsistent(
+                    node_field(PathKind::Account, 0, traces.len() - 1 - j, "node_type"),
+                    "node is in neither the old nor the new path",
+                ))
+            }
+            (false, false) => {
+                let [open_domain, close_domain] = if previous_path_type
+                    == Some(PathType::ExtensionOld)
+                {
+                    // Deleting a leaf, so the old domain is the one from before the deletion.
+                    let open_domain =
+                        next_domain(*domain, *direction).ok_or(TraceError::Inconsistent(
+                            node_field(PathKind::Account, 0, traces.len() - 1 - j, "node_type"),
+                            "node type cannot have a leaf deleted below it",
+                        ))?;
+                    [open_domain, *domain]
+                } else if previous_path_type == Some(PathType::ExtensionNew) {
+                    match *domain {
+                        HashDomain::Branch0 => [
+                            HashDomain::Branch0,
+                            if *direction {
+                                HashDomain::Branch1
+                            } else {
+                                HashDomain::Branch2
+                            },
+                        ],
+                        HashDomain::Branch1 => [HashDomain::Branch1, HashDomain::Branch3],
+                        HashDomain::Branch2 => [HashDomain::Branch2, HashDomain::Branch3],
+                        _ => {
+                            return Err(TraceError::Inconsistent(
+                                node_field(PathKind::Account, 1, traces.len() - 1 - j, "node_type"),
+                                "both siblings already present",
+                            ))
                         }
-                    } else {
-                        [*domain, *domain]
-                    };
-
-                if *direction {
-                    assert_eq!(domain_hash(*sibling, *open, open_domain), *next_open);
-                    assert_eq!(domain_hash(*sibling, *close, close_domain), *next_close);
+                    }
                 } else {
-                    assert_eq!(domain_hash(*open, *sibling, open_domain), *next_open);
-                    assert_eq!(domain_hash(*close, *sibling, close_domain), *next_close);
-                }
+                    [*domain, *domain]
+                };
+
+                check_hash(
+                    j,
+                    0,
+                    children(*direction, *open, *sibling),
+                    open_domain,
+                    *next_open,
+                )?;
+                check_hash(
+                    j,
+                    1,
+                    children(*direction, *close, *sibling),
+                    close_domain,
+                    *next_close,
+                )?;
+                PathType::Common
             }
-            PathType::ExtensionOld => {
-                assert!(
-                    previous_path_type.is_none()
-                        || previous_path_type == Some(PathType::ExtensionOld)
-                );
-                if *direction {
-                    assert_eq!(domain_hash(*sibling, *open, *domain), *next_open);
-                } else {
-                    assert_eq!(domain_hash(*open, *sibling, *domain), *next_open);
+            (false, true) => {
+                if !matches!(previous_path_type, None | Some(PathType::ExtensionOld)) {
+                    return Err(TraceError::Inconsistent(
+                        node_field(PathKind::Account, 0, traces.len() - 1 - j, "node_type"),
+                        "path extension does not continue to the end of the path",
+                    ));
                 }
+                check_hash(
+                    j,
+                    0,
+                    children(*direction, *open, *sibling),
+                    *domain,
+                    *next_open,
+                )?;
+                PathType::ExtensionOld
             }
-            PathType::ExtensionNew => {
-                assert!(
-                    previous_path_type.is_none()
-                        || previous_path_type == Some(PathType::ExtensionNew)
-                );
-                if *direction {
-                    assert_eq!(domain_hash(*sibling, *close, *domain), *next_close);
-                } else {
-                    assert_eq!(domain_hash(*close, *sibling, *domain), *next_close);
+            (true, false) => {
+                if !matches!(previous_path_type, None | Some(PathType::ExtensionNew)) {
+                    return Err(TraceError::Inconsistent(
+                        node_field(PathKind::Account, 1, traces.len() - 1 - j, "node_type"),
+                        "path extension does not continue to the end of the path",
+                    ));
                 }
+                check_hash(
+                    j,
+                    1,
+                    children(*direction, *close, *sibling),
+                    *domain,
+                    *next_close,
+                )?;
+                PathType::ExtensionNew
             }
-        }
+        };
 
         previous_path_type = Some(path_type);
     }
-}
-
-fn fr(x: HexBytes<32>) -> Fr {
-    Fr::from_bytes(&x.0).unwrap()
+    Ok(())
 }
 
 fn big_uint_to_fr(i: &BigUint) -> Fr {
@@ -990,8 +1177,9 @@ impl Bit for Fr {
     fn bit(&self, i: usize) -> bool {
         let mut bytes = self.to_bytes();
         bytes.reverse();
-        bytes
-            .get(31 - i / 8)
+        31usize
+            .checked_sub(i / 8)
+            .and_then(|j| bytes.get(j))
             .map_or_else(|| false, |&byte| byte & (1 << (i % 8)) != 0)
     }
 }
@@ -1005,6 +1193,7 @@ mod test {
     fn bit_trait() {
         assert!(Fr::one().bit(0));
         assert!(!Fr::one().bit(1));
+        assert!(!(-Fr::one()).bit(256));
     }
 
     fn contains(path: &[bool], key: Fr) -> bool {
@@ -1026,4 +1215,136 @@ mod test {
         assert!(contains(&[false, false, true], Fr::one()));
         assert!(!contains(&[false, false, false], Fr::one()));
     }
+
+    fn existing_storage_update() -> SMTTrace {
+        serde_json::from_str(include_str!("traces/existing_storage_update.json")).unwrap()
+    }
+
+    #[test]
+    fn non_canonical_node_value() {
+        let mut trace = existing_storage_update();
+        trace.account_path[1].path[1].value = crate::serde::HexBytes([0xff; 32]);
+
+        let error = Proof::try_from((MPTProofType::StorageChanged, trace)).unwrap_err();
+        let field = TraceField::Node {
+            kind: PathKind::Account,
+            index: 1,
+            depth: 1,
+            member: "value",
+        };
+        assert_eq!(error, TraceError::NonCanonical(field));
+        assert_eq!(field.depth(), Some(1));
+        assert_eq!(
+            error.to_string(),
+            "account_path[1].path[1].value is not a canonical field element"
+        );
+    }
+
+    #[test]
+    fn inconsistent_node_value() {
+        let mut trace = existing_storage_update();
+        trace.account_path[0].path[1].value = crate::serde::HexBytes(Fr::one().to_bytes());
+
+        assert_eq!(
+            Proof::try_from((MPTProofType::StorageChanged, trace)).unwrap_err(),
+            TraceError::Inconsistent(
+                TraceField::Node {
+                    kind: PathKind::Account,
+                    index: 0,
+                    depth: 1,
+                    member: "value",
+                },
+                "value is not the hash of the node below it"
+            )
+        );
+    }
+
+    #[test]
+    fn wrong_proof_type() {
+        assert_eq!(
+            Proof::try_from((MPTProofType::NonceChanged, existing_storage_update())).unwrap_err(),
+            TraceError::ProofTypeMismatch {
+                proof_type: MPTProofType::NonceChanged,
+                found: MPTProofType::StorageChanged,
+            }
+        );
+    }
+
+    #[test]
+    fn missing_state_update() {
+        let mut trace = existing_storage_update();
+        trace.state_update.as_mut().unwrap()[1] = None;
+
+        assert_eq!(
+            Proof::try_from((MPTProofType::StorageChanged, trace)).unwrap_err(),
+            TraceError::Missing(TraceField::StateUpdate { index: 1 })
+        );
+    }
+
+    #[test]
+    fn storage_update_with_common_state_root() {
+        let mut trace = existing_storage_update();
+        trace.common_state_root = Some(crate::serde::HexBytes(Fr::one().to_bytes()));
+
+        assert_eq!(
+            Proof::try_from((MPTProofType::StorageChanged, trace)).unwrap_err(),
+            TraceError::Inconsistent(
+                TraceField::CommonStateRoot,
+                "storage proof of an existing account cannot have a common state root"
+            )
+        );
+    }
+
+    #[test]
+    fn proof_serde_round_trip() {
+        for (proof_type, trace) in [
+            (MPTProofType::StorageChanged, existing_storage_update()),
+            (
+                MPTProofType::BalanceChanged,
+                serde_json::from_str(include_str!(
+                    "traces/empty_account_type_1_balance_update.json"
+                ))
+                .unwrap(),
+            ),
+        ] {
+            let proof = Proof::try_from((proof_type, trace)).unwrap();
+            let json = serde_json::to_string(&proof).unwrap();
+
+            let decoded: Proof = serde_json::from_str(&json).unwrap();
+            assert_eq!(serde_json::to_string(&decoded).unwrap(), json);
+            assert_eq!(decoded.n_rows(), proof.n_rows());
+            assert_eq!(MPTProofType::from(decoded.claim), proof_type);
+            decoded.check();
+        }
+    }
+
+    #[test]
+    fn proof_serde_invalid() {
+        let proof =
+            Proof::try_from((MPTProofType::StorageChanged, existing_storage_update())).unwrap();
+        let mut json = serde_json::to_value(&proof).unwrap();
+        json["claim"]["new_root"] = json["claim"]["old_root"].clone();
+
+        let error = serde_json::from_str::<Proof>(&json.to_string()).unwrap_err();
+        assert!(
+            error
+                .to_string()
+                .starts_with("account_path[1].root is inconsistent with the trace"),
+            "{error}"
+        );
+    }
+
+    #[test]
+    fn proof_serde_non_canonical() {
+        let proof =
+            Proof::try_from((MPTProofType::StorageChanged, existing_storage_update())).unwrap();
+        let mut json = serde_json::to_value(&proof).unwrap();
+        json["claim"]["old_root"] =
+            serde_json::to_value(crate::serde::HexBytes([0xff; 32])).unwrap();
+
+        let error = serde_json::from_str::<Proof>(&json.to_string()).unwrap_err();
+        assert!(error
+            .to_string()
+            .starts_with("hash is not a canonical field element"));
+    }
 }
@@ -0,0 +1,119 @@
+use crate::MPTProofType;
+use std::fmt::{Display, Formatter};
+
+/// Which of the two mpt paths in an `SMTTrace` a field belongs to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PathKind {
+    /// `account_path`
+    Account,
+    /// `state_path`
+    State,
+}
+
+/// Location of a value in an `SMTTrace`. `index` is 0 for the old (open) side of the trace and 1
+/// for the new (close) side.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TraceField {
+    AccountKey,
+    StateKey,
+    CommonStateRoot,
+    AccountUpdate {
+        index: usize,
+    },
+    StateUpdate {
+        index: usize,
+    },
+    Path {
+        kind: PathKind,
+        index: usize,
+    },
+    Root {
+        kind: PathKind,
+        index: usize,
+    },
+    Leaf {
+        kind: PathKind,
+        index: usize,
+        member: &'static str,
+    },
+    /// The node at `depth` in the path, where depth 0 is the child of the root.
+    Node {
+        kind: PathKind,
+        index: usize,
+        depth: usize,
+        member: &'static str,
+    },
+}
+
+impl TraceField {
+    /// Depth of the path node the field belongs to, if it is in a path node.
+    pub fn depth(&self) -> Option<usize> {
+        match self {
+            Self::Node { depth, .. } => Some(*depth),
+            _ => None,
+        }
+    }
+}
+
+impl Display for PathKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Account => write!(f, "account_path"),
+            Self::State => write!(f, "state_path"),
+        }
+    }
+}
+
+impl Display for TraceField {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::AccountKey => write!(f, "account_key"),
+            Self::StateKey => write!(f, "state_key"),
+            Self::CommonStateRoot => write!(f, "common_state_root"),
+            Self::AccountUpdate { index } => write!(f, "account_update[{index}]"),
+            Self::StateUpdate { index } => write!(f, "state_update[{index}]"),
+            Self::Path { kind, index } => write!(f, "{kind}[{index}]"),
+            Self::Root { kind, index } => write!(f, "{kind}[{index}].root"),
+            Self::Leaf {
+                kind,
+                index,
+                member,
+            } => write!(f, "{kind}[{index}].leaf.{member}"),
+            Self::Node {
+                kind,
+                index,
+                depth,
+                member,
+            } => write!(f, "{kind}[{index}].path[{depth}].{member}"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, thiserror::Error)]
+/// Errors converting an `SMTTrace` into a `Proof`.
+pub enum TraceError {
+    #[error("{0} is not a canonical field element")]
+    /// bytes are not the little endian representation of a field element
+    NonCanonical(TraceField),
+    #[error("{0} has node type {1}, which is not a branch node type")]
+    /// node type of a path node is not one of the branch domains
+    InvalidNodeType(TraceField, u64),
+    #[error("{0} is missing")]
+    /// field required for the proof type is None
+    Missing(TraceField),
+    #[error("{0} is inconsistent with the trace: {1}")]
+    /// field does not agree with the rest of the trace
+    Inconsistent(TraceField, &'static str),
+    #[error("trace is for a {found:?} proof, not a {proof_type:?} proof")]
+    /// the account or storage update in the trace is for a different proof type
+    ProofTypeMismatch {
+        proof_type: MPTProofType,
+        found: MPTProofType,
+    },
+    #[error("{proof_type:?} proof is not possible for this trace: {reason}")]
+    /// the proof type cannot be proven for the accounts in the trace
+    UnsupportedProofType {
+        proof_type: MPTProofType,
+        reason: &'static str,
+    },
+}
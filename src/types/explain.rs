@@ -0,0 +1,247 @@
+use super::{storage::StorageLeaf, trie::TrieRows, ClaimKind, Proof, StorageProof};
+use crate::{gadgets::mpt_update::PathType, serde::SMTTrace, util::hash_prefix, MPTProofType};
+use std::fmt::{Display, Formatter};
+
+/// What the old or new path of a trie ends in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum PathEnd {
+    Existing,
+    // A leaf with a different key.
+    Type1NonExistence,
+    // An empty node.
+    Type2NonExistence,
+}
+
+impl Display for PathEnd {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Existing => write!(f, "existing leaf"),
+            Self::Type1NonExistence => write!(f, "type-1 non-existence"),
+            Self::Type2NonExistence => write!(f, "type-2 non-existence"),
+        }
+    }
+}
+
+impl From<&StorageLeaf> for PathEnd {
+    fn from(leaf: &StorageLeaf) -> Self {
+        match leaf {
+            StorageLeaf::Entry { .. } => Self::Existing,
+            StorageLeaf::Leaf { .. } => Self::Type1NonExistence,
+            StorageLeaf::Empty { .. } => Self::Type2NonExistence,
+        }
+    }
+}
+
+fn change<T: Display>(old: Option<T>, new: Option<T>) -> String {
+    let show = |value: Option<T>| value.map_or_else(|| "none".to_string(), |v| v.to_string());
+    format!("{}→{}", show(old), show(new))
+}
+
+fn hex_change<T: std::fmt::LowerHex>(old: Option<T>, new: Option<T>) -> String {
+    let show = |value: Option<T>| value.map_or_else(|| "none".to_string(), |v| format!("{v:#x}"));
+    format!("{}→{}", show(old), show(new))
+}
+
+impl TrieRows {
+    /// Describes the depth of the trie, what the old and new paths end in, and how `item` is
+    /// inserted or deleted, e.g. "storage trie depth 6; type-1 non-existence at depth 4; storage
+    /// slot 0x5 inserted with ExtensionNew over 2 levels".
+    fn explain(&self, trie: &str, item: &str, [old, new]: [PathEnd; 2]) -> Vec<String> {
+        let mut clauses = vec![format!("{trie} trie depth {}", self.len())];
+
+        let n_common = self
+            .0
+            .iter()
+            .filter(|row| row.path_type == PathType::Common)
+            .count();
+        let extension = self
+            .0
+            .iter()
+            .map(|row| row.path_type)
+            .find(|path_type| *path_type != PathType::Common);
+        let n_extension = self.len() - n_common;
+        let depth = |path_type| {
+            if extension == Some(path_type) {
+                self.len()
+            } else {
+                n_common
+            }
+        };
+
+        // Whether a non-existence is of the old or new key follows from the insertion or deletion
+        // after it, so it is only stated once when both paths end the same way.
+        if old != PathEnd::Existing {
+            clauses.push(format!("{old} at depth {}", depth(PathType::ExtensionOld)));
+        }
+        if new != PathEnd::Existing && new != old {
+            clauses.push(format!("{new} at depth {}", depth(PathType::ExtensionNew)));
+        }
+
+        let action = match (old, new) {
+            (PathEnd::Existing, PathEnd::Existing) => None,
+            (_, PathEnd::Existing) => Some("inserted"),
+            (PathEnd::Existing, _) => Some("deleted"),
+            (_, _) => None,
+        };
+        match (action, extension) {
+            (Some(action), Some(extension)) => clauses.push(format!(
+                "{item} {action} with {extension:?} over {n_extension} levels"
+            )),
+            (Some(action), None) => clauses.push(format!("{item} {action} at depth {n_common}")),
+            (None, Some(extension)) => clauses.push(format!(
+                "{extension:?} over {n_extension} levels from depth {n_common}"
+            )),
+            (None, None) => (),
+        }
+        clauses
+    }
+}
+
+impl Proof {
+    /// Narrative of the update, for debugging witnesses, e.g. "account 0x0101…0101 storage slot
+    /// 0x5 0x0→0x133 (StorageChanged); account trie depth 3; storage trie depth 6; type-1
+    /// non-existence at depth 4; storage slot 0x5 inserted with ExtensionNew over 2 levels; root
+    /// 0x1a2b3c4d…→0x2b3c4d5e…".
+    pub fn explain(&self) -> String {
+        let address = self.claim.address;
+        let claim = match self.claim.kind {
+            ClaimKind::Nonce { old, new } => format!("nonce {}", change(old, new)),
+            ClaimKind::Balance { old, new } => format!("balance {}", change(old, new)),
+            ClaimKind::CodeHash { old, new } => format!("code hash {}", hex_change(old, new)),
+            ClaimKind::CodeSize { old, new } => format!("code size {}", change(old, new)),
+            ClaimKind::PoseidonCodeHash { old, new } => format!(
+                "poseidon code hash {}",
+                change(old.map(hash_prefix), new.map(hash_prefix))
+            ),
+            ClaimKind::Storage {
+                key,
+                old_value,
+                new_value,
+            } => format!("storage slot {key:#x} {}", hex_change(old_value, new_value)),
+            ClaimKind::IsEmpty(None) => "is empty".to_string(),
+            ClaimKind::IsEmpty(Some(key)) => format!("storage slot {key:#x} is empty"),
+            ClaimKind::AccountDestructed => "destructed".to_string(),
+        };
+        let mut clauses = vec![format!(
+            "account {address} {claim} ({:?})",
+            MPTProofType::from(self.claim)
+        )];
+
+        let account_ends = [
+            (&self.old_account, &self.leafs[0]),
+            (&self.new_account, &self.leafs[1]),
+        ]
+        .map(|(account, leaf)| match (account, leaf) {
+            (Some(_), _) => PathEnd::Existing,
+            (None, Some(_)) => PathEnd::Type1NonExistence,
+            (None, None) => PathEnd::Type2NonExistence,
+        });
+        clauses.extend(self.account_trie_rows.explain(
+            "account",
+            &format!("account {address}"),
+            account_ends,
+        ));
+
+        if let StorageProof::Update {
+            storage_key,
+            trie_rows,
+            old_leaf,
+            new_leaf,
+            ..
+        } = &self.storage
+        {
+            clauses.extend(trie_rows.explain(
+                "storage",
+                &format!("storage slot {storage_key:#x}"),
+                [old_leaf.into(), new_leaf.into()],
+            ));
+        }
+
+        clauses.push(format!(
+            "root {}→{}",
+            hash_prefix(self.claim.old_root),
+            hash_prefix(self.claim.new_root)
+        ));
+        clauses.join("; ")
+    }
+}
+
+impl SMTTrace {
+    /// Narrative of the trace as a `proof_type` update, from `Proof::explain`. If the trace cannot
+    /// be converted into a `Proof`, this describes why, and the lengths of the paths instead.
+    pub fn explain(&self, proof_type: MPTProofType) -> String {
+        match Proof::try_from((proof_type, self.clone())) {
+            Ok(proof) => proof.explain(),
+            Err(error) => {
+                let path_lengths = |paths: [Option<usize>; 2]| {
+                    paths
+                        .map(|length| length.map_or_else(|| "none".to_string(), |l| l.to_string()))
+                        .join("/")
+                };
+                format!(
+                    "invalid {proof_type:?} trace for account {}: {error}; account path depths \
+                     {}; storage path depths {}",
+                    self.address,
+                    path_lengths([0, 1].map(|i| Some(self.account_path[i].path.len()))),
+                    path_lengths(
+                        [0, 1].map(|i| self.state_path[i].as_ref().map(|path| path.path.len()))
+                    ),
+                )
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn explain(proof_type: MPTProofType, trace: &str) -> String {
+        let trace: SMTTrace = serde_json::from_str(trace).unwrap();
+        trace.explain(proof_type)
+    }
+
+    #[test]
+    fn explain_storage_insertion() {
+        let explanation = explain(
+            MPTProofType::StorageChanged,
+            include_str!("../traces/empty_storage_type_1_update_a.json"),
+        );
+        assert!(
+            explanation.starts_with(
+                "account 0x0101…0101 storage slot 0x1653e61 0x0→0x133 (StorageChanged); account \
+                 trie depth 3; storage trie depth 6; type-1 non-existence at depth 4; storage slot \
+                 0x1653e61 inserted with ExtensionNew over 2 levels; root 0x"
+            ),
+            "{explanation}"
+        );
+    }
+
+    #[test]
+    fn explain_account_insertion() {
+        let explanation = explain(
+            MPTProofType::BalanceChanged,
+            include_str!("../traces/empty_account_type_2_balance_update.json"),
+        );
+        assert!(
+            explanation.starts_with(
+                "account 0x1414…1414 balance none→123124128387 (BalanceChanged); account trie \
+                 depth 3; type-2 non-existence at depth 3; account 0x1414…1414 inserted at \
+                 depth 3; root 0x"
+            ),
+            "{explanation}"
+        );
+    }
+
+    #[test]
+    fn explain_invalid_trace() {
+        let explanation = explain(
+            MPTProofType::NonceChanged,
+            include_str!("../traces/existing_storage_update.json"),
+        );
+        assert!(
+            explanation.starts_with("invalid NonceChanged trace for account 0x"),
+            "{explanation}"
+        );
+    }
+}
@@ -1,18 +1,21 @@
-#[cfg(test)]
-use crate::types::{Bit, PathType};
 use crate::{
     serde::{SMTNode, SMTTrace, StateData},
-    types::{trie::TrieRows, HashDomain},
-    util::{domain_hash, fr, storage_key_hash, u256_from_hex, u256_hi_lo},
+    types::{trie::TrieRows, HashDomain, PathKind, TraceError, TraceField},
+    util::{domain_hash, fr_checked, storage_key_hash, u256_from_hex, u256_hi_lo},
 };
 use ethers_core::{k256::elliptic_curve::PrimeField, types::U256};
 use halo2_proofs::halo2curves::bn256::Fr;
+use serde::{Deserialize, Serialize};
 
-#[derive(Clone, Debug)]
+/// Storage part of a `Proof`. Deserializing it on its own does not check it, so only deserialize
+/// trusted storage proofs, or `Proof`s, which are validated.
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub enum StorageProof {
-    Root(Fr), // Not proving a storage update, so we only need the storage root.
+    // Not proving a storage update, so we only need the storage root.
+    Root(#[serde(with = "crate::serde::fr")] Fr),
     Update {
         storage_key: U256,
+        #[serde(with = "crate::serde::fr")]
         key: Fr,
         trie_rows: TrieRows,
         old_leaf: StorageLeaf,
@@ -20,11 +23,25 @@ pub enum StorageProof {
     },
 }
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
 pub enum StorageLeaf {
-    Empty { mpt_key: Fr },                    // Type 2 empty storage leaf
-    Leaf { mpt_key: Fr, value_hash: Fr },     // Type 1 empty storage leaf
-    Entry { storage_key: U256, value: U256 }, // Existing storage leaf (value is non-zero)
+    // Type 2 empty storage leaf
+    Empty {
+        #[serde(with = "crate::serde::fr")]
+        mpt_key: Fr,
+    },
+    // Type 1 empty storage leaf
+    Leaf {
+        #[serde(with = "crate::serde::fr")]
+        mpt_key: Fr,
+        #[serde(with = "crate::serde::fr")]
+        value_hash: Fr,
+    },
+    // Existing storage leaf (value is non-zero)
+    Entry {
+        storage_key: U256,
+        value: U256,
+    },
 }
 
 impl StorageProof {
@@ -89,33 +106,32 @@ impl StorageProof {
     }
 
     pub fn key_lookups(&self) -> Vec<Fr> {
-        match self {
-            Self::Root(_) => vec![],
-            Self::Update { .. } => {
-                vec![self.key(), self.other_key()]
-            }
-        }
+        self.key().into_iter().chain(self.other_key()).collect()
     }
 
     pub fn key_bit_lookups(&self) -> Vec<(Fr, usize, bool)> {
-        match self {
-            Self::Root(_) => vec![],
-            Self::Update { trie_rows, .. } => {
-                trie_rows.key_bit_lookups(self.key(), self.other_key())
+        match (self, self.key(), self.other_key()) {
+            (Self::Update { trie_rows, .. }, Some(key), Some(other_key)) => {
+                trie_rows.key_bit_lookups(key, other_key)
             }
+            _ => vec![],
         }
     }
 
-    pub fn key(&self) -> Fr {
+    /// Mpt key of the storage slot, or None if the proof is of the storage root only.
+    pub fn key(&self) -> Option<Fr> {
         match self {
-            Self::Root(_) => unreachable!(),
-            Self::Update { key, .. } => *key,
+            Self::Root(_) => None,
+            Self::Update { key, .. } => Some(*key),
         }
     }
 
-    pub fn other_key(&self) -> Fr {
+    /// Key of the leaf that the storage slot's path ends at in the old or new trie, if it is not
+    /// the storage slot's key, and the storage slot's key otherwise. None if the proof is of the
+    /// storage root only.
+    pub fn other_key(&self) -> Option<Fr> {
         match self {
-            Self::Root(_) => unreachable!(),
+            Self::Root(_) => None,
             Self::Update {
                 key,
                 old_leaf,
@@ -124,65 +140,69 @@ impl StorageProof {
             } => {
                 let old_key = old_leaf.key();
                 let new_key = new_leaf.key();
-                if *key == old_key {
-                    new_key
-                } else {
-                    old_key
-                }
+                Some(if *key == old_key { new_key } else { old_key })
             }
         }
     }
 
-    #[cfg(test)]
-    pub fn check(&self) {
+    /// Recomputes the storage trie hashes and checks that they are consistent with the storage
+    /// key and leafs.
+    pub fn validate(&self) -> Result<(), TraceError> {
         if let Self::Update {
+            storage_key,
+            key,
             trie_rows,
             old_leaf,
             new_leaf,
-            ..
         } = self
         {
-            // Check that trie rows are consistent and produce claimed roots.
-            trie_rows.check(self.old_root(), self.new_root());
-
-            // Check that directions match old and new keys.
-            for (i, row) in trie_rows.0.iter().enumerate() {
-                let old_key = old_leaf.key();
-                let new_key = new_leaf.key();
-                match row.path_type {
-                    PathType::Start => unreachable!(),
-                    PathType::Common => {
-                        assert_eq!(row.direction, old_key.bit(i));
-                        assert_eq!(row.direction, new_key.bit(i));
-                    }
-                    PathType::ExtensionOld => {
-                        assert_eq!(row.direction, old_key.bit(i));
-                    }
-                    PathType::ExtensionNew => {
-                        assert_eq!(row.direction, new_key.bit(i));
-                    }
-                }
-            }
-
-            // Check that final trie_row values match leaf hashes
-            if let Some(row) = trie_rows.0.last() {
-                assert_eq!(old_leaf.hash(), row.old);
-                assert_eq!(new_leaf.hash(), row.new);
+            if *key != storage_key_hash(*storage_key) {
+                return Err(TraceError::Inconsistent(
+                    TraceField::StateKey,
+                    "state_key is not the hash of the storage key",
+                ));
             }
+            trie_rows.validate(
+                PathKind::State,
+                [self.old_root(), self.new_root()],
+                [old_leaf.hash(), new_leaf.hash()],
+                [old_leaf.key(), new_leaf.key()],
+            )?;
         }
+        Ok(())
+    }
+
+    #[cfg(test)]
+    pub fn check(&self) {
+        self.validate().unwrap();
     }
 }
 
 impl StorageLeaf {
-    fn new(mpt_key: Fr, node: &Option<SMTNode>, data: &StateData) -> Self {
+    fn new(
+        index: usize,
+        mpt_key: Fr,
+        node: &Option<SMTNode>,
+        data: &StateData,
+    ) -> Result<Self, TraceError> {
         let value = u256_from_hex(data.value);
-        match (node, value.is_zero()) {
+        Ok(match (node, value.is_zero()) {
             (None, true) => Self::Empty { mpt_key },
             (Some(node), true) => {
-                assert_eq!(mpt_key, storage_key_hash(u256_from_hex(data.key)));
+                if mpt_key != storage_key_hash(u256_from_hex(data.key)) {
+                    return Err(TraceError::Inconsistent(
+                        TraceField::StateKey,
+                        "state_key is not the hash of the storage key",
+                    ));
+                }
+                let leaf_field = |member| TraceField::Leaf {
+                    kind: PathKind::State,
+                    index,
+                    member,
+                };
                 Self::Leaf {
-                    mpt_key: fr(node.sibling),
-                    value_hash: fr(node.value),
+                    mpt_key: fr_checked(node.sibling, leaf_field("sibling"))?,
+                    value_hash: fr_checked(node.value, leaf_field("value"))?,
                 }
             }
             (Some(_), false) => Self::Entry {
@@ -190,9 +210,13 @@ impl StorageLeaf {
                 value,
             },
             (None, false) => {
-                unreachable!();
+                return Err(TraceError::Missing(TraceField::Leaf {
+                    kind: PathKind::State,
+                    index,
+                    member: "value",
+                }))
             }
-        }
+        })
     }
 
     fn n_rows(&self) -> usize {
@@ -272,28 +296,53 @@ impl StorageLeaf {
     }
 }
 
-impl From<&SMTTrace> for StorageProof {
-    fn from(trace: &SMTTrace) -> Self {
+impl TryFrom<&SMTTrace> for StorageProof {
+    type Error = TraceError;
+
+    fn try_from(trace: &SMTTrace) -> Result<Self, TraceError> {
         if let Some(root) = trace.common_state_root {
-            return Self::Root(fr(root));
+            return Ok(Self::Root(fr_checked(root, TraceField::CommonStateRoot)?));
         }
-        let key = fr(trace.state_key.unwrap());
-        let [old_path, new_path] = &trace.state_path;
-        let old_leaf = old_path.as_ref().unwrap().leaf;
-        let new_leaf = new_path.as_ref().unwrap().leaf;
+        let key = fr_checked(
+            trace
+                .state_key
+                .ok_or(TraceError::Missing(TraceField::StateKey))?,
+            TraceField::StateKey,
+        )?;
+        let [old_path, new_path] = [0, 1].map(|index| {
+            trace.state_path[index]
+                .as_ref()
+                .ok_or(TraceError::Missing(TraceField::Path {
+                    kind: PathKind::State,
+                    index,
+                }))
+        });
+        let [old_path, new_path] = [old_path?, new_path?];
         let trie_rows = TrieRows::new(
+            PathKind::State,
             key,
-            &old_path.as_ref().unwrap().path,
-            &new_path.as_ref().unwrap().path,
-            old_leaf,
-            new_leaf,
-        );
+            &old_path.path,
+            &new_path.path,
+            old_path.leaf,
+            new_path.leaf,
+        )?;
 
-        let [old_entry, new_entry] = trace.state_update.unwrap().map(Option::unwrap);
-        assert_eq!(old_entry.key, new_entry.key);
+        let state_update = trace
+            .state_update
+            .ok_or(TraceError::Missing(TraceField::StateUpdate { index: 0 }))?;
+        let [old_entry, new_entry] = [0, 1].map(|index| {
+            state_update[index].ok_or(TraceError::Missing(TraceField::StateUpdate { index }))
+        });
+        let [old_entry, new_entry] = [old_entry?, new_entry?];
+        if old_entry.key != new_entry.key {
+            return Err(TraceError::Inconsistent(
+                TraceField::StateUpdate { index: 1 },
+                "storage key differs from old storage key",
+            ));
+        }
         let storage_key = u256_from_hex(old_entry.key);
-        let old_leaf = StorageLeaf::new(key, &old_leaf, &old_entry);
-        let new_leaf = StorageLeaf::new(key, &new_leaf, &new_entry);
+        let old_leaf = StorageLeaf::new(0, key, &old_path.leaf, &old_entry)?;
+        let new_leaf = StorageLeaf::new(1, key, &new_path.leaf, &new_entry)?;
 
         let storage_proof = Self::Update {
             storage_key,
@@ -302,14 +351,22 @@ impl From<&SMTTrace> for StorageProof {
             old_leaf,
             new_leaf,
         };
-        assert_eq!(
-            storage_proof.old_root(),
-            fr(old_path.as_ref().unwrap().root)
-        );
-        assert_eq!(
-            storage_proof.new_root(),
-            fr(new_path.as_ref().unwrap().root)
-        );
-        storage_proof
+        for (index, (path, root)) in [old_path, new_path]
+            .into_iter()
+            .zip([storage_proof.old_root(), storage_proof.new_root()])
+            .enumerate()
+        {
+            let field = TraceField::Root {
+                kind: PathKind::State,
+                index,
+            };
+            if fr_checked(path.root, field)? != root {
+                return Err(TraceError::Inconsistent(
+                    field,
+                    "root is not the hash of the top node of the path",
+                ));
+            }
+        }
+        Ok(storage_proof)
     }
 }
@@ -1,24 +1,32 @@
+use super::{PathKind, TraceError, TraceField};
 use crate::{
     gadgets::mpt_update::PathType,
-    serde::SMTNode,
+    serde::{HexBytes, SMTNode},
     types::HashDomain,
-    util::{check_domain_consistency, domain_hash, fr, Bit},
+    util::{domain_hash, domains_consistent, fr_checked, Bit},
 };
 use halo2_proofs::halo2curves::bn256::Fr;
 use itertools::{EitherOrBoth, Itertools};
+use serde::{Deserialize, Serialize};
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct TrieRow {
     pub domain: HashDomain,
+    #[serde(with = "crate::serde::fr")]
     pub old: Fr,
+    #[serde(with = "crate::serde::fr")]
     pub new: Fr,
+    #[serde(with = "crate::serde::fr")]
     pub sibling: Fr,
     pub direction: bool,
     pub path_type: PathType,
 }
 
+/// Rows of the old and new paths of a trie. Deserializing them does not check them, since that
+/// needs the roots and leaves of the paths, so only deserialize trusted rows, or `Proof`s, which
+/// are validated.
 #[allow(clippy::len_without_is_empty)]
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct TrieRows(pub Vec<TrieRow>);
 
 impl TrieRow {
@@ -43,96 +51,88 @@ impl TrieRow {
         }
     }
 
+    /// Domains of the old and new hashes of the row. These differ for the common row above a leaf
+    /// that is inserted or deleted. `validate` rejects rows where this falls back to `self.domain`
+    /// because the domain cannot take another child.
     fn hash_domains(&self, next_path_type: Option<PathType>) -> [HashDomain; 2] {
-        if self.path_type == PathType::Common
-            && matches!(
-                next_path_type,
-                Some(PathType::ExtensionNew) | Some(PathType::ExtensionOld)
-            )
-        {
-            get_domains(next_path_type.unwrap(), self.domain, self.direction)
-        } else {
-            [self.domain, self.domain]
+        self.try_hash_domains(next_path_type)
+            .unwrap_or([self.domain, self.domain])
+    }
+
+    fn try_hash_domains(&self, next_path_type: Option<PathType>) -> Option<[HashDomain; 2]> {
+        match (self.path_type, next_path_type) {
+            (
+                PathType::Common,
+                Some(next_path_type @ (PathType::ExtensionNew | PathType::ExtensionOld)),
+            ) => get_domains(next_path_type, self.domain, self.direction),
+            _ => Some([self.domain, self.domain]),
         }
     }
 }
 
 impl TrieRows {
     pub fn new(
+        kind: PathKind,
         key: Fr,
         old_nodes: &[SMTNode],
         new_nodes: &[SMTNode],
         old_leaf: Option<SMTNode>,
         new_leaf: Option<SMTNode>,
-    ) -> Self {
-        let old_leaf_hash = old_nodes
-            .last()
-            .map(|node| fr(node.value))
-            .unwrap_or_else(|| old_leaf.map(leaf_hash).unwrap_or_default());
-        let new_leaf_hash = new_nodes
-            .last()
-            .map(|node| fr(node.value))
-            .unwrap_or_else(|| new_leaf.map(leaf_hash).unwrap_or_default());
-        Self(
-            old_nodes
-                .iter()
-                .zip_longest(new_nodes.iter())
-                .enumerate()
-                .map(|(i, pair)| {
-                    let direction = key.bit(i);
-                    match pair {
-                        EitherOrBoth::Both(old, new) => {
-                            assert_eq!(old.sibling, new.sibling);
-
-                            let old_domain = HashDomain::try_from(old.node_type).unwrap();
-                            let new_domain = HashDomain::try_from(new.node_type).unwrap();
-                            let domain = if old_domain != new_domain {
-                                // This can only happen when inserting or deleting a node.
-                                assert!(old_nodes.len() != new_nodes.len());
-                                assert!(i == std::cmp::min(old_nodes.len(), new_nodes.len()) - 1);
-
-                                if i == old_nodes.len() - 1 {
-                                    // Inserting a leaf, so old is before insertion, new is after insertion.
-                                    check_domain_consistency(old_domain, new_domain, direction);
-                                    old_domain
-                                } else {
-                                    // Deleting a leaf, so new is after insertion, old is before insertion.
-                                    check_domain_consistency(new_domain, old_domain, direction);
-                                    new_domain
-                                }
-                            } else {
-                                old_domain
-                            };
-
-                            TrieRow {
-                                domain,
-                                direction,
-                                old: fr(old.value),
-                                new: fr(new.value),
-                                sibling: fr(old.sibling),
-                                path_type: PathType::Common,
-                            }
+    ) -> Result<Self, TraceError> {
+        check_depth(kind, [old_nodes, new_nodes])?;
+        let old_leaf_hash = match (old_nodes.last(), old_leaf) {
+            (Some(node), _) => node_fr(kind, 0, old_nodes.len() - 1, "value", node.value)?,
+            (None, Some(leaf)) => leaf_hash(kind, 0, leaf)?,
+            (None, None) => Fr::zero(),
+        };
+        let new_leaf_hash = match (new_nodes.last(), new_leaf) {
+            (Some(node), _) => node_fr(kind, 1, new_nodes.len() - 1, "value", node.value)?,
+            (None, Some(leaf)) => leaf_hash(kind, 1, leaf)?,
+            (None, None) => Fr::zero(),
+        };
+        old_nodes
+            .iter()
+            .zip_longest(new_nodes.iter())
+            .enumerate()
+            .map(|(i, pair)| {
+                let direction = key.bit(i);
+                Ok(match pair {
+                    EitherOrBoth::Both(old, new) => {
+                        if old.sibling != new.sibling {
+                            return Err(TraceError::Inconsistent(
+                                node_field(kind, 1, i, "sibling"),
+                                "sibling differs from old path",
+                            ));
                         }
-                        EitherOrBoth::Left(old) => TrieRow {
-                            domain: HashDomain::try_from(old.node_type).unwrap(),
-                            direction,
-                            old: fr(old.value),
-                            new: new_leaf_hash,
-                            sibling: fr(old.sibling),
-                            path_type: PathType::ExtensionOld,
-                        },
-                        EitherOrBoth::Right(new) => TrieRow {
-                            domain: HashDomain::try_from(new.node_type).unwrap(),
+                        TrieRow {
+                            domain: common_domain(kind, old_nodes, new_nodes, i, direction)?,
                             direction,
-                            old: old_leaf_hash,
-                            new: fr(new.value),
-                            sibling: fr(new.sibling),
-                            path_type: PathType::ExtensionNew,
-                        },
+                            old: node_fr(kind, 0, i, "value", old.value)?,
+                            new: node_fr(kind, 1, i, "value", new.value)?,
+                            sibling: node_fr(kind, 0, i, "sibling", old.sibling)?,
+                            path_type: PathType::Common,
+                        }
                     }
+                    EitherOrBoth::Left(old) => TrieRow {
+                        domain: node_domain(kind, 0, i, old)?,
+                        direction,
+                        old: node_fr(kind, 0, i, "value", old.value)?,
+                        new: new_leaf_hash,
+                        sibling: node_fr(kind, 0, i, "sibling", old.sibling)?,
+                        path_type: PathType::ExtensionOld,
+                    },
+                    EitherOrBoth::Right(new) => TrieRow {
+                        domain: node_domain(kind, 1, i, new)?,
+                        direction,
+                        old: old_leaf_hash,
+                        new: node_fr(kind, 1, i, "value", new.value)?,
+                        sibling: node_fr(kind, 1, i, "sibling", new.sibling)?,
+                        path_type: PathType::ExtensionNew,
+                    },
                 })
-                .collect(),
-        )
+            })
+            .collect::<Result<_, _>>()
+            .map(Self)
     }
 
     pub fn len(&self) -> usize {
@@ -149,13 +149,11 @@ impl TrieRows {
             };
 
             match row.path_type {
-                PathType::Start => unreachable!(),
+                // Not a valid row, so it has no hashes to look up.
+                PathType::Start => (),
                 PathType::Common => {
-                    let [old_domain, new_domain] = if let Some(next_row) = self.0.get(i + 1) {
-                        get_domains(next_row.path_type, row.domain, row.direction)
-                    } else {
-                        [row.domain, row.domain]
-                    };
+                    let [old_domain, new_domain] =
+                        row.hash_domains(self.0.get(i + 1).map(|row| row.path_type));
                     lookups.push((
                         old_left,
                         old_right,
@@ -221,8 +219,27 @@ impl TrieRows {
             .map_or_else(leaf_hash, |row| row.new_hash(next_path_type))
     }
 
-    #[cfg(test)]
-    pub fn check(&self, old_root: Fr, new_root: Fr) {
+    /// Checks that the rows hash up to `roots`, that their directions match the leaf `keys`, and
+    /// that the deepest rows hold the `leaf_hashes`. Index 0 is the old side and 1 the new side.
+    pub fn validate(
+        &self,
+        kind: PathKind,
+        roots: [Fr; 2],
+        leaf_hashes: [Fr; 2],
+        keys: [Fr; 2],
+    ) -> Result<(), TraceError> {
+        let Some(last_row) = self.0.last() else {
+            for index in [0, 1] {
+                if roots[index] != leaf_hashes[index] {
+                    return Err(TraceError::Inconsistent(
+                        TraceField::Root { kind, index },
+                        "root is not the hash of the leaf",
+                    ));
+                }
+            }
+            return Ok(());
+        };
+
         for (i, row) in self.0.iter().enumerate() {
             let [[old_left, old_right], [new_left, new_right]] = if row.direction {
                 [[row.sibling, row.old], [row.sibling, row.new]]
@@ -230,65 +247,109 @@ impl TrieRows {
                 [[row.old, row.sibling], [row.new, row.sibling]]
             };
 
-            let [expected_old_hash, expected_new_hash] = if i == 0 {
-                [old_root, new_root]
+            let expected_hashes = if i == 0 {
+                roots
             } else {
-                let previous_row = self.0.get(i - 1).unwrap();
+                let previous_row = &self.0[i - 1];
                 [previous_row.old, previous_row.new]
             };
+            let check_hash = |index: usize, left, right, domain| {
+                if domain_hash(left, right, domain) == expected_hashes[index] {
+                    Ok(())
+                } else if i == 0 {
+                    Err(TraceError::Inconsistent(
+                        TraceField::Root { kind, index },
+                        "root is not the hash of the top node of the path",
+                    ))
+                } else {
+                    Err(TraceError::Inconsistent(
+                        node_field(kind, index, i - 1, "value"),
+                        "value is not the hash of the node below it",
+                    ))
+                }
+            };
+            let check_direction = |index: usize| {
+                if row.direction == keys[index].bit(i) {
+                    Ok(())
+                } else {
+                    Err(TraceError::Inconsistent(
+                        TraceField::Leaf {
+                            kind,
+                            index,
+                            member: "sibling",
+                        },
+                        "leaf key does not match the path directions",
+                    ))
+                }
+            };
+            let check_extension = |index: usize| match self.0.get(i + 1) {
+                Some(next_row) if next_row.path_type != row.path_type => {
+                    Err(TraceError::Inconsistent(
+                        node_field(kind, index, i + 1, "node_type"),
+                        "path extension does not continue to the end of the path",
+                    ))
+                }
+                _ => Ok(()),
+            };
 
             match row.path_type {
-                PathType::Start => unreachable!(),
+                PathType::Start => {
+                    return Err(TraceError::Inconsistent(
+                        node_field(kind, 0, i, "node_type"),
+                        "node is in neither the old nor the new path",
+                    ))
+                }
                 PathType::Common => {
-                    let [old_domain, new_domain] = if let Some(next_row) = self.0.get(i + 1) {
-                        get_domains(next_row.path_type, row.domain, row.direction)
-                    } else {
-                        [row.domain, row.domain]
-                    };
-                    assert_eq!(
-                        domain_hash(old_left, old_right, old_domain),
-                        expected_old_hash
-                    );
-                    assert_eq!(
-                        domain_hash(new_left, new_right, new_domain),
-                        expected_new_hash
-                    );
+                    let [old_domain, new_domain] = row
+                        .try_hash_domains(self.0.get(i + 1).map(|row| row.path_type))
+                        .ok_or(TraceError::Inconsistent(
+                            node_field(kind, 0, i, "node_type"),
+                            "node type cannot have a leaf inserted or deleted below it",
+                        ))?;
+                    check_hash(0, old_left, old_right, old_domain)?;
+                    check_hash(1, new_left, new_right, new_domain)?;
+                    check_direction(0)?;
+                    check_direction(1)?;
                 }
                 PathType::ExtensionOld => {
-                    if let Some(row) = self.0.get(i + 1) {
-                        assert_eq!(row.path_type, PathType::ExtensionOld);
-                    }
-                    assert_eq!(
-                        domain_hash(old_left, old_right, row.domain),
-                        expected_old_hash
-                    );
+                    check_extension(0)?;
+                    check_hash(0, old_left, old_right, row.domain)?;
+                    check_direction(0)?;
                 }
                 PathType::ExtensionNew => {
-                    if let Some(row) = self.0.get(i + 1) {
-                        assert_eq!(row.path_type, PathType::ExtensionNew);
-                    }
-                    assert_eq!(
-                        domain_hash(new_left, new_right, row.domain),
-                        expected_new_hash
-                    );
+                    check_extension(1)?;
+                    check_hash(1, new_left, new_right, row.domain)?;
+                    check_direction(1)?;
                 }
             }
         }
+
+        for (index, value) in [last_row.old, last_row.new].into_iter().enumerate() {
+            if value != leaf_hashes[index] {
+                return Err(TraceError::Inconsistent(
+                    node_field(kind, index, self.0.len() - 1, "value"),
+                    "value is not the hash of the leaf",
+                ));
+            }
+        }
+        Ok(())
     }
 }
 
-pub fn next_domain(before_insertion_domain: HashDomain, insertion_direction: bool) -> HashDomain {
+/// Domain of a branch after a child is inserted in direction `insertion_direction`, or None if the
+/// branch already has both children or is not a branch.
+pub fn next_domain(
+    before_insertion_domain: HashDomain,
+    insertion_direction: bool,
+) -> Option<HashDomain> {
     match before_insertion_domain {
-        HashDomain::Branch0 => {
-            if insertion_direction {
-                HashDomain::Branch1
-            } else {
-                HashDomain::Branch2
-            }
-        }
-        HashDomain::Branch1 | HashDomain::Branch2 => HashDomain::Branch3,
-        HashDomain::Branch3 => unreachable!(),
-        _ => unreachable!(),
+        HashDomain::Branch0 => Some(if insertion_direction {
+            HashDomain::Branch1
+        } else {
+            HashDomain::Branch2
+        }),
+        HashDomain::Branch1 | HashDomain::Branch2 => Some(HashDomain::Branch3),
+        _ => None,
     }
 }
 
@@ -296,21 +357,131 @@ fn get_domains(
     next_path_type: PathType,
     before_insertion_domain: HashDomain,
     insertion_direction: bool,
-) -> [HashDomain; 2] {
+) -> Option<[HashDomain; 2]> {
     let mut domains = match next_path_type {
-        PathType::Start => unreachable!(),
+        PathType::Start => return None,
         PathType::Common => [before_insertion_domain, before_insertion_domain],
         PathType::ExtensionOld | PathType::ExtensionNew => [
             before_insertion_domain,
-            next_domain(before_insertion_domain, insertion_direction),
+            next_domain(before_insertion_domain, insertion_direction)?,
         ],
     };
     if next_path_type == PathType::ExtensionOld {
         domains.reverse();
     }
-    domains
+    Some(domains)
+}
+
+fn leaf_hash(kind: PathKind, index: usize, leaf: SMTNode) -> Result<Fr, TraceError> {
+    let leaf_field = |member| TraceField::Leaf {
+        kind,
+        index,
+        member,
+    };
+    Ok(domain_hash(
+        fr_checked(leaf.sibling, leaf_field("sibling"))?,
+        fr_checked(leaf.value, leaf_field("value"))?,
+        HashDomain::Leaf,
+    ))
+}
+
+/// Checks that neither path is deeper than the 256 bits of a key, which give the directions.
+pub(crate) fn check_depth(kind: PathKind, paths: [&[SMTNode]; 2]) -> Result<(), TraceError> {
+    for (index, nodes) in paths.into_iter().enumerate() {
+        if nodes.len() > 256 {
+            return Err(TraceError::Inconsistent(
+                node_field(kind, index, 256, "node_type"),
+                "path is deeper than the number of bits in a key",
+            ));
+        }
+    }
+    Ok(())
 }
 
-fn leaf_hash(leaf: SMTNode) -> Fr {
-    domain_hash(fr(leaf.sibling), fr(leaf.value), HashDomain::Leaf)
+pub(crate) fn node_field(
+    kind: PathKind,
+    index: usize,
+    depth: usize,
+    member: &'static str,
+) -> TraceField {
+    TraceField::Node {
+        kind,
+        index,
+        depth,
+        member,
+    }
+}
+
+pub(crate) fn node_fr(
+    kind: PathKind,
+    index: usize,
+    depth: usize,
+    member: &'static str,
+    x: HexBytes<32>,
+) -> Result<Fr, TraceError> {
+    fr_checked(x, node_field(kind, index, depth, member))
+}
+
+/// Domain of a node in a path, which has to be a branch node.
+pub(crate) fn node_domain(
+    kind: PathKind,
+    index: usize,
+    depth: usize,
+    node: &SMTNode,
+) -> Result<HashDomain, TraceError> {
+    match HashDomain::try_from(node.node_type) {
+        Ok(
+            domain @ (HashDomain::Branch0
+            | HashDomain::Branch1
+            | HashDomain::Branch2
+            | HashDomain::Branch3),
+        ) => Ok(domain),
+        _ => Err(TraceError::InvalidNodeType(
+            node_field(kind, index, depth, "node_type"),
+            node.node_type,
+        )),
+    }
+}
+
+/// Domain of the branch node at `depth` in both the old and new paths, from before the leaf
+/// insertion or after the leaf deletion if the paths have different lengths.
+pub(crate) fn common_domain(
+    kind: PathKind,
+    old_nodes: &[SMTNode],
+    new_nodes: &[SMTNode],
+    depth: usize,
+    direction: bool,
+) -> Result<HashDomain, TraceError> {
+    let old_domain = node_domain(kind, 0, depth, &old_nodes[depth])?;
+    let new_domain = node_domain(kind, 1, depth, &new_nodes[depth])?;
+    let is_last_common_node = depth + 1 == std::cmp::min(old_nodes.len(), new_nodes.len());
+    let node_type_field = node_field(kind, 1, depth, "node_type");
+
+    if !is_last_common_node || old_nodes.len() == new_nodes.len() {
+        return if old_domain == new_domain {
+            Ok(old_domain)
+        } else {
+            // This can only happen when inserting or deleting a node.
+            Err(TraceError::Inconsistent(
+                node_type_field,
+                "node type can only change where a leaf is inserted or deleted",
+            ))
+        };
+    }
+
+    let [before, after] = if depth + 1 == old_nodes.len() {
+        // Inserting a leaf, so old is before insertion, new is after insertion.
+        [old_domain, new_domain]
+    } else {
+        // Deleting a leaf, so new is after insertion, old is before insertion.
+        [new_domain, old_domain]
+    };
+    if domains_consistent(before, after, direction) {
+        Ok(before)
+    } else {
+        Err(TraceError::Inconsistent(
+            node_type_field,
+            "node type does not match the inserted or deleted leaf",
+        ))
+    }
 }
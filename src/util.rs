@@ -1,4 +1,8 @@
-use crate::{constraint_builder::Query, serde::HexBytes, types::HashDomain};
+use crate::{
+    constraint_builder::Query,
+    serde::HexBytes,
+    types::{HashDomain, TraceError, TraceField},
+};
 use ethers_core::types::{Address, U256};
 use halo2_proofs::{
     arithmetic::Field,
@@ -7,8 +11,8 @@ use halo2_proofs::{
 use hash_circuit::hash::Hashable;
 use num_bigint::BigUint;
 
-pub(crate) fn fr(x: HexBytes<32>) -> Fr {
-    Fr::from_bytes(&x.0).unwrap()
+pub(crate) fn fr_checked(x: HexBytes<32>, field: TraceField) -> Result<Fr, TraceError> {
+    Option::from(Fr::from_bytes(&x.0)).ok_or(TraceError::NonCanonical(field))
 }
 
 pub fn domain_hash(x: Fr, y: Fr, domain: HashDomain) -> Fr {
@@ -66,6 +70,13 @@ pub(crate) fn fr_from_biguint(b: &BigUint) -> Fr {
         })
 }
 
+/// The first 4 bytes of a hash, in big endian hex, e.g. "0x1a2b3c4d…".
+pub(crate) fn hash_prefix(fr: Fr) -> String {
+    let mut bytes = fr.to_bytes();
+    bytes.reverse();
+    format!("0x{}…", hex::encode(&bytes[..4]))
+}
+
 pub fn rlc(be_bytes: &[u8], randomness: Fr) -> Fr {
     let x = be_bytes.iter().fold(Fr::zero(), |acc, byte| {
         randomness * acc + Fr::from(u64::from(*byte))
@@ -98,17 +109,13 @@ pub fn account_key(address: Address) -> Fr {
 }
 
 // Sanity check that before and after branch types match the direction
-pub fn check_domain_consistency(before: HashDomain, after: HashDomain, direction: bool) {
+pub fn domains_consistent(before: HashDomain, after: HashDomain, direction: bool) -> bool {
     if direction {
-        assert!(
-            before == HashDomain::Branch0 && after == HashDomain::Branch1
-                || before == HashDomain::Branch2 && after == HashDomain::Branch3
-        );
+        before == HashDomain::Branch0 && after == HashDomain::Branch1
+            || before == HashDomain::Branch2 && after == HashDomain::Branch3
     } else {
-        assert!(
-            before == HashDomain::Branch0 && after == HashDomain::Branch2
-                || before == HashDomain::Branch1 && after == HashDomain::Branch3
-        );
+        before == HashDomain::Branch0 && after == HashDomain::Branch2
+            || before == HashDomain::Branch1 && after == HashDomain::Branch3
     }
 }
 
@@ -0,0 +1,229 @@
+//! Checks that an `SMTTrace` is a valid witness for an mpt update without running the circuit, so
+//! that bad traces can be rejected before any proving time is spent on them.
+use crate::{
+    serde::{AccountData, SMTTrace},
+    types::{ClaimKind, Proof, TraceError},
+    MPTProofType,
+};
+use halo2_proofs::halo2curves::bn256::Fr;
+use strum::IntoEnumIterator;
+
+/// Summary of a trace that passed `validate`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ValidationReport {
+    pub proof_type: MPTProofType,
+    /// Account trie root before the update.
+    pub old_root: Fr,
+    /// Account trie root after the update.
+    pub new_root: Fr,
+    /// Number of nodes in the old and new account paths.
+    pub account_path_lengths: [usize; 2],
+    /// Number of nodes in the old and new storage paths, if the trace has them.
+    pub state_path_lengths: Option<[usize; 2]>,
+    /// Number of rows the update uses in the mpt update gadget.
+    pub n_rows: usize,
+}
+
+/// Checks that `trace` is a valid witness for a `proof_type` update. This recomputes every hash in
+/// the trace, and checks that `account_key` is the hash of `address`, that `state_key` is the hash
+/// of the storage key, that the path directions match the keys, and that the paths hash up to the
+/// claimed roots.
+pub fn validate(
+    trace: &SMTTrace,
+    proof_type: MPTProofType,
+) -> Result<ValidationReport, TraceError> {
+    let proof = Proof::try_from((proof_type, trace.clone()))?;
+    proof.validate()?;
+
+    let [old_account_path, new_account_path] = &trace.account_path;
+    let state_path_lengths = match &trace.state_path {
+        [Some(old_path), Some(new_path)] => Some([old_path.path.len(), new_path.path.len()]),
+        _ => None,
+    };
+    Ok(ValidationReport {
+        proof_type,
+        old_root: proof.claim.old_root,
+        new_root: proof.claim.new_root,
+        account_path_lengths: [old_account_path.path.len(), new_account_path.path.len()],
+        state_path_lengths,
+        n_rows: proof.n_rows(),
+    })
+}
+
+/// Checks that `proof_type` is consistent with the account and storage updates in `trace`,
+/// without recomputing any hashes.
+pub fn check_proof_type(trace: &SMTTrace, proof_type: MPTProofType) -> Result<(), TraceError> {
+    let found = MPTProofType::from(ClaimKind::try_from((&proof_type, trace))?);
+    if found != proof_type {
+        return Err(TraceError::ProofTypeMismatch { proof_type, found });
+    }
+    Ok(())
+}
+
+/// The proof types that `check_proof_type` accepts for `trace`. Reads are consistent with more
+/// than one type, e.g. a trace for an existing account where nothing changes is a valid read of
+/// any of its fields.
+///
+/// No single proof type is consistent with a trace that changes several account fields at once.
+/// For those, the proof types of each changed field are returned instead, and the trace needs to
+/// be split into one update per field. An empty list means the trace is not a valid update.
+pub fn infer_proof_types(trace: &SMTTrace) -> Vec<MPTProofType> {
+    let consistent: Vec<_> = MPTProofType::iter()
+        .filter(|proof_type| check_proof_type(trace, *proof_type).is_ok())
+        .collect();
+    if !consistent.is_empty() || !matches!(trace.state_update, None | Some([None, None])) {
+        return consistent;
+    }
+    match &trace.account_update {
+        [Some(old), Some(new)] => changed_fields(old, new),
+        _ => vec![],
+    }
+}
+
+fn changed_fields(old: &AccountData, new: &AccountData) -> Vec<MPTProofType> {
+    [
+        (old.nonce != new.nonce, MPTProofType::NonceChanged),
+        (old.balance != new.balance, MPTProofType::BalanceChanged),
+        (old.code_hash != new.code_hash, MPTProofType::CodeHashExists),
+        (
+            old.poseidon_code_hash != new.poseidon_code_hash,
+            MPTProofType::PoseidonCodeHashExists,
+        ),
+        (old.code_size != new.code_size, MPTProofType::CodeSizeExists),
+    ]
+    .into_iter()
+    .filter_map(|(changed, proof_type)| changed.then_some(proof_type))
+    .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        serde::HexBytes,
+        types::{storage::StorageProof, PathKind, TraceField},
+    };
+
+    fn existing_storage_update() -> SMTTrace {
+        serde_json::from_str(include_str!("traces/existing_storage_update.json")).unwrap()
+    }
+
+    fn existing_account_balance_update() -> SMTTrace {
+        serde_json::from_str(include_str!("traces/existing_account_balance_update.json")).unwrap()
+    }
+
+    #[test]
+    fn valid_trace() {
+        let trace = existing_storage_update();
+        let report = validate(&trace, MPTProofType::StorageChanged).unwrap();
+
+        assert_eq!(report.proof_type, MPTProofType::StorageChanged);
+        assert_eq!(
+            report.old_root,
+            Fr::from_bytes(&trace.account_path[0].root.0).unwrap()
+        );
+        assert_eq!(
+            report.new_root,
+            Fr::from_bytes(&trace.account_path[1].root.0).unwrap()
+        );
+        assert_eq!(
+            report.account_path_lengths,
+            [
+                trace.account_path[0].path.len(),
+                trace.account_path[1].path.len()
+            ]
+        );
+        assert!(report.state_path_lengths.is_some());
+    }
+
+    #[test]
+    fn wrong_root() {
+        let mut trace = existing_storage_update();
+        trace.account_path[1].root = HexBytes(Fr::one().to_bytes());
+
+        assert_eq!(
+            validate(&trace, MPTProofType::StorageChanged).unwrap_err(),
+            TraceError::Inconsistent(
+                TraceField::Root {
+                    kind: PathKind::Account,
+                    index: 1,
+                },
+                "root is not the hash of the top node of the path"
+            )
+        );
+    }
+
+    #[test]
+    fn wrong_state_key() {
+        let mut proof =
+            Proof::try_from((MPTProofType::StorageChanged, existing_storage_update())).unwrap();
+        if let StorageProof::Update { key, .. } = &mut proof.storage {
+            *key = Fr::one();
+        }
+
+        assert_eq!(
+            proof.validate().unwrap_err(),
+            TraceError::Inconsistent(
+                TraceField::StateKey,
+                "state_key is not the hash of the storage key"
+            )
+        );
+    }
+
+    #[test]
+    fn infer_single_update() {
+        assert_eq!(
+            infer_proof_types(&existing_storage_update()),
+            vec![MPTProofType::StorageChanged]
+        );
+        assert_eq!(
+            infer_proof_types(&existing_account_balance_update()),
+            vec![MPTProofType::BalanceChanged]
+        );
+    }
+
+    #[test]
+    fn infer_read() {
+        let mut trace = existing_account_balance_update();
+        trace.account_update[1] = trace.account_update[0].clone();
+
+        assert_eq!(
+            infer_proof_types(&trace),
+            vec![
+                MPTProofType::NonceChanged,
+                MPTProofType::BalanceChanged,
+                MPTProofType::CodeHashExists,
+                MPTProofType::PoseidonCodeHashExists,
+                MPTProofType::CodeSizeExists,
+            ]
+        );
+    }
+
+    #[test]
+    fn infer_several_changes() {
+        let mut trace = existing_account_balance_update();
+        trace.account_update[1].as_mut().unwrap().nonce += 1;
+
+        assert_eq!(
+            infer_proof_types(&trace),
+            vec![MPTProofType::NonceChanged, MPTProofType::BalanceChanged]
+        );
+        assert!(check_proof_type(&trace, MPTProofType::BalanceChanged).is_err());
+    }
+
+    #[test]
+    fn declared_type_contradicts_trace() {
+        assert_eq!(
+            check_proof_type(&existing_storage_update(), MPTProofType::NonceChanged),
+            Err(TraceError::ProofTypeMismatch {
+                proof_type: MPTProofType::NonceChanged,
+                found: MPTProofType::StorageChanged,
+            })
+        );
+        assert!(check_proof_type(
+            &existing_account_balance_update(),
+            MPTProofType::CodeSizeExists
+        )
+        .is_err());
+    }
+}
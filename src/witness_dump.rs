@@ -0,0 +1,51 @@
+//! Dumps of the values assigned to the mpt circuit's columns, to inspect a witness offline.
+use serde::Serialize;
+use std::io::{self, Write};
+
+/// Values assigned to the columns of the mpt_update, key_bit, canonical_representation, and
+/// byte_representation gadgets, from `MptCircuitConfig::witness_dump`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct WitnessDump {
+    /// Column names, prefixed by their gadget, e.g. `mpt_update.old_hash`.
+    pub columns: Vec<String>,
+    pub rows: Vec<WitnessDumpRow>,
+}
+
+/// Values of one row. The one hot columns of the mpt update gadget are decoded to labels.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct WitnessDumpRow {
+    pub row: usize,
+    pub proof_type: String,
+    pub segment_type: String,
+    pub path_type: String,
+    /// Values in the order of `WitnessDump::columns`. Small values and their negations are in
+    /// decimal, and everything else is in hex.
+    pub values: Vec<String>,
+}
+
+impl WitnessDump {
+    /// Writes a header line, and then one line per row.
+    pub fn write_csv(&self, mut writer: impl Write) -> io::Result<()> {
+        write!(writer, "row,proof_type,segment_type,path_type")?;
+        for column in &self.columns {
+            write!(writer, ",{column}")?;
+        }
+        writeln!(writer)?;
+        for row in &self.rows {
+            write!(
+                writer,
+                "{},{},{},{}",
+                row.row, row.proof_type, row.segment_type, row.path_type
+            )?;
+            for value in &row.values {
+                write!(writer, ",{value}")?;
+            }
+            writeln!(writer)?;
+        }
+        Ok(())
+    }
+
+    pub fn write_json(&self, writer: impl Write) -> serde_json::Result<()> {
+        serde_json::to_writer(writer, self)
+    }
+}
@@ -0,0 +1,1003 @@
+//! Native zkTrie implementation for generating `SMTTrace`s without an external witness generator.
+//!
+//! The trie is a sparse binary Poseidon trie keyed by `account_key(address)` for accounts and
+//! `storage_key_hash(key)` for storage slots. Key bits are consumed starting from the least
+//! significant bit, and branch nodes are hashed with the `HashDomain::Branch0`..`Branch3` domain
+//! matching whether their children are branch nodes.
+use crate::{
+    serde::{AccountData, HexBytes, SMTNode, SMTPath, SMTTrace, StateData},
+    types::{account_hash_traces, HashDomain},
+    util::{
+        account_key, domain_hash, split_word, storage_key_hash, u256_from_biguint,
+        u256_to_big_endian, Bit,
+    },
+    MPTProofType,
+};
+use ethers_core::types::{Address, U256};
+use halo2_proofs::halo2curves::bn256::Fr;
+use num_bigint::BigUint;
+use num_traits::Zero;
+use std::collections::BTreeMap;
+
+#[derive(Clone, Debug, Default)]
+enum Node {
+    #[default]
+    Empty,
+    Leaf {
+        key: Fr,
+        value_hash: Fr,
+        hash: Fr,
+    },
+    Branch {
+        left: Box<Node>,
+        right: Box<Node>,
+        hash: Fr,
+    },
+}
+
+impl Node {
+    fn leaf(key: Fr, value_hash: Fr) -> Self {
+        Self::Leaf {
+            key,
+            value_hash,
+            hash: domain_hash(key, value_hash, HashDomain::Leaf),
+        }
+    }
+
+    fn branch(left: Node, right: Node) -> Self {
+        let hash = domain_hash(left.hash(), right.hash(), branch_domain(&left, &right));
+        Self::Branch {
+            left: Box::new(left),
+            right: Box::new(right),
+            hash,
+        }
+    }
+
+    fn hash(&self) -> Fr {
+        match self {
+            Self::Empty => Fr::zero(),
+            Self::Leaf { hash, .. } | Self::Branch { hash, .. } => *hash,
+        }
+    }
+
+    fn is_branch(&self) -> bool {
+        matches!(self, Self::Branch { .. })
+    }
+
+    fn update(self, key: Fr, value_hash: Fr, depth: usize) -> Self {
+        match self {
+            Self::Empty => Self::leaf(key, value_hash),
+            Self::Leaf {
+                key: existing_key, ..
+            } if existing_key == key => Self::leaf(key, value_hash),
+            Self::Leaf { .. } => split(self, Self::leaf(key, value_hash), depth),
+            Self::Branch { left, right, .. } => {
+                if key.bit(depth) {
+                    Self::branch(*left, right.update(key, value_hash, depth + 1))
+                } else {
+                    Self::branch(left.update(key, value_hash, depth + 1), *right)
+                }
+            }
+        }
+    }
+
+    fn delete(self, key: Fr, depth: usize) -> Self {
+        match self {
+            Self::Empty => Self::Empty,
+            Self::Leaf {
+                key: existing_key, ..
+            } if existing_key == key => Self::Empty,
+            Self::Leaf { .. } => self,
+            Self::Branch { left, right, .. } => {
+                let (left, right) = if key.bit(depth) {
+                    (*left, right.delete(key, depth + 1))
+                } else {
+                    (left.delete(key, depth + 1), *right)
+                };
+                // A branch left with a single leaf (and no other nodes) below it collapses into
+                // that leaf.
+                match (&left, &right) {
+                    (Self::Empty, Self::Empty) => Self::Empty,
+                    (Self::Empty, Self::Leaf { .. }) => right,
+                    (Self::Leaf { .. }, Self::Empty) => left,
+                    _ => Self::branch(left, right),
+                }
+            }
+        }
+    }
+}
+
+// Inserts new_leaf below a position currently occupied by existing_leaf.
+fn split(existing_leaf: Node, new_leaf: Node, depth: usize) -> Node {
+    let (existing_key, new_key) = match (&existing_leaf, &new_leaf) {
+        (Node::Leaf { key: a, .. }, Node::Leaf { key: b, .. }) => (*a, *b),
+        _ => unreachable!("can only split leaf nodes"),
+    };
+    // `Node::update` replaces leaves with an equal key instead of splitting them, so the keys
+    // differ in some bit and the recursion ends before the last bit.
+    debug_assert_ne!(existing_key, new_key);
+    match (existing_key.bit(depth), new_key.bit(depth)) {
+        (false, false) => Node::branch(split(existing_leaf, new_leaf, depth + 1), Node::Empty),
+        (true, true) => Node::branch(Node::Empty, split(existing_leaf, new_leaf, depth + 1)),
+        (false, true) => Node::branch(existing_leaf, new_leaf),
+        (true, false) => Node::branch(new_leaf, existing_leaf),
+    }
+}
+
+fn branch_domain(left: &Node, right: &Node) -> HashDomain {
+    match (left.is_branch(), right.is_branch()) {
+        (false, false) => HashDomain::Branch0,
+        (false, true) => HashDomain::Branch1,
+        (true, false) => HashDomain::Branch2,
+        (true, true) => HashDomain::Branch3,
+    }
+}
+
+fn hex(x: Fr) -> HexBytes<32> {
+    HexBytes(x.to_bytes())
+}
+
+fn big_uint(x: U256) -> BigUint {
+    BigUint::from_bytes_be(&u256_to_big_endian(&x))
+}
+
+/// Sparse binary Poseidon trie with the same node hashing as zkTrie.
+#[derive(Clone, Debug, Default)]
+pub struct ZkTrie {
+    root: Node,
+}
+
+impl ZkTrie {
+    /// Root hash of the trie. The root of an empty trie is 0.
+    pub fn root(&self) -> Fr {
+        self.root.hash()
+    }
+
+    /// Value hash of the leaf with `key`, if it exists.
+    pub fn get(&self, key: Fr) -> Option<Fr> {
+        let mut node = &self.root;
+        let mut depth = 0;
+        loop {
+            match node {
+                Node::Empty => return None,
+                Node::Leaf {
+                    key: leaf_key,
+                    value_hash,
+                    ..
+                } => return (*leaf_key == key).then_some(*value_hash),
+                Node::Branch { left, right, .. } => {
+                    node = if key.bit(depth) { &**right } else { &**left };
+                    depth += 1;
+                }
+            }
+        }
+    }
+
+    /// Inserts a leaf for `key`, or updates its value hash if it already exists.
+    pub fn update(&mut self, key: Fr, value_hash: Fr) {
+        self.root = std::mem::take(&mut self.root).update(key, value_hash, 0);
+    }
+
+    /// Deletes the leaf for `key`, if it exists.
+    pub fn delete(&mut self, key: Fr) {
+        self.root = std::mem::take(&mut self.root).delete(key, 0);
+    }
+
+    /// Merkle path from the root to the leaf or empty node that `key` ends at.
+    pub fn path(&self, key: Fr) -> SMTPath {
+        let mut path = vec![];
+        let mut path_part = BigUint::default();
+        let mut node = &self.root;
+        while let Node::Branch { left, right, .. } = node {
+            let depth = path.len();
+            let (child, sibling) = if key.bit(depth) {
+                path_part += BigUint::from(1u8) << depth;
+                (&**right, &**left)
+            } else {
+                (&**left, &**right)
+            };
+            path.push(SMTNode {
+                value: hex(child.hash()),
+                sibling: hex(sibling.hash()),
+                node_type: branch_domain(left, right).into(),
+            });
+            node = child;
+        }
+        let leaf = match node {
+            Node::Leaf {
+                key, value_hash, ..
+            } => Some(SMTNode {
+                value: hex(*value_hash),
+                sibling: hex(*key),
+                node_type: HashDomain::Leaf.into(),
+            }),
+            _ => None,
+        };
+
+        SMTPath {
+            root: hex(self.root()),
+            leaf,
+            path,
+            path_part,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+struct Account {
+    data: AccountData,
+    storage: ZkTrie,
+    storage_values: BTreeMap<U256, U256>,
+}
+
+impl Account {
+    fn value_hash(&self, address: Address) -> Fr {
+        account_hash_traces(address, self.data.clone(), self.storage.root())[5][1]
+    }
+}
+
+/// A change to (or read of) the state, as a single mpt update.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StateOp {
+    SetNonce {
+        address: Address,
+        nonce: u64,
+    },
+    SetBalance {
+        address: Address,
+        balance: U256,
+    },
+    /// Adds `amount` to the current balance of the account, which is 0 if it does not exist.
+    AddBalance {
+        address: Address,
+        amount: U256,
+    },
+    SetCodeHash {
+        address: Address,
+        code_hash: U256,
+    },
+    SetPoseidonCodeHash {
+        address: Address,
+        poseidon_code_hash: U256,
+    },
+    SetCodeSize {
+        address: Address,
+        code_size: u64,
+    },
+    /// Writing 0 deletes the storage slot.
+    WriteStorage {
+        address: Address,
+        key: U256,
+        value: U256,
+    },
+    /// Reads the current value of the storage slot.
+    ReadStorage {
+        address: Address,
+        key: U256,
+    },
+    /// Proves that the account does not exist.
+    ReadMissingAccount {
+        address: Address,
+    },
+    DestructAccount {
+        address: Address,
+    },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, thiserror::Error)]
+/// Operations that cannot be applied to the current state.
+pub enum StateOpError {
+    #[error("account {0:?} exists")]
+    /// the account is read as missing, but it exists
+    AccountExists(Address),
+    #[error("account {0:?} does not exist")]
+    /// the account is destructed or has storage written, but it does not exist
+    MissingAccount(Address),
+    #[error("nonce or balance must be the first field set on empty account {0:?}")]
+    /// a new account is created by setting a field other than its nonce or balance
+    EmptyAccountField(Address),
+    #[error("balance of account {0:?} overflows")]
+    /// AddBalance overflows the balance of the account
+    BalanceOverflow(Address),
+    #[error("{value} does not fit in the {proof_type:?} field, which is a u64")]
+    /// the new value of a u64 account field is too large
+    ValueOverflow {
+        proof_type: MPTProofType,
+        value: U256,
+    },
+    #[error("{0:?} proof requires a storage key")]
+    /// the storage key is None for a storage proof type
+    MissingStorageKey(MPTProofType),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, thiserror::Error)]
+#[error("operation {index} cannot be applied: {error}")]
+/// An operation in `WitnessGenerator::apply_all` cannot be applied. The operations before it have
+/// been applied, and `applied` has their proof types and traces.
+pub struct ApplyAllError {
+    pub index: usize,
+    pub error: StateOpError,
+    pub applied: Vec<(MPTProofType, SMTTrace)>,
+}
+
+/// Keeps the account and storage tries of a state and produces the `SMTTrace` for each update
+/// applied to it.
+#[derive(Clone, Debug, Default)]
+pub struct WitnessGenerator {
+    account_trie: ZkTrie,
+    accounts: BTreeMap<Address, Account>,
+}
+
+impl WitnessGenerator {
+    /// State root of the account trie.
+    pub fn root(&self) -> Fr {
+        self.account_trie.root()
+    }
+
+    /// Account data for `address`, if the account exists.
+    pub fn account(&self, address: Address) -> Option<&AccountData> {
+        self.accounts.get(&address).map(|account| &account.data)
+    }
+
+    /// Value of the storage slot `key` for `address`. Missing slots have value 0.
+    pub fn storage(&self, address: Address, key: U256) -> U256 {
+        self.accounts
+            .get(&address)
+            .and_then(|account| account.storage_values.get(&key).copied())
+            .unwrap_or_default()
+    }
+
+    /// Applies the update and returns its trace. `new_value` is ignored for proof types that don't
+    /// write a value (AccountDoesNotExist, StorageDoesNotExist, and AccountDestructed) and `key`
+    /// is the storage key for StorageChanged and StorageDoesNotExist. A StorageChanged update that
+    /// writes 0 to an empty slot has a StorageDoesNotExist trace, as `apply` labels it.
+    ///
+    /// Panics if the update cannot be applied to the current state. Use `apply` to get a
+    /// `StateOpError` instead.
+    pub fn handle_new_state(
+        &mut self,
+        proof_type: MPTProofType,
+        address: Address,
+        new_value: U256,
+        key: Option<U256>,
+    ) -> SMTTrace {
+        self.try_handle_new_state(proof_type, address, new_value, key)
+            .unwrap_or_else(|error| panic!("invalid state update: {error}"))
+    }
+
+    fn try_handle_new_state(
+        &mut self,
+        proof_type: MPTProofType,
+        address: Address,
+        new_value: U256,
+        key: Option<U256>,
+    ) -> Result<SMTTrace, StateOpError> {
+        match proof_type {
+            MPTProofType::StorageChanged | MPTProofType::StorageDoesNotExist => {
+                let key = key.ok_or(StateOpError::MissingStorageKey(proof_type))?;
+                let new_value = if proof_type == MPTProofType::StorageChanged {
+                    new_value
+                } else {
+                    self.storage(address, key)
+                };
+                self.handle_storage(address, key, new_value)
+            }
+            _ => self.handle_account(proof_type, address, new_value),
+        }
+    }
+
+    /// Applies the operation and returns its proof type and trace. The state is unchanged if the
+    /// operation cannot be applied.
+    pub fn apply(&mut self, op: StateOp) -> Result<(MPTProofType, SMTTrace), StateOpError> {
+        let (proof_type, address, new_value, key) = match op {
+            StateOp::SetNonce { address, nonce } => {
+                (MPTProofType::NonceChanged, address, U256::from(nonce), None)
+            }
+            StateOp::SetBalance { address, balance } => {
+                (MPTProofType::BalanceChanged, address, balance, None)
+            }
+            StateOp::AddBalance { address, amount } => {
+                let balance = self
+                    .account(address)
+                    .map_or_else(U256::zero, |account| u256_from_biguint(&account.balance));
+                let balance = balance
+                    .checked_add(amount)
+                    .ok_or(StateOpError::BalanceOverflow(address))?;
+                (MPTProofType::BalanceChanged, address, balance, None)
+            }
+            StateOp::SetCodeHash { address, code_hash } => {
+                (MPTProofType::CodeHashExists, address, code_hash, None)
+            }
+            StateOp::SetPoseidonCodeHash {
+                address,
+                poseidon_code_hash,
+            } => (
+                MPTProofType::PoseidonCodeHashExists,
+                address,
+                poseidon_code_hash,
+                None,
+            ),
+            StateOp::SetCodeSize { address, code_size } => (
+                MPTProofType::CodeSizeExists,
+                address,
+                U256::from(code_size),
+                None,
+            ),
+            StateOp::WriteStorage {
+                address,
+                key,
+                value,
+            } => {
+                // Writing 0 to an empty slot, including one of a missing account, is proven like
+                // reading it.
+                let proof_type = if value.is_zero() && self.storage(address, key).is_zero() {
+                    MPTProofType::StorageDoesNotExist
+                } else {
+                    MPTProofType::StorageChanged
+                };
+                (proof_type, address, value, Some(key))
+            }
+            StateOp::ReadStorage { address, key } => {
+                let value = self.storage(address, key);
+                let proof_type = if value.is_zero() {
+                    MPTProofType::StorageDoesNotExist
+                } else {
+                    MPTProofType::StorageChanged
+                };
+                (proof_type, address, value, Some(key))
+            }
+            StateOp::ReadMissingAccount { address } => (
+                MPTProofType::AccountDoesNotExist,
+                address,
+                U256::zero(),
+                None,
+            ),
+            StateOp::DestructAccount { address } => {
+                (MPTProofType::AccountDestructed, address, U256::zero(), None)
+            }
+        };
+        Ok((
+            proof_type,
+            self.try_handle_new_state(proof_type, address, new_value, key)?,
+        ))
+    }
+
+    /// Applies the operations in order. The old root of each trace is the new root of the one
+    /// before it. Stops at the first operation that cannot be applied, after applying the ones
+    /// before it, whose traces are returned in the error.
+    pub fn apply_all(
+        &mut self,
+        ops: impl IntoIterator<Item = StateOp>,
+    ) -> Result<Vec<(MPTProofType, SMTTrace)>, ApplyAllError> {
+        let mut applied = vec![];
+        for (index, op) in ops.into_iter().enumerate() {
+            match self.apply(op) {
+                Ok(trace) => applied.push(trace),
+                Err(error) => {
+                    return Err(ApplyAllError {
+                        index,
+                        error,
+                        applied,
+                    })
+                }
+            }
+        }
+        Ok(applied)
+    }
+
+    fn handle_account(
+        &mut self,
+        proof_type: MPTProofType,
+        address: Address,
+        new_value: U256,
+    ) -> Result<SMTTrace, StateOpError> {
+        let account_key = account_key(address);
+        let old_path = self.account_trie.path(account_key);
+        let old_account = self.accounts.get(&address).cloned();
+
+        let new_account = match proof_type {
+            MPTProofType::AccountDoesNotExist if old_account.is_some() => {
+                return Err(StateOpError::AccountExists(address))
+            }
+            MPTProofType::AccountDestructed if old_account.is_none() => {
+                return Err(StateOpError::MissingAccount(address))
+            }
+            MPTProofType::AccountDoesNotExist | MPTProofType::AccountDestructed => None,
+            _ if old_account.is_none() && new_value.is_zero() => None,
+            _ => {
+                let mut account = old_account.clone().unwrap_or_default();
+                let data = &mut account.data;
+                let as_u64 = |value: U256| {
+                    u64::try_from(value)
+                        .map_err(|_| StateOpError::ValueOverflow { proof_type, value })
+                };
+                match proof_type {
+                    MPTProofType::NonceChanged => data.nonce = as_u64(new_value)?,
+                    MPTProofType::BalanceChanged => data.balance = big_uint(new_value),
+                    MPTProofType::CodeHashExists => data.code_hash = big_uint(new_value),
+                    MPTProofType::PoseidonCodeHashExists => {
+                        data.poseidon_code_hash = big_uint(new_value)
+                    }
+                    MPTProofType::CodeSizeExists => data.code_size = as_u64(new_value)?,
+                    _ => unreachable!(),
+                }
+                if old_account.is_none() && data.nonce == 0 && data.balance.is_zero() {
+                    return Err(StateOpError::EmptyAccountField(address));
+                }
+                Some(account)
+            }
+        };
+
+        let common_state_root = old_account
+            .as_ref()
+            .map_or_else(Fr::zero, |account| account.storage.root());
+        match &new_account {
+            Some(account) => self
+                .account_trie
+                .update(account_key, account.value_hash(address)),
+            None => self.account_trie.delete(account_key),
+        }
+        let new_path = self.account_trie.path(account_key);
+        let account_update = [
+            old_account.as_ref().map(|account| account.data.clone()),
+            new_account.as_ref().map(|account| account.data.clone()),
+        ];
+        match new_account {
+            Some(account) => self.accounts.insert(address, account),
+            None => self.accounts.remove(&address),
+        };
+
+        Ok(SMTTrace {
+            address: HexBytes(address.0),
+            account_key: hex(account_key),
+            account_path: [old_path, new_path],
+            account_update,
+            state_path: [None, None],
+            common_state_root: Some(hex(common_state_root)),
+            state_key: None,
+            state_update: None,
+        })
+    }
+
+    fn handle_storage(
+        &mut self,
+        address: Address,
+        key: U256,
+        new_value: U256,
+    ) -> Result<SMTTrace, StateOpError> {
+        let account_key = account_key(address);
+        let storage_key = storage_key_hash(key);
+        let mut key_bytes = [0; 32];
+        key.to_big_endian(&mut key_bytes);
+        let state_data = |value: U256| {
+            let mut value_bytes = [0; 32];
+            value.to_big_endian(&mut value_bytes);
+            Some(StateData {
+                key: HexBytes(key_bytes),
+                value: HexBytes(value_bytes),
+            })
+        };
+
+        let old_path = self.account_trie.path(account_key);
+        let Some(account) = self.accounts.get_mut(&address) else {
+            if !new_value.is_zero() {
+                return Err(StateOpError::MissingAccount(address));
+            }
+            return Ok(SMTTrace {
+                address: HexBytes(address.0),
+                account_key: hex(account_key),
+                account_path: [old_path.clone(), old_path],
+                account_update: [None, None],
+                state_path: [None, None],
+                common_state_root: Some(hex(Fr::zero())),
+                state_key: Some(hex(storage_key)),
+                state_update: Some([state_data(U256::zero()), state_data(U256::zero())]),
+            });
+        };
+
+        let old_value = account
+            .storage_values
+            .get(&key)
+            .copied()
+            .unwrap_or_default();
+        let old_state_path = account.storage.path(storage_key);
+        if new_value.is_zero() {
+            account.storage.delete(storage_key);
+            account.storage_values.remove(&key);
+        } else {
+            let (high, low) = split_word(new_value);
+            account
+                .storage
+                .update(storage_key, domain_hash(high, low, HashDomain::Pair));
+            account.storage_values.insert(key, new_value);
+        }
+        let new_state_path = account.storage.path(storage_key);
+
+        let value_hash = account.value_hash(address);
+        let account_data = account.data.clone();
+        self.account_trie.update(account_key, value_hash);
+        let new_path = self.account_trie.path(account_key);
+
+        Ok(SMTTrace {
+            address: HexBytes(address.0),
+            account_key: hex(account_key),
+            account_path: [old_path, new_path],
+            account_update: [Some(account_data.clone()), Some(account_data)],
+            state_path: [Some(old_state_path), Some(new_state_path)],
+            common_state_root: None,
+            state_key: Some(hex(storage_key)),
+            state_update: Some([state_data(old_value), state_data(new_value)]),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::types::Proof;
+
+    const STORAGE_ADDRESS: Address = Address::repeat_byte(1);
+
+    fn initial_generator() -> WitnessGenerator {
+        let mut generator = WitnessGenerator::default();
+        for i in 1..10 {
+            generator.handle_new_state(
+                MPTProofType::BalanceChanged,
+                Address::repeat_byte(i),
+                U256::one(),
+                None,
+            );
+        }
+        generator
+    }
+
+    fn initial_storage_generator() -> WitnessGenerator {
+        let mut generator = initial_generator();
+        for i in 40..60 {
+            generator.handle_new_state(
+                MPTProofType::StorageChanged,
+                STORAGE_ADDRESS,
+                U256::one(),
+                Some(U256::from(i)),
+            );
+        }
+        generator
+    }
+
+    fn check_trace(proof_type: MPTProofType, trace: SMTTrace, expected: &str) {
+        let json = serde_json::to_string_pretty(&trace).unwrap();
+        assert_eq!(format!("{}\n", json), expected, "{}", json);
+        Proof::from_trace_unchecked((proof_type, trace)).check();
+    }
+
+    #[test]
+    fn empty_account_type_1() {
+        let mut generator = initial_generator();
+        let trace = generator.handle_new_state(
+            MPTProofType::AccountDoesNotExist,
+            Address::zero(),
+            U256::zero(),
+            None,
+        );
+        check_trace(
+            MPTProofType::AccountDoesNotExist,
+            trace,
+            include_str!("traces/empty_account_type_1.json"),
+        );
+    }
+
+    #[test]
+    fn empty_account_type_1_balance_update() {
+        let mut generator = initial_generator();
+        let trace = generator.handle_new_state(
+            MPTProofType::BalanceChanged,
+            Address::zero(),
+            U256::from(200),
+            None,
+        );
+        check_trace(
+            MPTProofType::BalanceChanged,
+            trace,
+            include_str!("traces/empty_account_type_1_balance_update.json"),
+        );
+    }
+
+    #[test]
+    fn empty_account_type_2_balance_update() {
+        let mut generator = initial_generator();
+        let trace = generator.handle_new_state(
+            MPTProofType::BalanceChanged,
+            Address::repeat_byte(20),
+            U256::from(123124128387u64),
+            None,
+        );
+        check_trace(
+            MPTProofType::BalanceChanged,
+            trace,
+            include_str!("traces/empty_account_type_2_balance_update.json"),
+        );
+    }
+
+    #[test]
+    fn existing_account_keccak_codehash_update() {
+        let mut generator = initial_generator();
+        let trace = generator.handle_new_state(
+            MPTProofType::CodeHashExists,
+            Address::repeat_byte(8),
+            U256([1111, u64::MAX, 444, 555]),
+            None,
+        );
+        check_trace(
+            MPTProofType::CodeHashExists,
+            trace,
+            include_str!("traces/existing_account_keccak_codehash_update.json"),
+        );
+    }
+
+    #[test]
+    fn existing_storage_update() {
+        let mut generator = initial_storage_generator();
+        let trace = generator.handle_new_state(
+            MPTProofType::StorageChanged,
+            STORAGE_ADDRESS,
+            U256::from(20),
+            Some(U256::from(40)),
+        );
+        check_trace(
+            MPTProofType::StorageChanged,
+            trace,
+            include_str!("traces/existing_storage_update.json"),
+        );
+    }
+
+    #[test]
+    fn empty_storage_type_1_update_a() {
+        let mut generator = initial_storage_generator();
+        let trace = generator.handle_new_state(
+            MPTProofType::StorageChanged,
+            STORAGE_ADDRESS,
+            U256::from(307),
+            Some(U256::from(23412321)),
+        );
+        check_trace(
+            MPTProofType::StorageChanged,
+            trace,
+            include_str!("traces/empty_storage_type_1_update_a.json"),
+        );
+    }
+
+    #[test]
+    fn delete_restores_root() {
+        let mut generator = initial_storage_generator();
+        let root = generator.root();
+
+        let trace = generator.handle_new_state(
+            MPTProofType::StorageChanged,
+            STORAGE_ADDRESS,
+            U256::from(307),
+            Some(U256::from(23412321)),
+        );
+        Proof::from_trace_unchecked((MPTProofType::StorageChanged, trace)).check();
+        let trace = generator.handle_new_state(
+            MPTProofType::StorageChanged,
+            STORAGE_ADDRESS,
+            U256::zero(),
+            Some(U256::from(23412321)),
+        );
+        Proof::from_trace_unchecked((MPTProofType::StorageChanged, trace)).check();
+        assert_eq!(generator.root(), root);
+
+        let trace = generator.handle_new_state(
+            MPTProofType::BalanceChanged,
+            Address::zero(),
+            U256::from(200),
+            None,
+        );
+        Proof::from_trace_unchecked((MPTProofType::BalanceChanged, trace)).check();
+        let trace = generator.handle_new_state(
+            MPTProofType::AccountDestructed,
+            Address::zero(),
+            U256::zero(),
+            None,
+        );
+        Proof::from_trace_unchecked((MPTProofType::AccountDestructed, trace)).check();
+        assert_eq!(generator.root(), root);
+    }
+
+    #[test]
+    fn apply_block_of_ops() {
+        let mut generator = initial_storage_generator();
+        let root = generator.root();
+        let address = Address::repeat_byte(30);
+
+        let traces = generator
+            .apply_all([
+                StateOp::ReadMissingAccount { address },
+                StateOp::AddBalance {
+                    address,
+                    amount: U256::from(10),
+                },
+                StateOp::AddBalance {
+                    address,
+                    amount: U256::from(5),
+                },
+                StateOp::SetNonce { address, nonce: 1 },
+                StateOp::SetCodeHash {
+                    address,
+                    code_hash: U256::from(1234),
+                },
+                StateOp::SetPoseidonCodeHash {
+                    address,
+                    poseidon_code_hash: U256::from(5678),
+                },
+                StateOp::SetCodeSize {
+                    address,
+                    code_size: 100,
+                },
+                StateOp::WriteStorage {
+                    address: STORAGE_ADDRESS,
+                    key: U256::from(40),
+                    value: U256::from(7),
+                },
+                StateOp::ReadStorage {
+                    address: STORAGE_ADDRESS,
+                    key: U256::from(40),
+                },
+                StateOp::ReadStorage {
+                    address: STORAGE_ADDRESS,
+                    key: U256::from(1000),
+                },
+                StateOp::DestructAccount { address },
+            ])
+            .unwrap();
+
+        assert_eq!(
+            traces
+                .iter()
+                .map(|(proof_type, _)| *proof_type)
+                .collect::<Vec<_>>(),
+            vec![
+                MPTProofType::AccountDoesNotExist,
+                MPTProofType::BalanceChanged,
+                MPTProofType::BalanceChanged,
+                MPTProofType::NonceChanged,
+                MPTProofType::CodeHashExists,
+                MPTProofType::PoseidonCodeHashExists,
+                MPTProofType::CodeSizeExists,
+                MPTProofType::StorageChanged,
+                MPTProofType::StorageChanged,
+                MPTProofType::StorageDoesNotExist,
+                MPTProofType::AccountDestructed,
+            ]
+        );
+        assert_eq!(traces[0].1.account_path[0].root, hex(root));
+        for ((_, trace), (_, next_trace)) in traces.iter().zip(&traces[1..]) {
+            assert_eq!(trace.account_path[1].root, next_trace.account_path[0].root);
+        }
+        assert_eq!(
+            traces[2].1.account_update[1].as_ref().unwrap().balance,
+            BigUint::from(15u64)
+        );
+
+        for trace in traces {
+            Proof::try_from(trace).unwrap().check();
+        }
+    }
+
+    #[test]
+    fn write_zero_to_empty_slot() {
+        let mut generator = initial_storage_generator();
+        let root = generator.root();
+        let missing = Address::repeat_byte(30);
+
+        let traces = generator
+            .apply_all([
+                StateOp::WriteStorage {
+                    address: STORAGE_ADDRESS,
+                    key: U256::from(1000),
+                    value: U256::zero(),
+                },
+                StateOp::WriteStorage {
+                    address: missing,
+                    key: U256::one(),
+                    value: U256::zero(),
+                },
+            ])
+            .unwrap();
+
+        assert_eq!(generator.root(), root);
+        for (proof_type, trace) in traces {
+            assert_eq!(proof_type, MPTProofType::StorageDoesNotExist);
+            Proof::try_from((proof_type, trace)).unwrap().check();
+        }
+    }
+
+    #[test]
+    fn invalid_ops() {
+        let mut generator = initial_storage_generator();
+        let root = generator.root();
+        let missing = Address::repeat_byte(30);
+
+        for (op, error) in [
+            (
+                StateOp::ReadMissingAccount {
+                    address: STORAGE_ADDRESS,
+                },
+                StateOpError::AccountExists(STORAGE_ADDRESS),
+            ),
+            (
+                StateOp::DestructAccount { address: missing },
+                StateOpError::MissingAccount(missing),
+            ),
+            (
+                StateOp::WriteStorage {
+                    address: missing,
+                    key: U256::one(),
+                    value: U256::one(),
+                },
+                StateOpError::MissingAccount(missing),
+            ),
+            (
+                StateOp::SetCodeSize {
+                    address: missing,
+                    code_size: 1,
+                },
+                StateOpError::EmptyAccountField(missing),
+            ),
+            (
+                StateOp::AddBalance {
+                    address: STORAGE_ADDRESS,
+                    amount: U256::MAX,
+                },
+                StateOpError::BalanceOverflow(STORAGE_ADDRESS),
+            ),
+        ] {
+            assert_eq!(generator.apply(op).unwrap_err(), error, "{op:?}");
+        }
+        assert_eq!(generator.root(), root);
+
+        let error = generator
+            .try_handle_new_state(MPTProofType::NonceChanged, STORAGE_ADDRESS, U256::MAX, None)
+            .unwrap_err();
+        assert_eq!(
+            error,
+            StateOpError::ValueOverflow {
+                proof_type: MPTProofType::NonceChanged,
+                value: U256::MAX
+            }
+        );
+        let error = generator
+            .try_handle_new_state(
+                MPTProofType::StorageChanged,
+                STORAGE_ADDRESS,
+                U256::one(),
+                None,
+            )
+            .unwrap_err();
+        assert_eq!(
+            error,
+            StateOpError::MissingStorageKey(MPTProofType::StorageChanged)
+        );
+
+        // The ops before the invalid one are applied.
+        let result = generator.apply_all([
+            StateOp::SetBalance {
+                address: missing,
+                balance: U256::one(),
+            },
+            StateOp::ReadMissingAccount { address: missing },
+        ]);
+        let error = result.unwrap_err();
+        assert_eq!(
+            (error.index, error.error),
+            (1, StateOpError::AccountExists(missing))
+        );
+        assert_eq!(error.applied.len(), 1);
+        assert_eq!(error.applied[0].0, MPTProofType::BalanceChanged);
+        assert_eq!(
+            error.applied[0].1.account_path[1].root,
+            hex(generator.root())
+        );
+        assert!(generator.account(missing).is_some());
+    }
+}